@@ -1,5 +1,15 @@
 use home_automation_common::action::AutomationStatusUpdate;
+use home_automation_common::websocket::dto::{AutomationMessage, MacroExecutionReport};
+use tokio::sync::mpsc::UnboundedSender;
 
 pub trait AutomationStatusUpdateHandler: Send {
     fn on_status_update(&mut self, status_update: AutomationStatusUpdate);
+
+    /// Called once the websocket is connected, messages for the server can be sent with the given sender.
+    fn on_connected(&mut self, _sender: UnboundedSender<AutomationMessage>) {}
+
+    fn on_disconnected(&mut self) {}
+
+    /// Called with the report of a macro which this client sent without waiting for its reply.
+    fn on_macro_executed(&mut self, _report: MacroExecutionReport) {}
 }
@@ -1,52 +1,90 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use futures_util::sink::Sink;
 use futures_util::stream::Stream;
 use futures_util::{SinkExt, StreamExt};
 use home_automation_common::websocket::convert::{
-    convert_message_to_text, parse_message_from_string,
+    convert_identified_message_to_text, parse_identified_message_from_string, ProtocolVersion,
 };
 use home_automation_common::websocket::dto::{
-    AutomationMessage, ClientDeviceType, SingleClientUpdate,
+    AutomationMessage, ClientDeviceType, ConnectedStreamdeck, IdentifiedMessage, SingleClientUpdate,
 };
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
 
 use crate::websocket::handler::AutomationStatusUpdateHandler;
 
+pub use self::request::{WebsocketRequester, DEFAULT_REQUEST_TIMEOUT};
+
 pub mod handler;
+mod request;
 
 #[derive(Clone)]
 pub struct WebsocketClientInfo {
     pub client_type: ClientDeviceType,
     pub client_name: String,
+    pub streamdecks: Vec<ConnectedStreamdeck>,
+}
+
+impl WebsocketClientInfo {
+    /// Creates the message which tells the server about this client, it is sent as response to pings.
+    pub fn create_update_message(&self) -> AutomationMessage {
+        AutomationMessage::Pong {
+            client_update: SingleClientUpdate {
+                name: self.client_name.clone(),
+                device_type: self.client_type.clone(),
+                streamdecks: self.streamdecks.clone(),
+            },
+        }
+    }
 }
 
+/// The write half of the websocket with the protocol version which was negotiated for the connection.
+struct WebsocketWriter<W> {
+    writer: W,
+    protocol_version: ProtocolVersion,
+}
+
+/// Client info which can be changed while the websocket is running, e.g. when devices are attached.
+pub type SharedWebsocketClientInfo = Arc<RwLock<WebsocketClientInfo>>;
+
 pub struct WebsocketRunner {
     sender: UnboundedSender<AutomationMessage>,
+    requester: WebsocketRequester,
     join_handle: JoinHandle<()>,
 }
 
 impl WebsocketRunner {
     pub fn new(
-        client_info: WebsocketClientInfo,
+        client_info: SharedWebsocketClientInfo,
         ws_server_url: String,
+        auth_token: Option<String>,
         message_handler: Arc<Mutex<dyn AutomationStatusUpdateHandler>>,
     ) -> WebsocketRunner {
         let (automation_message_tx, automation_message_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (request_tx, request_rx) = tokio::sync::mpsc::unbounded_channel();
+        let requester = WebsocketRequester::new(request_tx);
 
         let join_handle = tokio::spawn(Self::run_websocket(
             ws_server_url,
+            auth_token,
             client_info,
             automation_message_rx,
+            request_rx,
             message_handler,
             automation_message_tx.clone(),
+            requester.clone(),
         ));
 
         WebsocketRunner {
             sender: automation_message_tx,
+            requester,
             join_handle,
         }
     }
@@ -61,27 +99,71 @@ impl WebsocketRunner {
         self.sender.clone()
     }
 
+    /// Sends requests which are answered by the server, e.g. to find out whether a macro was executed.
+    pub fn get_requester(&self) -> WebsocketRequester {
+        self.requester.clone()
+    }
+
+    /// Resolves to the reply of the server or fails if there is no reply within the timeout.
+    pub async fn request(
+        &self,
+        message: AutomationMessage,
+        timeout: Duration,
+    ) -> anyhow::Result<AutomationMessage> {
+        self.requester.request(message, timeout).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn run_websocket(
         server_url: String,
-        client_info: WebsocketClientInfo,
+        auth_token: Option<String>,
+        client_info: SharedWebsocketClientInfo,
         automation_message_receiver: UnboundedReceiver<AutomationMessage>,
+        request_receiver: UnboundedReceiver<IdentifiedMessage>,
         bcp_message_handler: Arc<Mutex<dyn AutomationStatusUpdateHandler>>,
         ws_sender: UnboundedSender<AutomationMessage>,
+        requester: WebsocketRequester,
     ) {
         let websocket_writer = Arc::new(Mutex::new(Option::None));
 
         // sender task
         tokio::spawn(Self::send_messages(
             automation_message_receiver,
+            request_receiver,
             websocket_writer.clone(),
         ));
 
-        match tokio_tungstenite::connect_async(&server_url).await {
-            Ok((ws_stream, _)) => {
+        let request = match create_connect_request(&server_url, auth_token.as_deref()) {
+            Ok(request) => request,
+            Err(err) => {
+                error!("Could not create websocket request: {}.", err);
+                return;
+            }
+        };
+        match tokio_tungstenite::connect_async(request).await {
+            Ok((ws_stream, response)) => {
+                // servers which do not support subprotocols only speak the first version
+                let protocol_version = response
+                    .headers()
+                    .get(SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|protocol| protocol.to_str().ok())
+                    .and_then(ProtocolVersion::from_subprotocol)
+                    .unwrap_or_default();
+                info!(
+                    "Connected to automation server with protocol version {}.",
+                    protocol_version.number()
+                );
                 let (ws_write, ws_read) = ws_stream.split();
                 let mut locked_writer = websocket_writer.lock().await;
-                *locked_writer = Some(ws_write);
+                *locked_writer = Some(WebsocketWriter {
+                    writer: ws_write,
+                    protocol_version,
+                });
                 drop(locked_writer);
+                bcp_message_handler
+                    .lock()
+                    .await
+                    .on_connected(ws_sender.clone());
 
                 // receiver task
                 let receiver_handle = tokio::spawn(Self::handle_messages(
@@ -89,11 +171,14 @@ impl WebsocketRunner {
                     ws_read,
                     bcp_message_handler.clone(),
                     ws_sender.clone(),
+                    requester.clone(),
                 ));
 
                 receiver_handle.await.unwrap_or_else(|err| {
                     error!("Could not await receiver task: {}.", err);
                 });
+                requester.cancel_pending_requests();
+                bcp_message_handler.lock().await.on_disconnected();
             }
             Err(err) => {
                 error!("Could not connect to automation server: {}.", err);
@@ -103,34 +188,46 @@ impl WebsocketRunner {
 
     async fn send_messages(
         mut automation_message_receiver: UnboundedReceiver<AutomationMessage>,
+        mut request_receiver: UnboundedReceiver<IdentifiedMessage>,
         websocket_message_writer: Arc<
             Mutex<
                 Option<
-                    impl Sink<tungstenite::Message, Error = tungstenite::Error> + std::marker::Unpin,
+                    WebsocketWriter<
+                        impl Sink<tungstenite::Message, Error = tungstenite::Error> + std::marker::Unpin,
+                    >,
                 >,
             >,
         >,
     ) {
         loop {
-            match automation_message_receiver.recv().await {
-                Some(message) => match convert_message_to_tungstenite_message(message) {
-                    Ok(converted_message) => {
-                        let mut locked_writer = websocket_message_writer.lock().await;
-                        match *locked_writer {
-                            Some(ref mut writer) => {
-                                if let Err(err) = writer.send(converted_message).await {
+            let message = tokio::select! {
+                message = automation_message_receiver.recv() => message.map(IdentifiedMessage::from),
+                request = request_receiver.recv() => request,
+            };
+            match message {
+                Some(message) => {
+                    let mut locked_writer = websocket_message_writer.lock().await;
+                    match *locked_writer {
+                        Some(ref mut writer) => match convert_message_to_tungstenite_message(
+                            message,
+                            writer.protocol_version,
+                        ) {
+                            Ok(converted_message) => {
+                                if let Err(err) = writer.writer.send(converted_message).await {
                                     error!("Could not send message on websocket: {}.", err);
                                     break;
                                 }
                             }
-                            None => {
-                                error!("No websocket writer found in optional value.");
-                                break;
+                            Err(err) => {
+                                error!("Could not convert message to websocket message: {}", err)
                             }
+                        },
+                        None => {
+                            error!("No websocket writer found in optional value.");
+                            break;
                         }
                     }
-                    Err(err) => error!("Could not convert message to websocket message: {}", err),
-                },
+                }
                 None => {
                     error!("Could not receive message to send anymore.");
                     break;
@@ -141,19 +238,26 @@ impl WebsocketRunner {
     }
 
     async fn handle_messages(
-        client_info: WebsocketClientInfo,
+        client_info: SharedWebsocketClientInfo,
         ws_read: impl Stream<Item = Result<tungstenite::Message, tungstenite::Error>>
             + std::marker::Unpin,
         message_handler: Arc<Mutex<dyn AutomationStatusUpdateHandler>>,
         ws_sender: UnboundedSender<AutomationMessage>,
+        requester: WebsocketRequester,
     ) {
         let mut websocket_receiver = ws_read;
         loop {
             match websocket_receiver.next().await {
                 Some(message_result) => match message_result {
                     Ok(message) => {
-                        Self::handle_message(&client_info, message, &message_handler, &ws_sender)
-                            .await;
+                        Self::handle_message(
+                            &client_info,
+                            message,
+                            &message_handler,
+                            &ws_sender,
+                            &requester,
+                        )
+                        .await;
                     }
                     Err(err) => {
                         error!(
@@ -172,25 +276,27 @@ impl WebsocketRunner {
     }
 
     async fn handle_message(
-        client_info: &WebsocketClientInfo,
+        client_info: &SharedWebsocketClientInfo,
         message: tungstenite::Message,
         message_handler: &Arc<Mutex<dyn AutomationStatusUpdateHandler>>,
         ws_sender: &UnboundedSender<AutomationMessage>,
+        requester: &WebsocketRequester,
     ) {
         match message.to_text() {
-            Ok(text) => match parse_message_from_string(text) {
-                Ok(message) => match message {
-                    AutomationMessage::StatusUpdate { update } => {
+            Ok(text) => match parse_identified_message_from_string(text) {
+                // replies are passed to the request they belong to
+                Ok(message) => match requester.resolve(message) {
+                    None => {}
+                    Some(AutomationMessage::StatusUpdate { update }) => {
                         let mut locked_message_handler = message_handler.lock().await;
                         locked_message_handler.on_status_update(update);
                     }
-                    AutomationMessage::Ping => {
-                        let message = AutomationMessage::Pong {
-                            client_update: SingleClientUpdate {
-                                name: client_info.client_name.clone(),
-                                device_type: client_info.client_type.clone(),
-                            },
-                        };
+                    Some(AutomationMessage::MacroExecuted { report }) => {
+                        let mut locked_message_handler = message_handler.lock().await;
+                        locked_message_handler.on_macro_executed(report);
+                    }
+                    Some(AutomationMessage::Ping) => {
+                        let message = client_info.read().unwrap().create_update_message();
                         if let Err(err) = ws_sender.send(message) {
                             error!("Could not send pong message from websocket: {}.", err);
                         }
@@ -204,9 +310,32 @@ impl WebsocketRunner {
     }
 }
 
+/// Requests all supported protocol versions, the server selects the newest version it supports.
+fn create_connect_request(
+    server_url: &str,
+    auth_token: Option<&str>,
+) -> anyhow::Result<tungstenite::handshake::client::Request> {
+    let mut request = server_url.into_client_request()?;
+    let subprotocols = ProtocolVersion::SUPPORTED
+        .map(|version| version.subprotocol())
+        .join(", ");
+    request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_str(&subprotocols)?,
+    );
+    if let Some(auth_token) = auth_token {
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", auth_token))?,
+        );
+    }
+    Ok(request)
+}
+
 fn convert_message_to_tungstenite_message(
-    message: AutomationMessage,
+    message: IdentifiedMessage,
+    version: ProtocolVersion,
 ) -> anyhow::Result<tungstenite::Message> {
-    let text = convert_message_to_text(message)?;
+    let text = convert_identified_message_to_text(message, version)?;
     Ok(tungstenite::Message::Text(text))
 }
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use home_automation_common::automacro::AutomationMacro;
+use home_automation_common::websocket::dto::{
+    AutomationMessage, ClientState, IdentifiedMessage, MacroExecutionReport,
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// Requests which are not answered within this duration fail.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type PendingRequests = HashMap<u64, oneshot::Sender<AutomationMessage>>;
+
+/// Sends requests to the server and resolves them with the reply which has the id of the request.
+#[derive(Clone)]
+pub struct WebsocketRequester {
+    sender: UnboundedSender<IdentifiedMessage>,
+    pending_requests: Arc<Mutex<PendingRequests>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl WebsocketRequester {
+    pub(super) fn new(sender: UnboundedSender<IdentifiedMessage>) -> WebsocketRequester {
+        WebsocketRequester {
+            sender,
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// Resolves to the reply of the server, error replies and missing replies are returned as errors.
+    pub async fn request(
+        &self,
+        message: AutomationMessage,
+        timeout: Duration,
+    ) -> anyhow::Result<AutomationMessage> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.pending_requests
+            .lock()
+            .unwrap()
+            .insert(id, reply_sender);
+        if let Err(err) = self.sender.send(IdentifiedMessage::new(Some(id), message)) {
+            self.pending_requests.lock().unwrap().remove(&id);
+            return Err(anyhow!("Could not send request {}: {}", id, err));
+        }
+
+        let reply = tokio::time::timeout(timeout, reply_receiver).await;
+        self.pending_requests.lock().unwrap().remove(&id);
+        match reply {
+            Ok(Ok(AutomationMessage::Error { error })) => {
+                Err(anyhow!("Server could not handle request {}: {}", id, error))
+            }
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err(anyhow!(
+                "Connection was closed before request {} was answered.",
+                id
+            )),
+            Err(_) => Err(anyhow!(
+                "Request {} was not answered within {} ms.",
+                id,
+                timeout.as_millis()
+            )),
+        }
+    }
+
+    /// Resolves once the server executed all actions of the macro, the report contains the actions which failed.
+    pub async fn execute_macro(
+        &self,
+        mac: AutomationMacro,
+    ) -> anyhow::Result<MacroExecutionReport> {
+        match self
+            .request(
+                AutomationMessage::ExecuteMacro { mac },
+                DEFAULT_REQUEST_TIMEOUT,
+            )
+            .await?
+        {
+            AutomationMessage::MacroExecuted { report } => Ok(report),
+            reply => Err(anyhow!("Unexpected reply to macro: {:?}.", reply)),
+        }
+    }
+
+    pub async fn request_client_states(&self) -> anyhow::Result<Vec<ClientState>> {
+        match self
+            .request(
+                AutomationMessage::RequestClientStates,
+                DEFAULT_REQUEST_TIMEOUT,
+            )
+            .await?
+        {
+            AutomationMessage::ClientStates { states } => Ok(states),
+            reply => Err(anyhow!("Unexpected reply to client states: {:?}.", reply)),
+        }
+    }
+
+    /// Passes a reply to the request it belongs to. Returns the message if no request is waiting for it.
+    pub(super) fn resolve(&self, message: IdentifiedMessage) -> Option<AutomationMessage> {
+        let pending_request = message
+            .id
+            .and_then(|id| self.pending_requests.lock().unwrap().remove(&id));
+        match pending_request {
+            Some(reply_sender) => {
+                // the request may have timed out in the meantime
+                let _ = reply_sender.send(message.message);
+                None
+            }
+            None => Some(message.message),
+        }
+    }
+
+    /// Fails all pending requests, their replies can not arrive on a new connection.
+    pub(super) fn cancel_pending_requests(&self) {
+        self.pending_requests.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolve_request_with_reply() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let requester = WebsocketRequester::new(sender);
+
+        let server_requester = requester.clone();
+        let server = tokio::spawn(async move {
+            for _ in 0..3 {
+                let request = receiver.recv().await.unwrap();
+                let reply = match request.message {
+                    AutomationMessage::RequestClientStates => {
+                        AutomationMessage::ClientStates { states: Vec::new() }
+                    }
+                    AutomationMessage::ExecuteMacro { mac } => AutomationMessage::MacroExecuted {
+                        report: MacroExecutionReport {
+                            macro_name: mac.name().to_owned(),
+                            client_name: String::new(),
+                            executed_at: String::new(),
+                            actions: Vec::new(),
+                        },
+                    },
+                    _ => AutomationMessage::Error {
+                        error: "failed".to_owned(),
+                    },
+                };
+                // an unrelated message is not taken as reply
+                assert_eq!(
+                    Some(AutomationMessage::Ping),
+                    server_requester.resolve(AutomationMessage::Ping.into())
+                );
+                assert_eq!(
+                    None,
+                    server_requester.resolve(IdentifiedMessage::new(request.id, reply))
+                );
+            }
+        });
+
+        assert_eq!(
+            Vec::<ClientState>::new(),
+            requester.request_client_states().await.unwrap()
+        );
+        let mac = AutomationMacro::new("Macro".to_owned(), Vec::new());
+        let report = requester.execute_macro(mac).await.unwrap();
+        assert_eq!("Macro", report.macro_name);
+        let error = requester
+            .request(AutomationMessage::Ping, DEFAULT_REQUEST_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().ends_with("failed"));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn time_out_request() {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let requester = WebsocketRequester::new(sender);
+
+        let result = requester
+            .request(AutomationMessage::Ping, Duration::from_millis(10))
+            .await;
+        assert!(result.is_err());
+        assert!(requester.pending_requests.lock().unwrap().is_empty());
+    }
+}
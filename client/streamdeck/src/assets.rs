@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use home_automation_common::assets::validate_asset_name;
+use home_automation_common::automodule::streamdeck::StreamdeckClientConfiguration;
+use hyper::{header, Body, Request, StatusCode};
+use image::DynamicImage;
+
+const ASSET_CACHE_SUBFOLDER_NAME: &str = "assetCache";
+
+/// Keeps local copies of the image assets which are fetched from the automation server.
+#[derive(Clone)]
+pub struct AssetCache {
+    cache_folder: PathBuf,
+    server_url: String,
+    auth_token: Option<String>,
+}
+
+impl AssetCache {
+    pub fn new(
+        application_folder: &Path,
+        configuration: &StreamdeckClientConfiguration,
+        auth_token: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let mut cache_folder = application_folder.to_owned();
+        cache_folder.push(ASSET_CACHE_SUBFOLDER_NAME);
+        if !cache_folder.exists() {
+            std::fs::create_dir_all(&cache_folder)
+                .context("Could not prepare asset cache folder.")?;
+        }
+
+        let server_url = format!(
+            "http://{}:{}/api/assets",
+            configuration.server_ip, configuration.server_port
+        );
+
+        Ok(AssetCache {
+            cache_folder,
+            server_url,
+            auth_token,
+        })
+    }
+
+    pub fn is_cached(&self, name: &str) -> bool {
+        validate_asset_name(name).is_ok() && self.cache_folder.join(name).exists()
+    }
+
+    /// Loads a cached image, returns None if the image has not been fetched yet.
+    pub fn load_image(&self, name: &str) -> anyhow::Result<Option<DynamicImage>> {
+        if !self.is_cached(name) {
+            return Ok(None);
+        }
+        let image = image::open(self.cache_folder.join(name))
+            .with_context(|| format!("Could not decode cached image {}.", name))?;
+        Ok(Some(image))
+    }
+
+    /// Fetches all given assets which are not cached yet from the server.
+    /// Returns whether any new asset was added to the cache.
+    pub async fn fetch_missing_assets(&self, names: Vec<String>) -> anyhow::Result<bool> {
+        let client = hyper::Client::new();
+        let mut fetched_any = false;
+        for name in names {
+            if self.is_cached(&name) {
+                continue;
+            }
+            validate_asset_name(&name)?;
+
+            let url = format!("{}/{}", self.server_url, name);
+            let mut request = Request::get(url);
+            if let Some(auth_token) = &self.auth_token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", auth_token));
+            }
+            let response = client
+                .request(
+                    request
+                        .body(Body::empty())
+                        .context("Could not construct asset request.")?,
+                )
+                .await
+                .with_context(|| format!("Could not fetch asset {}.", name))?;
+            if response.status() != StatusCode::OK {
+                return Err(anyhow!(
+                    "Received unexpected status code {} when fetching asset {}.",
+                    response.status(),
+                    name
+                ));
+            }
+            let content = hyper::body::to_bytes(response.into_body())
+                .await
+                .with_context(|| format!("Could not read body of asset {}.", name))?;
+            image::load_from_memory(&content)
+                .with_context(|| format!("Fetched asset {} is not a valid image.", name))?;
+
+            std::fs::write(self.cache_folder.join(&name), &content)
+                .with_context(|| format!("Could not write asset {} to cache.", name))?;
+            fetched_any = true;
+        }
+        Ok(fetched_any)
+    }
+}
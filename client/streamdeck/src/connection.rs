@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use home_automation_common::websocket::dto::AutomationMessage;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::Instant;
+
+/// Gives access to the websocket of the current connection to the server, which is replaced on every reconnect.
+/// Messages can be queued while the server is not reachable, they are sent once the connection is back.
+#[derive(Clone, Default)]
+pub struct ServerConnection {
+    state: Arc<Mutex<ServerConnectionState>>,
+}
+
+#[derive(Default)]
+struct ServerConnectionState {
+    sender: Option<UnboundedSender<AutomationMessage>>,
+    queue: VecDeque<QueuedMessage>,
+}
+
+struct QueuedMessage {
+    message: AutomationMessage,
+    expires_at: Instant,
+}
+
+impl ServerConnection {
+    /// Sends all queued messages which have not expired yet.
+    pub fn connect(&self, sender: UnboundedSender<AutomationMessage>) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        for queued_message in state.queue.drain(..) {
+            if queued_message.expires_at < now {
+                debug!("Discarding expired queued message.");
+                continue;
+            }
+            if let Err(err) = sender.send(queued_message.message) {
+                error!("Could not send queued message: {}.", err);
+            }
+        }
+        state.sender = Some(sender);
+    }
+
+    pub fn disconnect(&self) {
+        self.state.lock().unwrap().sender = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().sender.is_some()
+    }
+
+    pub fn send(&self, message: AutomationMessage) -> anyhow::Result<()> {
+        match &self.state.lock().unwrap().sender {
+            Some(sender) => sender
+                .send(message)
+                .map_err(|err| anyhow!("Could not send websocket message: {}", err)),
+            None => Err(anyhow!("Not connected to the server.")),
+        }
+    }
+
+    /// Sends the message right away or queues it if the server is not reachable.
+    pub fn send_or_queue(
+        &self,
+        message: AutomationMessage,
+        expiry: Duration,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match &state.sender {
+            Some(sender) => sender
+                .send(message)
+                .map_err(|err| anyhow!("Could not send websocket message: {}", err)),
+            None => {
+                state.queue.push_back(QueuedMessage {
+                    message,
+                    expires_at: Instant::now() + expiry,
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn send_queued_messages_after_reconnect() {
+        let connection = ServerConnection::default();
+        assert!(connection.send(AutomationMessage::Ping).is_err());
+
+        connection
+            .send_or_queue(AutomationMessage::Ping, Duration::from_secs(10))
+            .unwrap();
+        connection
+            .send_or_queue(
+                AutomationMessage::RequestClientStates,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(30)).await;
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        connection.connect(sender);
+
+        assert_eq!(
+            Some(AutomationMessage::RequestClientStates),
+            receiver.recv().await
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
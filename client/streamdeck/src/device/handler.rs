@@ -1,62 +1,80 @@
-use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::{anyhow, Context};
-use hidapi::HidApi;
+use anyhow::anyhow;
 
-use crate::device::connect_to_streamdeck;
+use crate::device::SharedStreamdeckDevice;
 
-const BUTTON_COUNT: usize = 15;
+/// The device is only locked for this long per read so that keys can be rendered in between.
+const READ_TIMEOUT: Duration = Duration::from_millis(20);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ButtonEvent {
     ButtonPressed(u8),
     ButtonReleased(u8),
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Connected,
+    Disconnected,
+}
+
+/// Reads the pressed buttons in a separate thread until the receiver of the button events is dropped.
+/// If the deck is unplugged, it is detected again once it is plugged back in.
 pub fn handle_button_presses(
-    hid_api: Arc<Mutex<HidApi>>,
+    device: SharedStreamdeckDevice,
     event_sender: tokio::sync::mpsc::UnboundedSender<ButtonEvent>,
+    device_event_sender: tokio::sync::mpsc::UnboundedSender<DeviceEvent>,
 ) {
     std::thread::spawn(move || {
-        if let Err(err) = handle_button_presses_internal(hid_api, event_sender) {
+        if let Err(err) = handle_button_presses_internal(device, event_sender, device_event_sender)
+        {
             error!("Could not handle streamdeck button presses: {}.", err);
         }
     });
 }
 
 fn handle_button_presses_internal(
-    hid_api: Arc<Mutex<HidApi>>,
+    device: SharedStreamdeckDevice,
     event_sender: tokio::sync::mpsc::UnboundedSender<ButtonEvent>,
+    device_event_sender: tokio::sync::mpsc::UnboundedSender<DeviceEvent>,
 ) -> anyhow::Result<()> {
-    let mut streamdeck = connect_to_streamdeck(hid_api)?;
-    let mut buttons_state: [bool; BUTTON_COUNT] = [false; BUTTON_COUNT];
-    loop {
-        let buttons_pressed = streamdeck
-            .read_buttons(None)
-            .context("Could not read pressed buttons")?;
-
-        if buttons_pressed.len() != BUTTON_COUNT {
+    let button_count = device
+        .lock()
+        .map_err(|err| anyhow!("Could not lock streamdeck device: {}", err))?
+        .model()
+        .key_count() as usize;
+    let mut buttons_state = vec![false; button_count];
+    while !event_sender.is_closed() {
+        let read_result = device
+            .lock()
+            .map_err(|err| anyhow!("Could not lock streamdeck device: {}", err))?
+            .read_buttons(READ_TIMEOUT);
+        let buttons_pressed = match read_result {
+            Ok(Some(buttons_pressed)) => buttons_pressed,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("Lost connection to streamdeck: {}.", err);
+                release_all_buttons(&mut buttons_state, &event_sender)?;
+                if !wait_for_reconnect(&device, &event_sender, &device_event_sender)? {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        if buttons_pressed.len() != button_count {
             return Err(anyhow!(
                 "Button event count was {}, but expected {}.",
                 buttons_pressed.len(),
-                BUTTON_COUNT
+                button_count
             ));
         }
 
-        for (i, button_state) in buttons_state.iter_mut().enumerate().take(BUTTON_COUNT) {
-            let pressed = match buttons_pressed
-                .get(i)
-                .context("Pressed buttons did not contain expected item")?
-            {
-                0 => false,
-                1 => true,
-                val => {
-                    return Err(anyhow!(
-                        "Pressed button contained unexpected value: {}.",
-                        val
-                    ))
-                }
-            };
-
+        for (i, (button_state, pressed)) in
+            buttons_state.iter_mut().zip(buttons_pressed).enumerate()
+        {
             if pressed && !(*button_state) {
                 // button was not pressed and is now pressed
                 let event = ButtonEvent::ButtonPressed(i as u8);
@@ -70,6 +88,51 @@ fn handle_button_presses_internal(
             }
         }
     }
+    Ok(())
+}
+
+/// Buttons which were held while the deck was unplugged would otherwise never be released.
+fn release_all_buttons(
+    buttons_state: &mut [bool],
+    event_sender: &tokio::sync::mpsc::UnboundedSender<ButtonEvent>,
+) -> anyhow::Result<()> {
+    for (i, button_state) in buttons_state.iter_mut().enumerate() {
+        if *button_state {
+            *button_state = false;
+            send_button_event(ButtonEvent::ButtonReleased(i as u8), event_sender)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns false if the button events are not received anymore while waiting.
+fn wait_for_reconnect(
+    device: &SharedStreamdeckDevice,
+    event_sender: &tokio::sync::mpsc::UnboundedSender<ButtonEvent>,
+    device_event_sender: &tokio::sync::mpsc::UnboundedSender<DeviceEvent>,
+) -> anyhow::Result<bool> {
+    device
+        .lock()
+        .map_err(|err| anyhow!("Could not lock streamdeck device: {}", err))?
+        .disconnect();
+    send_device_event(DeviceEvent::Disconnected, device_event_sender);
+
+    while !event_sender.is_closed() {
+        std::thread::sleep(RECONNECT_INTERVAL);
+        let reconnect_result = device
+            .lock()
+            .map_err(|err| anyhow!("Could not lock streamdeck device: {}", err))?
+            .reconnect();
+        match reconnect_result {
+            Ok(()) => {
+                info!("Reconnected to streamdeck.");
+                send_device_event(DeviceEvent::Connected, device_event_sender);
+                return Ok(true);
+            }
+            Err(err) => debug!("Streamdeck is not available yet: {}.", err),
+        }
+    }
+    Ok(false)
 }
 
 fn send_button_event(
@@ -80,3 +143,66 @@ fn send_button_event(
         .send(event)
         .map_err(|err| anyhow!("Could not send button event: {}.", err))
 }
+
+fn send_device_event(
+    event: DeviceEvent,
+    device_event_sender: &tokio::sync::mpsc::UnboundedSender<DeviceEvent>,
+) {
+    if let Err(err) = device_event_sender.send(event) {
+        warn!("Could not send device event: {}.", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use home_automation_common::automodule::streamdeck::StreamdeckModel;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::device::simulator::VirtualStreamdeck;
+
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+    async fn receive<T>(receiver: &mut UnboundedReceiver<T>) -> T {
+        tokio::time::timeout(RECEIVE_TIMEOUT, receiver.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reconnect_after_unplug() {
+        let deck = VirtualStreamdeck::new(StreamdeckModel::Original);
+        let device = deck.shared();
+        let (button_event_tx, mut button_event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (device_event_tx, mut device_event_rx) = tokio::sync::mpsc::unbounded_channel();
+        handle_button_presses(device.clone(), button_event_tx, device_event_tx);
+
+        deck.press(1);
+        assert_eq!(
+            ButtonEvent::ButtonPressed(1),
+            receive(&mut button_event_rx).await
+        );
+
+        deck.unplug();
+        assert_eq!(
+            ButtonEvent::ButtonReleased(1),
+            receive(&mut button_event_rx).await
+        );
+        assert_eq!(
+            DeviceEvent::Disconnected,
+            receive(&mut device_event_rx).await
+        );
+        assert!(!device.lock().unwrap().is_connected());
+
+        deck.plug_in();
+        assert_eq!(DeviceEvent::Connected, receive(&mut device_event_rx).await);
+        assert!(device.lock().unwrap().is_connected());
+
+        deck.press(2);
+        assert_eq!(
+            ButtonEvent::ButtonPressed(2),
+            receive(&mut button_event_rx).await
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use hidapi::HidApi;
+use home_automation_common::automodule::streamdeck::StreamdeckModel;
+use image::DynamicImage;
+use streamdeck::{Kind, StreamDeck};
+
+use crate::device::StreamdeckDevice;
+
+const ELGATO_VENDOR_ID: u16 = 0x0fd9;
+const SUPPORTED_PRODUCT_IDS: &[u16] = &[
+    streamdeck::pids::ORIGINAL,
+    streamdeck::pids::ORIGINAL_V2,
+    streamdeck::pids::MINI,
+    streamdeck::pids::XL,
+    streamdeck::pids::MK2,
+];
+
+/// A physical streamdeck which is connected over USB.
+/// The deck is identified by its product id and serial so that the same deck is reconnected after it was unplugged.
+pub struct HidStreamdeck {
+    hid_api: Arc<Mutex<HidApi>>,
+    product_id: u16,
+    serial: Option<String>,
+    model: StreamdeckModel,
+    device: Option<StreamDeck>,
+}
+
+impl HidStreamdeck {
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+}
+
+impl StreamdeckDevice for HidStreamdeck {
+    fn model(&self) -> StreamdeckModel {
+        self.model
+    }
+
+    fn is_connected(&self) -> bool {
+        self.device.is_some()
+    }
+
+    fn disconnect(&mut self) {
+        self.device = None;
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        let locked_hid = self
+            .hid_api
+            .lock()
+            .map_err(|err| anyhow!("Could not lock mutex for HID API: {}", err))?;
+        let device = StreamDeck::connect_with_hid(
+            &locked_hid,
+            ELGATO_VENDOR_ID,
+            self.product_id,
+            self.serial.clone(),
+        )
+        .map_err(|err| anyhow!("Could not reconnect to streamdeck: {}", err))?;
+        self.device = Some(device);
+        Ok(())
+    }
+
+    fn set_button_image(&mut self, key: u8, image: DynamicImage) -> anyhow::Result<()> {
+        match &mut self.device {
+            Some(device) => device
+                .set_button_image(key, image)
+                .map_err(|err| anyhow!("Could not write button image: {}", err)),
+            // all keys are rendered again after reconnecting
+            None => Ok(()),
+        }
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> anyhow::Result<()> {
+        match &mut self.device {
+            Some(device) => device
+                .set_brightness(brightness)
+                .map_err(|err| anyhow!("Could not set brightness: {}", err)),
+            None => Ok(()),
+        }
+    }
+
+    fn read_buttons(&mut self, timeout: Duration) -> anyhow::Result<Option<Vec<bool>>> {
+        let device = self
+            .device
+            .as_mut()
+            .context("Streamdeck is not connected.")?;
+        match device.read_buttons(Some(timeout)) {
+            Ok(buttons) => buttons
+                .into_iter()
+                .map(|button| match button {
+                    0 => Ok(false),
+                    1 => Ok(true),
+                    val => Err(anyhow!(
+                        "Pressed button contained unexpected value: {}.",
+                        val
+                    )),
+                })
+                .collect::<anyhow::Result<Vec<bool>>>()
+                .map(Some),
+            Err(streamdeck::Error::NoData) => Ok(None),
+            Err(err) => Err(anyhow!("Could not read pressed buttons: {}", err)),
+        }
+    }
+}
+
+fn get_model(kind: Kind) -> StreamdeckModel {
+    match kind {
+        Kind::Original => StreamdeckModel::Original,
+        Kind::OriginalV2 => StreamdeckModel::OriginalV2,
+        Kind::Mini => StreamdeckModel::Mini,
+        Kind::Xl => StreamdeckModel::Xl,
+        Kind::Mk2 => StreamdeckModel::Mk2,
+    }
+}
+
+/// Connects to all attached streamdecks of the supported models, the model is detected from the product id.
+/// Decks without a serial number can not be told apart, so only the first of them is used.
+pub fn connect_to_streamdecks(hid_api: Arc<Mutex<HidApi>>) -> anyhow::Result<Vec<HidStreamdeck>> {
+    let mut locked_hid = hid_api
+        .lock()
+        .map_err(|err| anyhow!("Could not lock mutex for HID API: {}", err))?;
+    locked_hid
+        .refresh_devices()
+        .context("Could not refresh HID devices.")?;
+
+    let mut attached_decks: Vec<(u16, Option<String>)> = Vec::new();
+    for device in locked_hid
+        .device_list()
+        .filter(|device| device.vendor_id() == ELGATO_VENDOR_ID)
+        .filter(|device| SUPPORTED_PRODUCT_IDS.contains(&device.product_id()))
+    {
+        let attached_deck = (
+            device.product_id(),
+            device.serial_number().map(|serial| serial.to_owned()),
+        );
+        // a deck can be listed once per HID interface
+        if !attached_decks.contains(&attached_deck) {
+            attached_decks.push(attached_deck);
+        }
+    }
+    if attached_decks.is_empty() {
+        return Err(anyhow!("Could not find a supported streamdeck."));
+    }
+
+    let mut streamdecks = Vec::new();
+    for (product_id, serial) in attached_decks {
+        if serial.is_none()
+            && streamdecks
+                .iter()
+                .any(|deck: &HidStreamdeck| deck.serial.is_none())
+        {
+            warn!(
+                "Ignoring streamdeck with pid {} because it has no serial number.",
+                product_id
+            );
+            continue;
+        }
+        let device =
+            StreamDeck::connect_with_hid(&locked_hid, ELGATO_VENDOR_ID, product_id, serial.clone())
+                .with_context(|| {
+                    format!("Could not connect to streamdeck with pid {}", product_id)
+                })?;
+        let model = get_model(device.kind());
+        info!(
+            "Connected to streamdeck of model {:?} with serial {}.",
+            model,
+            serial.as_deref().unwrap_or("unknown")
+        );
+        streamdecks.push(HidStreamdeck {
+            hid_api: hid_api.clone(),
+            product_id,
+            serial,
+            model,
+            device: Some(device),
+        });
+    }
+    Ok(streamdecks)
+}
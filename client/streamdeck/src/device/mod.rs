@@ -1,68 +1,191 @@
 use anyhow::{anyhow, Context};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use hidapi::HidApi;
-use rusttype::{Font, Scale};
-use streamdeck::{Colour, StreamDeck, TextOptions, TextPosition};
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckHorizontalAlignment, StreamdeckKeyStyle, StreamdeckModel, StreamdeckVerticalAlignment,
+};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, Rgb};
+use imageproc::drawing::draw_text_mut;
+use rusttype::Font;
+use streamdeck::Colour;
+
+use crate::device::text::layout_text;
 
 pub mod handler;
+pub mod hid;
+#[cfg(test)]
+pub mod simulator;
+mod text;
 
 const DEFAULT_FONT: &[u8] = include_bytes!("../resources/Roboto-Regular.ttf");
+const DEFAULT_FONT_SIZE: u8 = 20;
+const TEXT_PADDING: u32 = 4;
+
+/// A streamdeck which can display images on its keys and report which keys are pressed.
+pub trait StreamdeckDevice: Send {
+    fn model(&self) -> StreamdeckModel;
+
+    fn is_connected(&self) -> bool;
+
+    /// Releases the device handle after the deck was unplugged.
+    fn disconnect(&mut self);
+
+    /// Tries to open the same deck again.
+    fn reconnect(&mut self) -> anyhow::Result<()>;
+
+    /// The image has to be of the key image size of the model.
+    fn set_button_image(&mut self, key: u8, image: DynamicImage) -> anyhow::Result<()>;
+
+    /// Sets the brightness in percent.
+    fn set_brightness(&mut self, brightness: u8) -> anyhow::Result<()>;
+
+    /// Returns the pressed state of all keys or None if no key changed within the timeout.
+    fn read_buttons(&mut self, timeout: Duration) -> anyhow::Result<Option<Vec<bool>>>;
+}
+
+pub type SharedStreamdeckDevice = Arc<Mutex<Box<dyn StreamdeckDevice>>>;
+
+pub fn image_size(model: StreamdeckModel) -> (u32, u32) {
+    match model {
+        StreamdeckModel::Original | StreamdeckModel::OriginalV2 | StreamdeckModel::Mk2 => (72, 72),
+        StreamdeckModel::Mini => (80, 80),
+        StreamdeckModel::Xl => (96, 96),
+    }
+}
 
 pub struct StreamdeckClient {
-    device: StreamDeck,
+    device: SharedStreamdeckDevice,
     font: Font<'static>,
-    text_options: TextOptions,
+    foreground: Colour,
+    background: Colour,
 }
 
 impl StreamdeckClient {
-    pub fn connect(hidapi: Arc<Mutex<HidApi>>) -> anyhow::Result<StreamdeckClient> {
-        let device = connect_to_streamdeck(hidapi)?;
-
+    pub fn new(device: SharedStreamdeckDevice) -> anyhow::Result<StreamdeckClient> {
         let font = Font::try_from_bytes(DEFAULT_FONT).context("Could not load default font")?;
 
-        let text_options = TextOptions::new(
-            Colour::from_str("FFFFFF").unwrap(),
-            Colour::from_str("1B5B88").unwrap(),
-            Scale { x: 20.0, y: 20.0 },
-            1.1,
-        );
-
         Ok(StreamdeckClient {
             device,
             font,
-            text_options,
+            foreground: Colour::from_str("FFFFFF").unwrap(),
+            background: Colour::from_str("1B5B88").unwrap(),
         })
     }
 
-    pub fn set_button_text(&mut self, index: u8, text: &str) -> anyhow::Result<()> {
+    pub fn model(&self) -> StreamdeckModel {
+        self.device.lock().unwrap().model()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.device.lock().unwrap().is_connected()
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) -> anyhow::Result<()> {
+        self.device
+            .lock()
+            .map_err(|err| anyhow!("Could not lock streamdeck device: {}", err))?
+            .set_brightness(brightness)
+            .context("Could not set brightness")
+    }
+
+    /// Turns the key off by showing a black image.
+    pub fn clear_button(&mut self, index: u8) -> anyhow::Result<()> {
+        let (width, height) = image_size(self.model());
+        let key_image = ImageBuffer::from_pixel(width, height, Rgb([0, 0, 0]));
         self.device
-            .set_button_text(
-                index,
+            .lock()
+            .map_err(|err| anyhow!("Could not lock streamdeck device: {}", err))?
+            .set_button_image(index, DynamicImage::ImageRgb8(key_image))
+            .context("Could not clear button image")
+    }
+
+    /// Renders the text on a button, optionally on top of an image which is resized to the key resolution.
+    /// The text is wrapped and shrunk to fit on the button.
+    pub fn set_button(
+        &mut self,
+        index: u8,
+        text: &str,
+        image: Option<&DynamicImage>,
+        style: &StreamdeckKeyStyle,
+    ) -> anyhow::Result<()> {
+        let background_colour = parse_colour(&style.background_color, &self.background);
+        let foreground_colour = parse_colour(&style.foreground_color, &self.foreground);
+
+        let (width, height) = image_size(self.model());
+        let background = Rgb([
+            background_colour.r,
+            background_colour.g,
+            background_colour.b,
+        ]);
+        let mut key_image = ImageBuffer::from_pixel(width, height, background);
+
+        if let Some(image) = image {
+            let resized_image = image
+                .resize_to_fill(width, height, FilterType::Triangle)
+                .into_rgb8();
+            image::imageops::overlay(&mut key_image, &resized_image, 0, 0);
+        }
+
+        let foreground = Rgb([
+            foreground_colour.r,
+            foreground_colour.g,
+            foreground_colour.b,
+        ]);
+        let text_width = width - 2 * TEXT_PADDING;
+        let text_height = height - 2 * TEXT_PADDING;
+        let layout = layout_text(
+            &self.font,
+            text,
+            text_width as f32,
+            text_height as f32,
+            style.font_size.unwrap_or(DEFAULT_FONT_SIZE),
+        );
+
+        let free_height = (text_height as f32 - layout.height()).max(0.0);
+        let mut y = TEXT_PADDING as f32
+            + match style.vertical_alignment.clone().unwrap_or_default() {
+                StreamdeckVerticalAlignment::Top => 0.0,
+                StreamdeckVerticalAlignment::Middle => free_height / 2.0,
+                StreamdeckVerticalAlignment::Bottom => free_height,
+            };
+        for line in &layout.lines {
+            let free_width = (text_width as f32 - line.width).max(0.0);
+            let x = TEXT_PADDING as f32
+                + match style.horizontal_alignment.clone().unwrap_or_default() {
+                    StreamdeckHorizontalAlignment::Left => 0.0,
+                    StreamdeckHorizontalAlignment::Center => free_width / 2.0,
+                    StreamdeckHorizontalAlignment::Right => free_width,
+                };
+            draw_text_mut(
+                &mut key_image,
+                foreground,
+                x.round() as i32,
+                y.round() as i32,
+                layout.scale,
                 &self.font,
-                &TextPosition::Absolute { x: 5, y: 5 },
-                text,
-                &self.text_options,
-            )
-            .context("Could not set button text")?;
+                &line.text,
+            );
+            y += layout.line_height;
+        }
+
+        self.device
+            .lock()
+            .map_err(|err| anyhow!("Could not lock streamdeck device: {}", err))?
+            .set_button_image(index, DynamicImage::ImageRgb8(key_image))
+            .context("Could not set button image")?;
         Ok(())
     }
 }
 
-fn connect_to_streamdeck(hid_api: Arc<Mutex<HidApi>>) -> anyhow::Result<StreamDeck> {
-    let locked_hid = hid_api
-        .lock()
-        .map_err(|err| anyhow!("Could not lock mutex for HID API: {}", err))?;
-
-    match StreamDeck::connect_with_hid(&locked_hid, 0x0fd9, streamdeck::pids::ORIGINAL_V2, None) {
-        Ok(streamdeck) => Ok(streamdeck),
-        Err(_) => {
-            warn!("Could not connect to streamdeck with V2 product ID, trying older pid.");
-            let streamdeck =
-                StreamDeck::connect_with_hid(&locked_hid, 0x0fd9, streamdeck::pids::ORIGINAL, None)
-                    .context("Could not connect to streamdeck with older pid")?;
-            Ok(streamdeck)
-        }
+fn parse_colour(colour: &Option<String>, default_colour: &Colour) -> Colour {
+    match colour {
+        Some(colour) => Colour::from_str(colour.trim_start_matches('#')).unwrap_or_else(|err| {
+            warn!("Could not parse colour {}: {}.", colour, err);
+            default_colour.clone()
+        }),
+        None => default_colour.clone(),
     }
 }
@@ -0,0 +1,154 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use home_automation_common::automodule::streamdeck::StreamdeckModel;
+use image::DynamicImage;
+
+use crate::device::{image_size, SharedStreamdeckDevice, StreamdeckDevice};
+
+/// A headless streamdeck which plays back scripted key events and records the rendered key images.
+/// Clones share the same state, so a test can keep a clone to script and inspect the deck.
+#[derive(Clone)]
+pub struct VirtualStreamdeck {
+    model: StreamdeckModel,
+    state: Arc<Mutex<VirtualStreamdeckState>>,
+}
+
+struct VirtualStreamdeckState {
+    plugged_in: bool,
+    connected: bool,
+    brightness: u8,
+    pressed: Vec<bool>,
+    pending_reads: VecDeque<Vec<bool>>,
+    key_images: HashMap<u8, DynamicImage>,
+}
+
+impl VirtualStreamdeck {
+    pub fn new(model: StreamdeckModel) -> VirtualStreamdeck {
+        VirtualStreamdeck {
+            model,
+            state: Arc::new(Mutex::new(VirtualStreamdeckState {
+                plugged_in: true,
+                connected: true,
+                brightness: 100,
+                pressed: vec![false; model.key_count() as usize],
+                pending_reads: VecDeque::new(),
+                key_images: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn shared(&self) -> SharedStreamdeckDevice {
+        Arc::new(Mutex::new(Box::new(self.clone())))
+    }
+
+    /// Unplugging clears the key images like on a real deck.
+    pub fn unplug(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.plugged_in = false;
+        state.pressed.fill(false);
+        state.pending_reads.clear();
+        state.key_images.clear();
+    }
+
+    pub fn plug_in(&self) {
+        self.state.lock().unwrap().plugged_in = true;
+    }
+
+    pub fn press(&self, key: u8) {
+        self.set_pressed(key, true);
+    }
+
+    pub fn release(&self, key: u8) {
+        self.set_pressed(key, false);
+    }
+
+    fn set_pressed(&self, key: u8, pressed: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.pressed[key as usize] = pressed;
+        let buttons = state.pressed.clone();
+        state.pending_reads.push_back(buttons);
+    }
+
+    pub fn key_image(&self, key: u8) -> Option<DynamicImage> {
+        self.state.lock().unwrap().key_images.get(&key).cloned()
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.state.lock().unwrap().brightness
+    }
+
+    pub fn rendered_keys(&self) -> Vec<u8> {
+        let mut keys: Vec<u8> = self
+            .state
+            .lock()
+            .unwrap()
+            .key_images
+            .keys()
+            .copied()
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+}
+
+impl StreamdeckDevice for VirtualStreamdeck {
+    fn model(&self) -> StreamdeckModel {
+        self.model
+    }
+
+    fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().connected
+    }
+
+    fn disconnect(&mut self) {
+        self.state.lock().unwrap().connected = false;
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.plugged_in {
+            return Err(anyhow!("Virtual streamdeck is unplugged."));
+        }
+        state.connected = true;
+        Ok(())
+    }
+
+    fn set_button_image(&mut self, key: u8, image: DynamicImage) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.connected {
+            return Ok(());
+        }
+        if !state.plugged_in {
+            return Err(anyhow!("Virtual streamdeck is unplugged."));
+        }
+        if key >= self.model.key_count() {
+            return Err(anyhow!("Key {} does not exist.", key));
+        }
+        if (image.width(), image.height()) != image_size(self.model) {
+            return Err(anyhow!("Image for key {} has the wrong size.", key));
+        }
+        state.key_images.insert(key, image);
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> anyhow::Result<()> {
+        self.state.lock().unwrap().brightness = brightness;
+        Ok(())
+    }
+
+    fn read_buttons(&mut self, timeout: Duration) -> anyhow::Result<Option<Vec<bool>>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.plugged_in || !state.connected {
+            return Err(anyhow!("Virtual streamdeck is not connected."));
+        }
+        let buttons = state.pending_reads.pop_front();
+        drop(state);
+        if buttons.is_none() {
+            std::thread::sleep(timeout);
+        }
+        Ok(buttons)
+    }
+}
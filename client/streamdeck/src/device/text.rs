@@ -0,0 +1,127 @@
+use rusttype::{point, Font, Scale};
+
+const MIN_FONT_SIZE: u8 = 8;
+const LINE_HEIGHT: f32 = 1.1;
+
+/// Text which is wrapped into lines and scaled to fit into a given area.
+pub struct TextLayout {
+    pub scale: Scale,
+    pub lines: Vec<TextLine>,
+    pub line_height: f32,
+}
+
+pub struct TextLine {
+    pub text: String,
+    pub width: f32,
+}
+
+impl TextLayout {
+    pub fn height(&self) -> f32 {
+        self.lines.len() as f32 * self.line_height
+    }
+}
+
+/// Wraps the text at word boundaries (and explicit line breaks) and shrinks the font size until the text fits.
+pub fn layout_text(
+    font: &Font,
+    text: &str,
+    max_width: f32,
+    max_height: f32,
+    font_size: u8,
+) -> TextLayout {
+    let mut size = font_size.max(MIN_FONT_SIZE);
+    loop {
+        let layout = layout_text_with_size(font, text, max_width, size);
+        let fits = layout.height() <= max_height
+            && layout.lines.iter().all(|line| line.width <= max_width);
+        if fits || size <= MIN_FONT_SIZE {
+            return layout;
+        }
+        size -= 1;
+    }
+}
+
+fn layout_text_with_size(font: &Font, text: &str, max_width: f32, size: u8) -> TextLayout {
+    let scale = Scale::uniform(size as f32);
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_owned()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if line.is_empty() || measure_text_width(font, scale, &candidate) <= max_width {
+                line = candidate;
+            } else {
+                lines.push(create_line(font, scale, line));
+                line = word.to_owned();
+            }
+        }
+        lines.push(create_line(font, scale, line));
+    }
+
+    TextLayout {
+        scale,
+        lines,
+        line_height: (size as f32 * LINE_HEIGHT).round(),
+    }
+}
+
+fn create_line(font: &Font, scale: Scale, text: String) -> TextLine {
+    let width = measure_text_width(font, scale, &text);
+    TextLine { text, width }
+}
+
+fn measure_text_width(font: &Font, scale: Scale, text: &str) -> f32 {
+    font.layout(text, scale, point(0.0, 0.0))
+        .last()
+        .map(|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width)
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_FONT: &[u8] = include_bytes!("../resources/Roboto-Regular.ttf");
+
+    fn get_font() -> Font<'static> {
+        Font::try_from_bytes(TEST_FONT).unwrap()
+    }
+
+    #[test]
+    fn keep_short_text_on_one_line() {
+        let layout = layout_text(&get_font(), "Light", 64.0, 64.0, 20);
+
+        assert_eq!(1, layout.lines.len());
+        assert_eq!(20.0, layout.scale.y);
+    }
+
+    #[test]
+    fn wrap_words() {
+        let layout = layout_text(&get_font(), "Living room light", 64.0, 64.0, 16);
+
+        assert!(layout.lines.len() > 1);
+        assert!(layout.lines.iter().all(|line| line.width <= 64.0));
+        assert!(layout.height() <= 64.0);
+    }
+
+    #[test]
+    fn keep_explicit_line_breaks() {
+        let layout = layout_text(&get_font(), "On\nOff", 64.0, 64.0, 16);
+
+        let lines: Vec<&str> = layout.lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(vec!["On", "Off"], lines);
+    }
+
+    #[test]
+    fn shrink_long_words() {
+        let layout = layout_text(&get_font(), "Dishwasher", 64.0, 64.0, 30);
+
+        assert_eq!(1, layout.lines.len());
+        assert!(layout.scale.y < 30.0);
+        assert!(layout.lines[0].width <= 64.0);
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use home_automation_common::automacro::AutomationMacro;
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckAutomationConfiguration, StreamdeckButtonConfiguration, StreamdeckOfflineAction,
+};
+use home_automation_common::config::ConfigurationManager;
+use home_automation_common::websocket::dto::AutomationMessage;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+
+use crate::connection::ServerConnection;
+use crate::device::handler::ButtonEvent;
+use crate::display::SharedDisplayController;
+use crate::gesture::{Gesture, GestureDetector, GestureEvent, GestureSettings};
+use crate::handler::RenderRequest;
+
+const DEFAULT_OFFLINE_QUEUE_SECONDS: u64 = 60;
+
+/// Detects gestures in the button events and sends the configured macros to the server.
+/// Pressing a key while the deck is dimmed or shows the screensaver only wakes the deck up.
+/// Keys which open or close a folder are handled on the deck and do not trigger any gesture.
+pub async fn handle_button_events(
+    mut receiver: UnboundedReceiver<ButtonEvent>,
+    connection: ServerConnection,
+    button_configuration_manager: Arc<
+        RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>,
+    >,
+    display: SharedDisplayController,
+    render_sender: UnboundedSender<RenderRequest>,
+) {
+    let mut gesture_detector = GestureDetector::default();
+    let mut navigating_keys = HashSet::new();
+    loop {
+        let next_deadline = gesture_detector.next_deadline();
+        let gesture_events = tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => {
+                    if display.lock().unwrap().on_button_event(&event, Instant::now()) {
+                        if let ButtonEvent::ButtonPressed(_) = event {
+                            if let Err(err) = render_sender.send(RenderRequest::Refresh) {
+                                error!("Could not request streamdeck refresh: {}.", err);
+                            }
+                        }
+                        continue;
+                    }
+                    if navigate_folders(&button_configuration_manager, &display, &event, &mut navigating_keys) {
+                        if let Err(err) = render_sender.send(RenderRequest::Refresh) {
+                            error!("Could not request streamdeck refresh: {}.", err);
+                        }
+                        continue;
+                    }
+                    let settings = get_gesture_settings(&button_configuration_manager, &display, &event);
+                    gesture_detector.on_button_event(event, Instant::now(), settings)
+                }
+                None => break,
+            },
+            _ = sleep_until_deadline(next_deadline) => gesture_detector.on_tick(Instant::now()),
+        };
+
+        for gesture_event in gesture_events {
+            if let Err(err) = execute_gesture_macro(
+                &connection,
+                &render_sender,
+                &button_configuration_manager,
+                &display,
+                gesture_event,
+            ) {
+                warn!("Could not execute macro for gesture: {}.", err);
+            }
+        }
+    }
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Returns true if the event opened or closed a folder, the release of the key is swallowed as well.
+fn navigate_folders(
+    button_configuration_manager: &Arc<
+        RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>,
+    >,
+    display: &SharedDisplayController,
+    event: &ButtonEvent,
+    navigating_keys: &mut HashSet<u8>,
+) -> bool {
+    let key = match event {
+        ButtonEvent::ButtonPressed(key) => *key,
+        ButtonEvent::ButtonReleased(key) => return navigating_keys.remove(key),
+    };
+    let locked_configuration_manager = button_configuration_manager.read().unwrap();
+    let mut display = display.lock().unwrap();
+    let folder_action = locked_configuration_manager
+        .get_configuration()
+        .find_button_configuration(display.current_folder(), key)
+        .and_then(|button_configuration| button_configuration.folder_action.clone());
+    match folder_action {
+        Some(folder_action) => {
+            display.on_folder_action(&folder_action);
+            navigating_keys.insert(key);
+            true
+        }
+        None => false,
+    }
+}
+
+fn get_gesture_settings(
+    button_configuration_manager: &Arc<
+        RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>,
+    >,
+    display: &SharedDisplayController,
+    event: &ButtonEvent,
+) -> GestureSettings {
+    let key = match event {
+        ButtonEvent::ButtonPressed(key) | ButtonEvent::ButtonReleased(key) => *key,
+    };
+    let locked_configuration_manager = button_configuration_manager.read().unwrap();
+    let configuration = locked_configuration_manager.get_configuration();
+    let current_folder = display.lock().unwrap().current_folder().map(str::to_owned);
+    GestureSettings::new(
+        configuration.find_button_configuration(current_folder.as_deref(), key),
+        configuration.gesture_timing.as_ref(),
+    )
+}
+
+fn execute_gesture_macro(
+    connection: &ServerConnection,
+    render_sender: &UnboundedSender<RenderRequest>,
+    button_configuration_manager: &Arc<
+        RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>,
+    >,
+    display: &SharedDisplayController,
+    gesture_event: GestureEvent,
+) -> anyhow::Result<()> {
+    let locked_configuration_manager = button_configuration_manager.read().unwrap();
+    let configuration = locked_configuration_manager.get_configuration();
+    let current_folder = display.lock().unwrap().current_folder().map(str::to_owned);
+    if let Some(button_config) =
+        configuration.find_button_configuration(current_folder.as_deref(), gesture_event.key)
+    {
+        let handled_by_live_key = button_config
+            .live_key
+            .as_ref()
+            .is_some_and(|live_key| live_key.handles_presses())
+            && matches!(gesture_event.gesture, Gesture::Press | Gesture::LongPress);
+        if handled_by_live_key {
+            return render_sender
+                .send(RenderRequest::LiveKeyGesture {
+                    folder_id: current_folder,
+                    gesture_event,
+                })
+                .map_err(|err| anyhow!("Could not forward gesture to live key: {}", err));
+        }
+        let gesture_macro = match gesture_event.gesture {
+            Gesture::Press => Some(&button_config.press_macro),
+            Gesture::LongPress => button_config.long_press_macro.as_ref(),
+            Gesture::DoublePress => button_config.double_press_macro.as_ref(),
+            Gesture::Repeat => button_config.repeat_macro.as_ref(),
+            Gesture::Release => button_config.release_macro.as_ref(),
+        };
+        if let Some(gesture_macro) = gesture_macro {
+            execute_macro(
+                connection,
+                render_sender,
+                button_config,
+                gesture_macro.clone(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Depending on the button configuration, macros which are triggered while the server is not reachable
+/// are queued or rejected with visual feedback on the button.
+fn execute_macro(
+    connection: &ServerConnection,
+    render_sender: &UnboundedSender<RenderRequest>,
+    button_config: &StreamdeckButtonConfiguration,
+    auto_macro: AutomationMacro,
+) -> anyhow::Result<()> {
+    let message = AutomationMessage::ExecuteMacro { mac: auto_macro };
+    match button_config.offline_action.clone().unwrap_or_default() {
+        StreamdeckOfflineAction::Queue => {
+            let expiry = Duration::from_secs(
+                button_config
+                    .offline_queue_seconds
+                    .unwrap_or(DEFAULT_OFFLINE_QUEUE_SECONDS),
+            );
+            connection.send_or_queue(message, expiry)
+        }
+        StreamdeckOfflineAction::Reject => connection.send(message).inspect_err(|_| {
+            if let Err(err) = render_sender.send(RenderRequest::ShowRejected(button_config.key)) {
+                error!(
+                    "Could not request rejected feedback on streamdeck: {}.",
+                    err
+                );
+            }
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use home_automation_common::automodule::streamdeck::{
+        StreamdeckFolder, StreamdeckFolderAction, StreamdeckGestureTiming, StreamdeckLiveKey,
+        StreamdeckModel,
+    };
+    use home_automation_common::fs;
+
+    use super::*;
+    use crate::device::handler::handle_button_presses;
+    use crate::device::simulator::VirtualStreamdeck;
+    use crate::display::DisplayController;
+
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn test_macro(name: &str) -> AutomationMacro {
+        AutomationMacro::new(name.to_owned(), vec![])
+    }
+
+    fn button(key: u8) -> StreamdeckButtonConfiguration {
+        StreamdeckButtonConfiguration {
+            key,
+            text: format!("Key {}", key),
+            image: None,
+            style: None,
+            state_binding: None,
+            press_macro: test_macro("press"),
+            release_macro: None,
+            long_press_macro: None,
+            double_press_macro: None,
+            repeat_macro: None,
+            gesture_timing: None,
+            offline_action: None,
+            offline_queue_seconds: None,
+            folder_action: None,
+            live_key: None,
+        }
+    }
+
+    /// Runs the button reading and macro dispatch for the virtual deck, the connection to the server is not connected yet.
+    fn start_dispatch(
+        path: &Path,
+        deck: &VirtualStreamdeck,
+        configuration: StreamdeckAutomationConfiguration,
+    ) -> (ServerConnection, UnboundedReceiver<RenderRequest>) {
+        let mut configuration_manager = ConfigurationManager::load(path, "buttons.json").unwrap();
+        configuration_manager.set_configuration(configuration);
+
+        let (button_event_tx, button_event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (device_event_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        let connection = ServerConnection::default();
+        handle_button_presses(deck.shared(), button_event_tx, device_event_tx);
+        let (render_tx, render_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(handle_button_events(
+            button_event_rx,
+            connection.clone(),
+            Arc::new(RwLock::new(configuration_manager)),
+            Arc::new(std::sync::Mutex::new(
+                DisplayController::new(Instant::now()),
+            )),
+            render_tx,
+        ));
+        (connection, render_rx)
+    }
+
+    /// Returns the messages which are sent to the server.
+    fn connect(connection: &ServerConnection) -> UnboundedReceiver<AutomationMessage> {
+        let (message_tx, message_rx) = tokio::sync::mpsc::unbounded_channel();
+        connection.connect(message_tx);
+        message_rx
+    }
+
+    async fn receive_macro(receiver: &mut UnboundedReceiver<AutomationMessage>) -> AutomationMacro {
+        match tokio::time::timeout(RECEIVE_TIMEOUT, receiver.recv()).await {
+            Ok(Some(AutomationMessage::ExecuteMacro { mac })) => mac,
+            message => panic!("Expected a macro, got {:?}.", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatch_press_and_release_macros() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let deck = VirtualStreamdeck::new(StreamdeckModel::Original);
+        let mut released_button = button(3);
+        released_button.release_macro = Some(test_macro("release"));
+        let (connection, _) = start_dispatch(
+            &path,
+            &deck,
+            StreamdeckAutomationConfiguration {
+                button_configurations: vec![button(2), released_button],
+                ..Default::default()
+            },
+        );
+        let mut receiver = connect(&connection);
+
+        deck.press(3);
+        assert_eq!(test_macro("press"), receive_macro(&mut receiver).await);
+        deck.release(3);
+        assert_eq!(test_macro("release"), receive_macro(&mut receiver).await);
+
+        // unconfigured keys do not send anything
+        deck.press(4);
+        deck.release(4);
+        deck.press(2);
+        assert_eq!(test_macro("press"), receive_macro(&mut receiver).await);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dispatch_long_and_double_press_macros() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let deck = VirtualStreamdeck::new(StreamdeckModel::Mini);
+        let mut gesture_button = button(5);
+        gesture_button.long_press_macro = Some(test_macro("long"));
+        gesture_button.double_press_macro = Some(test_macro("double"));
+        let (connection, _) = start_dispatch(
+            &path,
+            &deck,
+            StreamdeckAutomationConfiguration {
+                gesture_timing: Some(StreamdeckGestureTiming {
+                    long_press_millis: Some(100),
+                    double_press_millis: Some(100),
+                    repeat_delay_millis: None,
+                    repeat_interval_millis: None,
+                }),
+                button_configurations: vec![gesture_button],
+                ..Default::default()
+            },
+        );
+        let mut receiver = connect(&connection);
+
+        deck.press(5);
+        assert_eq!(test_macro("long"), receive_macro(&mut receiver).await);
+        deck.release(5);
+
+        deck.press(5);
+        deck.release(5);
+        deck.press(5);
+        deck.release(5);
+        assert_eq!(test_macro("double"), receive_macro(&mut receiver).await);
+
+        deck.press(5);
+        deck.release(5);
+        assert_eq!(test_macro("press"), receive_macro(&mut receiver).await);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn queue_or_reject_macros_while_offline() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let deck = VirtualStreamdeck::new(StreamdeckModel::Original);
+        let mut queued_button = button(1);
+        queued_button.press_macro = test_macro("queued");
+        queued_button.offline_action = Some(StreamdeckOfflineAction::Queue);
+        let (connection, mut render_requests) = start_dispatch(
+            &path,
+            &deck,
+            StreamdeckAutomationConfiguration {
+                button_configurations: vec![button(0), queued_button],
+                ..Default::default()
+            },
+        );
+
+        deck.press(0);
+        match tokio::time::timeout(RECEIVE_TIMEOUT, render_requests.recv()).await {
+            Ok(Some(RenderRequest::ShowRejected(key))) => assert_eq!(0, key),
+            _ => panic!("Expected rejected feedback for key 0."),
+        }
+        deck.press(1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let mut receiver = connect(&connection);
+        assert_eq!(test_macro("queued"), receive_macro(&mut receiver).await);
+        assert!(receiver.try_recv().is_err());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn forward_presses_to_live_keys() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let deck = VirtualStreamdeck::new(StreamdeckModel::Original);
+        let mut counter_button = button(2);
+        counter_button.release_macro = Some(test_macro("release"));
+        counter_button.live_key = Some(StreamdeckLiveKey::Counter);
+        let (connection, mut render_requests) = start_dispatch(
+            &path,
+            &deck,
+            StreamdeckAutomationConfiguration {
+                button_configurations: vec![counter_button],
+                ..Default::default()
+            },
+        );
+        let mut receiver = connect(&connection);
+
+        deck.press(2);
+        deck.release(2);
+        match tokio::time::timeout(RECEIVE_TIMEOUT, render_requests.recv()).await {
+            Ok(Some(request)) => assert_eq!(
+                RenderRequest::LiveKeyGesture {
+                    folder_id: None,
+                    gesture_event: GestureEvent {
+                        key: 2,
+                        gesture: Gesture::Press,
+                    },
+                },
+                request
+            ),
+            _ => panic!("Expected a gesture for the live key."),
+        }
+        // other gestures still execute their macros
+        assert_eq!(test_macro("release"), receive_macro(&mut receiver).await);
+        assert!(receiver.try_recv().is_err());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_and_close_folders() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let deck = VirtualStreamdeck::new(StreamdeckModel::Original);
+        let mut folder_button = button(4);
+        folder_button.folder_action = Some(StreamdeckFolderAction::Open {
+            folder_id: "lights".to_owned(),
+        });
+        let mut back_button = button(0);
+        back_button.folder_action = Some(StreamdeckFolderAction::Back);
+        let mut folder_light_button = button(4);
+        folder_light_button.press_macro = test_macro("folder");
+        let (connection, _) = start_dispatch(
+            &path,
+            &deck,
+            StreamdeckAutomationConfiguration {
+                button_configurations: vec![button(0), folder_button],
+                folders: vec![StreamdeckFolder {
+                    id: "lights".to_owned(),
+                    name: "Lights".to_owned(),
+                    button_configurations: vec![back_button, folder_light_button],
+                }],
+                ..Default::default()
+            },
+        );
+        let mut receiver = connect(&connection);
+
+        deck.press(4);
+        deck.release(4);
+        deck.press(4);
+        assert_eq!(test_macro("folder"), receive_macro(&mut receiver).await);
+        deck.release(4);
+
+        deck.press(0);
+        deck.release(0);
+        deck.press(0);
+        assert_eq!(test_macro("press"), receive_macro(&mut receiver).await);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+}
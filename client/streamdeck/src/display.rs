@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckAutomationConfiguration, StreamdeckDisplaySettings, StreamdeckFolderAction,
+};
+use tokio::time::Instant;
+
+use crate::device::handler::ButtonEvent;
+
+const DEFAULT_BRIGHTNESS: u8 = 100;
+const DEFAULT_DIMMED_BRIGHTNESS: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    Active,
+    Dimmed,
+    Screensaver,
+}
+
+/// Tracks when the deck was last used and whether it should be dimmed or show the screensaver.
+/// It also keeps track of the opened folders, the buttons of the last opened folder are shown.
+pub struct DisplayController {
+    mode: DisplayMode,
+    last_activity: Instant,
+    sleep_requested: bool,
+    brightness: Option<u8>,
+    waking_keys: HashSet<u8>,
+    open_folders: Vec<String>,
+}
+
+pub type SharedDisplayController = Arc<Mutex<DisplayController>>;
+
+impl DisplayController {
+    pub fn new(now: Instant) -> DisplayController {
+        DisplayController {
+            mode: DisplayMode::Active,
+            last_activity: now,
+            sleep_requested: false,
+            brightness: None,
+            waking_keys: HashSet::new(),
+            open_folders: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+
+    /// Returns true if the event only woke the deck up and must not trigger the macro of the key.
+    /// The release of a key which woke the deck up is swallowed as well.
+    pub fn on_button_event(&mut self, event: &ButtonEvent, now: Instant) -> bool {
+        self.last_activity = now;
+        match event {
+            ButtonEvent::ButtonPressed(key) => {
+                if self.mode == DisplayMode::Active {
+                    return false;
+                }
+                self.sleep_requested = false;
+                self.mode = DisplayMode::Active;
+                self.waking_keys.insert(*key);
+                true
+            }
+            ButtonEvent::ButtonReleased(key) => self.waking_keys.remove(key),
+        }
+    }
+
+    pub fn wake(&mut self, now: Instant) {
+        self.last_activity = now;
+        self.sleep_requested = false;
+    }
+
+    pub fn sleep(&mut self) {
+        self.sleep_requested = true;
+    }
+
+    /// Overrides the configured brightness until the client is restarted.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = Some(brightness.min(100));
+    }
+
+    /// Returns whether the mode changed.
+    pub fn update(&mut self, now: Instant, settings: Option<&StreamdeckDisplaySettings>) -> bool {
+        let idle_time = now.saturating_duration_since(self.last_activity);
+        let idle_for = |minutes: Option<u32>| {
+            minutes.is_some_and(|minutes| idle_time >= Duration::from_secs(minutes as u64 * 60))
+        };
+        let mode = if self.sleep_requested
+            || idle_for(settings.and_then(|settings| settings.screensaver_after_minutes))
+        {
+            DisplayMode::Screensaver
+        } else if idle_for(settings.and_then(|settings| settings.dim_after_minutes)) {
+            DisplayMode::Dimmed
+        } else {
+            DisplayMode::Active
+        };
+
+        let changed = mode != self.mode;
+        self.mode = mode;
+        changed
+    }
+
+    pub fn current_folder(&self) -> Option<&str> {
+        self.open_folders.last().map(|folder_id| folder_id.as_str())
+    }
+
+    pub fn on_folder_action(&mut self, action: &StreamdeckFolderAction) {
+        match action {
+            StreamdeckFolderAction::Open { folder_id } => self.open_folders.push(folder_id.clone()),
+            StreamdeckFolderAction::Back => {
+                self.open_folders.pop();
+            }
+        }
+    }
+
+    /// Goes back to the device buttons if an opened folder was removed from the configuration.
+    pub fn close_removed_folders(&mut self, configuration: &StreamdeckAutomationConfiguration) {
+        if !self
+            .open_folders
+            .iter()
+            .all(|folder_id| configuration.has_folder(folder_id))
+        {
+            self.open_folders.clear();
+        }
+    }
+
+    pub fn brightness(&self, settings: Option<&StreamdeckDisplaySettings>) -> u8 {
+        match self.mode {
+            DisplayMode::Active => self
+                .brightness
+                .or_else(|| settings.and_then(|settings| settings.brightness))
+                .unwrap_or(DEFAULT_BRIGHTNESS),
+            DisplayMode::Dimmed | DisplayMode::Screensaver => settings
+                .and_then(|settings| settings.dimmed_brightness)
+                .unwrap_or(DEFAULT_DIMMED_BRIGHTNESS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> StreamdeckDisplaySettings {
+        StreamdeckDisplaySettings {
+            brightness: Some(80),
+            dim_after_minutes: Some(1),
+            dimmed_brightness: Some(20),
+            screensaver_after_minutes: Some(5),
+            screensaver: None,
+        }
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn dim_and_show_screensaver_when_idle() {
+        let start = Instant::now();
+        let settings = settings();
+        let mut controller = DisplayController::new(start);
+
+        assert!(!controller.update(start + Duration::from_secs(30), Some(&settings)));
+        assert_eq!(80, controller.brightness(Some(&settings)));
+
+        assert!(controller.update(start + minutes(2), Some(&settings)));
+        assert_eq!(DisplayMode::Dimmed, controller.mode());
+        assert_eq!(20, controller.brightness(Some(&settings)));
+
+        assert!(controller.update(start + minutes(5), Some(&settings)));
+        assert_eq!(DisplayMode::Screensaver, controller.mode());
+    }
+
+    #[test]
+    fn stay_active_without_settings() {
+        let start = Instant::now();
+        let mut controller = DisplayController::new(start);
+
+        assert!(!controller.update(start + minutes(600), None));
+        assert_eq!(DisplayMode::Active, controller.mode());
+        assert_eq!(100, controller.brightness(None));
+    }
+
+    #[test]
+    fn wake_up_without_triggering_key() {
+        let start = Instant::now();
+        let settings = settings();
+        let mut controller = DisplayController::new(start);
+        controller.update(start + minutes(2), Some(&settings));
+
+        let now = start + minutes(3);
+        assert!(controller.on_button_event(&ButtonEvent::ButtonPressed(3), now));
+        assert_eq!(DisplayMode::Active, controller.mode());
+        assert!(controller.on_button_event(&ButtonEvent::ButtonReleased(3), now));
+        assert!(!controller.update(now, Some(&settings)));
+
+        assert!(!controller.on_button_event(&ButtonEvent::ButtonPressed(3), now));
+        assert!(!controller.on_button_event(&ButtonEvent::ButtonReleased(3), now));
+    }
+
+    #[test]
+    fn sleep_and_wake_on_request() {
+        let start = Instant::now();
+        let mut controller = DisplayController::new(start);
+        controller.set_brightness(150);
+
+        controller.sleep();
+        assert!(controller.update(start, None));
+        assert_eq!(DisplayMode::Screensaver, controller.mode());
+
+        controller.wake(start);
+        assert!(controller.update(start, None));
+        assert_eq!(DisplayMode::Active, controller.mode());
+        assert_eq!(100, controller.brightness(None));
+    }
+
+    #[test]
+    fn open_and_close_folders() {
+        let mut controller = DisplayController::new(Instant::now());
+        let open = |folder_id: &str| StreamdeckFolderAction::Open {
+            folder_id: folder_id.to_owned(),
+        };
+
+        controller.on_folder_action(&open("rooms"));
+        controller.on_folder_action(&open("kitchen"));
+        assert_eq!(Some("kitchen"), controller.current_folder());
+        controller.on_folder_action(&StreamdeckFolderAction::Back);
+        assert_eq!(Some("rooms"), controller.current_folder());
+
+        controller.close_removed_folders(&StreamdeckAutomationConfiguration::default());
+        assert_eq!(None, controller.current_folder());
+        controller.on_folder_action(&StreamdeckFolderAction::Back);
+        assert_eq!(None, controller.current_folder());
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckButtonConfiguration, StreamdeckGestureTiming,
+};
+use tokio::time::Instant;
+
+use crate::device::handler::ButtonEvent;
+
+const DEFAULT_LONG_PRESS_MILLIS: u64 = 500;
+const DEFAULT_DOUBLE_PRESS_MILLIS: u64 = 300;
+const DEFAULT_REPEAT_DELAY_MILLIS: u64 = 500;
+const DEFAULT_REPEAT_INTERVAL_MILLIS: u64 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Press,
+    LongPress,
+    DoublePress,
+    Repeat,
+    Release,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GestureEvent {
+    pub key: u8,
+    pub gesture: Gesture,
+}
+
+/// Which gestures are detected for a button and how long they take.
+#[derive(Clone, Debug, Default)]
+pub struct GestureSettings {
+    detect_long_press: bool,
+    detect_double_press: bool,
+    repeat: bool,
+    long_press: Duration,
+    double_press: Duration,
+    repeat_delay: Duration,
+    repeat_interval: Duration,
+}
+
+impl GestureSettings {
+    pub fn new(
+        button_configuration: Option<&StreamdeckButtonConfiguration>,
+        device_timing: Option<&StreamdeckGestureTiming>,
+    ) -> GestureSettings {
+        let button_configuration = match button_configuration {
+            Some(button_configuration) => button_configuration,
+            None => return GestureSettings::default(),
+        };
+        let timing = [button_configuration.gesture_timing.as_ref(), device_timing]
+            .into_iter()
+            .flatten()
+            .fold(StreamdeckGestureTiming::default(), |timing, fallback| {
+                timing.merge(fallback)
+            });
+        GestureSettings {
+            // live keys are reset with a long press
+            detect_long_press: button_configuration.long_press_macro.is_some()
+                || button_configuration
+                    .live_key
+                    .as_ref()
+                    .is_some_and(|live_key| live_key.handles_presses()),
+            detect_double_press: button_configuration.double_press_macro.is_some(),
+            repeat: button_configuration.repeat_macro.is_some(),
+            long_press: Duration::from_millis(
+                timing
+                    .long_press_millis
+                    .unwrap_or(DEFAULT_LONG_PRESS_MILLIS),
+            ),
+            double_press: Duration::from_millis(
+                timing
+                    .double_press_millis
+                    .unwrap_or(DEFAULT_DOUBLE_PRESS_MILLIS),
+            ),
+            repeat_delay: Duration::from_millis(
+                timing
+                    .repeat_delay_millis
+                    .unwrap_or(DEFAULT_REPEAT_DELAY_MILLIS),
+            ),
+            repeat_interval: Duration::from_millis(
+                timing
+                    .repeat_interval_millis
+                    .unwrap_or(DEFAULT_REPEAT_INTERVAL_MILLIS)
+                    .max(1),
+            ),
+        }
+    }
+
+    /// Without long or double press the short press can be reported as soon as the button goes down.
+    fn defers_press(&self) -> bool {
+        self.detect_long_press || self.detect_double_press
+    }
+}
+
+#[derive(Default)]
+struct KeyState {
+    settings: GestureSettings,
+    pressed_at: Option<Instant>,
+    long_press_reported: bool,
+    double_press_reported: bool,
+    next_repeat: Option<Instant>,
+    pending_press_until: Option<Instant>,
+}
+
+impl KeyState {
+    fn next_deadline(&self) -> Option<Instant> {
+        let long_press_deadline = match self.pressed_at {
+            Some(pressed_at)
+                if self.settings.detect_long_press
+                    && !self.long_press_reported
+                    && !self.double_press_reported =>
+            {
+                Some(pressed_at + self.settings.long_press)
+            }
+            _ => None,
+        };
+        [
+            long_press_deadline,
+            self.next_repeat,
+            self.pending_press_until,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+}
+
+/// Turns raw button presses and releases into gestures (short, long, double press and hold repeat).
+#[derive(Default)]
+pub struct GestureDetector {
+    keys: HashMap<u8, KeyState>,
+}
+
+impl GestureDetector {
+    pub fn on_button_event(
+        &mut self,
+        event: ButtonEvent,
+        now: Instant,
+        settings: GestureSettings,
+    ) -> Vec<GestureEvent> {
+        let mut gestures = Vec::new();
+        match event {
+            ButtonEvent::ButtonPressed(key) => {
+                let key_state = self.keys.entry(key).or_default();
+                match key_state.pending_press_until.take() {
+                    Some(pending_press_until) if now < pending_press_until => {
+                        // second press within the double press window
+                        key_state.pressed_at = Some(now);
+                        key_state.double_press_reported = true;
+                        gestures.push(GestureEvent {
+                            key,
+                            gesture: Gesture::DoublePress,
+                        });
+                        return gestures;
+                    }
+                    // the tick which reports the deferred press came too late
+                    Some(_) => gestures.push(GestureEvent {
+                        key,
+                        gesture: Gesture::Press,
+                    }),
+                    None => {}
+                }
+
+                key_state.settings = settings;
+                key_state.pressed_at = Some(now);
+                key_state.long_press_reported = false;
+                key_state.double_press_reported = false;
+                if key_state.settings.repeat {
+                    key_state.next_repeat = Some(now + key_state.settings.repeat_delay);
+                }
+                if !key_state.settings.defers_press() {
+                    gestures.push(GestureEvent {
+                        key,
+                        gesture: Gesture::Press,
+                    });
+                }
+            }
+            ButtonEvent::ButtonReleased(key) => {
+                let key_state = self.keys.entry(key).or_default();
+                let was_pressed = key_state.pressed_at.take().is_some();
+                key_state.next_repeat = None;
+                let is_short_press = was_pressed
+                    && key_state.settings.defers_press()
+                    && !key_state.long_press_reported
+                    && !key_state.double_press_reported;
+                if is_short_press {
+                    if key_state.settings.detect_double_press {
+                        key_state.pending_press_until = Some(now + key_state.settings.double_press);
+                    } else {
+                        gestures.push(GestureEvent {
+                            key,
+                            gesture: Gesture::Press,
+                        });
+                    }
+                }
+                gestures.push(GestureEvent {
+                    key,
+                    gesture: Gesture::Release,
+                });
+            }
+        }
+        gestures
+    }
+
+    /// Reports the gestures which are due at the given time.
+    pub fn on_tick(&mut self, now: Instant) -> Vec<GestureEvent> {
+        let mut gestures = Vec::new();
+        let mut keys: Vec<u8> = self.keys.keys().copied().collect();
+        keys.sort_unstable();
+        for key in keys {
+            let key_state = self.keys.get_mut(&key).unwrap();
+            if let Some(pressed_at) = key_state.pressed_at {
+                if key_state.settings.detect_long_press
+                    && !key_state.long_press_reported
+                    && !key_state.double_press_reported
+                    && now >= pressed_at + key_state.settings.long_press
+                {
+                    key_state.long_press_reported = true;
+                    gestures.push(GestureEvent {
+                        key,
+                        gesture: Gesture::LongPress,
+                    });
+                }
+            }
+            if let Some(next_repeat) = key_state.next_repeat {
+                if now >= next_repeat {
+                    key_state.next_repeat = Some(next_repeat + key_state.settings.repeat_interval);
+                    gestures.push(GestureEvent {
+                        key,
+                        gesture: Gesture::Repeat,
+                    });
+                }
+            }
+            if let Some(pending_press_until) = key_state.pending_press_until {
+                if now >= pending_press_until {
+                    key_state.pending_press_until = None;
+                    gestures.push(GestureEvent {
+                        key,
+                        gesture: Gesture::Press,
+                    });
+                }
+            }
+        }
+        gestures
+    }
+
+    /// The next point in time at which a gesture could be detected without further button events.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.keys
+            .values()
+            .filter_map(|key_state| key_state.next_deadline())
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use home_automation_common::automacro::AutomationMacro;
+
+    use super::*;
+
+    const KEY: u8 = 3;
+
+    fn test_macro() -> AutomationMacro {
+        AutomationMacro::new("test".to_owned(), vec![])
+    }
+
+    fn settings(long_press: bool, double_press: bool, repeat: bool) -> GestureSettings {
+        let button_configuration = StreamdeckButtonConfiguration {
+            key: KEY,
+            text: "Test".to_owned(),
+            image: None,
+            style: None,
+            state_binding: None,
+            press_macro: test_macro(),
+            release_macro: None,
+            long_press_macro: long_press.then(test_macro),
+            double_press_macro: double_press.then(test_macro),
+            repeat_macro: repeat.then(test_macro),
+            gesture_timing: None,
+            offline_action: None,
+            offline_queue_seconds: None,
+            folder_action: None,
+            live_key: None,
+        };
+        GestureSettings::new(Some(&button_configuration), None)
+    }
+
+    fn gestures(events: Vec<GestureEvent>) -> Vec<Gesture> {
+        events.into_iter().map(|event| event.gesture).collect()
+    }
+
+    #[test]
+    fn press_immediately_without_gestures() {
+        let mut detector = GestureDetector::default();
+        let now = Instant::now();
+
+        let pressed = detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now,
+            settings(false, false, false),
+        );
+        let released = detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            now + Duration::from_millis(50),
+            settings(false, false, false),
+        );
+
+        assert_eq!(vec![Gesture::Press], gestures(pressed));
+        assert_eq!(vec![Gesture::Release], gestures(released));
+        assert_eq!(None, detector.next_deadline());
+    }
+
+    #[test]
+    fn short_press_with_long_press_configured() {
+        let mut detector = GestureDetector::default();
+        let now = Instant::now();
+
+        let pressed = detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now,
+            settings(true, false, false),
+        );
+        let released = detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            now + Duration::from_millis(100),
+            settings(true, false, false),
+        );
+
+        assert!(pressed.is_empty());
+        assert_eq!(vec![Gesture::Press, Gesture::Release], gestures(released));
+    }
+
+    #[test]
+    fn long_press() {
+        let mut detector = GestureDetector::default();
+        let now = Instant::now();
+
+        detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now,
+            settings(true, false, false),
+        );
+        let deadline = detector.next_deadline().unwrap();
+        assert_eq!(
+            now + Duration::from_millis(DEFAULT_LONG_PRESS_MILLIS),
+            deadline
+        );
+
+        let ticked = detector.on_tick(deadline);
+        let released = detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            deadline + Duration::from_millis(100),
+            settings(true, false, false),
+        );
+
+        assert_eq!(vec![Gesture::LongPress], gestures(ticked));
+        assert_eq!(vec![Gesture::Release], gestures(released));
+    }
+
+    #[test]
+    fn double_press() {
+        let mut detector = GestureDetector::default();
+        let now = Instant::now();
+
+        detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now,
+            settings(false, true, false),
+        );
+        detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            now + Duration::from_millis(50),
+            settings(false, true, false),
+        );
+        let second_press = detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now + Duration::from_millis(150),
+            settings(false, true, false),
+        );
+        let second_release = detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            now + Duration::from_millis(200),
+            settings(false, true, false),
+        );
+
+        assert_eq!(vec![Gesture::DoublePress], gestures(second_press));
+        assert_eq!(vec![Gesture::Release], gestures(second_release));
+        assert_eq!(None, detector.next_deadline());
+    }
+
+    #[test]
+    fn single_press_after_double_press_window() {
+        let mut detector = GestureDetector::default();
+        let now = Instant::now();
+
+        detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now,
+            settings(false, true, false),
+        );
+        let released = detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            now + Duration::from_millis(50),
+            settings(false, true, false),
+        );
+        assert_eq!(vec![Gesture::Release], gestures(released));
+
+        let deadline = detector.next_deadline().unwrap();
+        let ticked = detector.on_tick(deadline);
+
+        assert_eq!(vec![Gesture::Press], gestures(ticked));
+        assert_eq!(None, detector.next_deadline());
+    }
+
+    #[test]
+    fn single_presses_with_late_tick() {
+        let mut detector = GestureDetector::default();
+        let now = Instant::now();
+
+        detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now,
+            settings(false, true, false),
+        );
+        detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            now + Duration::from_millis(50),
+            settings(false, true, false),
+        );
+        // the second press is handled before the tick at the end of the window
+        let deadline = detector.next_deadline().unwrap();
+        let second_press = detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            deadline + Duration::from_millis(1),
+            settings(false, true, false),
+        );
+
+        detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            deadline + Duration::from_millis(50),
+            settings(false, true, false),
+        );
+
+        assert_eq!(vec![Gesture::Press], gestures(second_press));
+        // the second press waits for its own double press window
+        let second_deadline = detector.next_deadline().unwrap();
+        assert!(second_deadline > deadline);
+        assert_eq!(
+            vec![Gesture::Press],
+            gestures(detector.on_tick(second_deadline))
+        );
+    }
+
+    #[test]
+    fn repeat_while_held() {
+        let mut detector = GestureDetector::default();
+        let now = Instant::now();
+
+        let pressed = detector.on_button_event(
+            ButtonEvent::ButtonPressed(KEY),
+            now,
+            settings(false, false, true),
+        );
+        assert_eq!(vec![Gesture::Press], gestures(pressed));
+
+        let mut repeats = 0;
+        let end = now + Duration::from_millis(1000);
+        while let Some(deadline) = detector.next_deadline() {
+            if deadline > end {
+                break;
+            }
+            repeats += gestures(detector.on_tick(deadline))
+                .into_iter()
+                .filter(|gesture| *gesture == Gesture::Repeat)
+                .count();
+        }
+        detector.on_button_event(
+            ButtonEvent::ButtonReleased(KEY),
+            end,
+            settings(false, false, true),
+        );
+
+        // first repeat after 500ms, then every 200ms
+        assert_eq!(3, repeats);
+        assert_eq!(None, detector.next_deadline());
+    }
+}
@@ -1,88 +1,987 @@
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
-use hidapi::HidApi;
 use home_automation_client_lib::websocket::handler::AutomationStatusUpdateHandler;
 use home_automation_common::action::AutomationStatusUpdate;
-use home_automation_common::automodule::streamdeck::StreamdeckAutomationConfiguration;
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckAutomationConfiguration, StreamdeckButtonConfiguration,
+    StreamdeckDevicesConfiguration, StreamdeckDisplayCommand, StreamdeckDisplaySettings,
+    StreamdeckKeyStyle, StreamdeckModel, StreamdeckScreensaver,
+};
 use home_automation_common::config::ConfigurationManager;
+use home_automation_common::state::{
+    streamdeck_key_state_id, AutomationState, AutomationStateValue,
+};
+use home_automation_common::websocket::dto::{AutomationMessage, ConnectedStreamdeck};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::Instant;
 
-use crate::config::Configuration;
-use crate::StreamdeckClient;
+use crate::assets::AssetCache;
+use crate::connection::ServerConnection;
+use crate::device::{SharedStreamdeckDevice, StreamdeckClient};
+use crate::display::{DisplayMode, SharedDisplayController};
+use crate::gesture::GestureEvent;
+use crate::live::{get_live_key_text, LiveKeys};
 
+/// Renders the keys of one streamdeck and applies the updates of the server to it.
 pub struct StreamdeckAutomationClient {
-    configuration: Configuration,
+    device_id: String,
     streamdeck_client: StreamdeckClient,
     button_configuration_manager:
         Arc<RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>>,
+    asset_cache: AssetCache,
+    render_sender: UnboundedSender<RenderRequest>,
+    states: HashMap<String, AutomationStateValue>,
+    display: SharedDisplayController,
+    screensaver_clock: Option<String>,
+    connection: ServerConnection,
+    live_keys: LiveKeys,
+    reported_live_values: HashMap<String, AutomationStateValue>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenderRequest {
+    Refresh,
+    /// Briefly shows on the key that its macro could not be sent to the server.
+    ShowRejected(u8),
+    /// A gesture on a live key in the given folder, which updates the value of the key.
+    LiveKeyGesture {
+        folder_id: Option<String>,
+        gesture_event: GestureEvent,
+    },
 }
 
 impl StreamdeckAutomationClient {
     pub fn new(
-        hid_api: Arc<std::sync::Mutex<HidApi>>,
+        device: SharedStreamdeckDevice,
+        device_id: String,
         button_configuration_manager: Arc<
             RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>,
         >,
-        configuration: Configuration,
+        asset_cache: AssetCache,
+        render_sender: UnboundedSender<RenderRequest>,
+        display: SharedDisplayController,
+        connection: ServerConnection,
     ) -> anyhow::Result<StreamdeckAutomationClient> {
-        let streamdeck_client = StreamdeckClient::connect(hid_api)?;
+        let streamdeck_client = StreamdeckClient::new(device)?;
+        if let Err(err) = button_configuration_manager
+            .read()
+            .unwrap()
+            .get_configuration()
+            .validate(streamdeck_client.model())
+        {
+            warn!(
+                "Stored button configuration does not match the connected streamdeck: {}",
+                err
+            );
+        }
 
         Ok(StreamdeckAutomationClient {
-            configuration,
+            device_id,
             streamdeck_client,
             button_configuration_manager,
+            asset_cache,
+            render_sender,
+            states: HashMap::new(),
+            display,
+            screensaver_clock: None,
+            connection,
+            live_keys: LiveKeys::default(),
+            reported_live_values: HashMap::new(),
         })
     }
 
-    fn fill_streamdeck(&mut self) -> anyhow::Result<()> {
-        let configuration_manager = self.button_configuration_manager.read().unwrap();
+    pub fn model(&self) -> StreamdeckModel {
+        self.streamdeck_client.model()
+    }
+
+    /// Returns the streamdeck if it is currently plugged in.
+    pub fn get_connected_streamdecks(&self) -> Vec<ConnectedStreamdeck> {
+        if !self.streamdeck_client.is_connected() {
+            return Vec::new();
+        }
+        vec![ConnectedStreamdeck {
+            device_id: self.device_id.clone(),
+            model: self.model(),
+        }]
+    }
+
+    pub fn fill_streamdeck(&mut self) -> anyhow::Result<()> {
+        let button_configuration_manager = self.button_configuration_manager.clone();
+        let configuration_manager = button_configuration_manager.read().unwrap();
+
+        let configuration = configuration_manager.get_configuration();
+        let (mode, brightness, current_folder) = {
+            let display = self.display.lock().unwrap();
+            (
+                display.mode(),
+                display.brightness(configuration.display.as_ref()),
+                display.current_folder().map(str::to_owned),
+            )
+        };
+        self.streamdeck_client.set_brightness(brightness)?;
+        if mode == DisplayMode::Screensaver {
+            return self.render_screensaver(configuration.display.as_ref());
+        }
+        self.screensaver_clock = None;
+
+        for key in 0..self.model().key_count() {
+            match configuration.find_button_configuration(current_folder.as_deref(), key) {
+                Some(button_configuration) => self.render_button(
+                    button_configuration,
+                    current_folder.as_deref(),
+                    configuration.style.as_ref(),
+                )?,
+                None => self.streamdeck_client.clear_button(key)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Dims the deck or shows the screensaver when it has not been used and keeps the screensaver clock up to date.
+    pub fn update_display(&mut self) -> anyhow::Result<()> {
+        let settings = self.get_display_settings();
+        let (changed, mode) = {
+            let mut display = self.display.lock().unwrap();
+            (
+                display.update(Instant::now(), settings.as_ref()),
+                display.mode(),
+            )
+        };
+        if changed {
+            return self.fill_streamdeck();
+        }
+        if mode == DisplayMode::Screensaver
+            && self
+                .screensaver_clock
+                .as_ref()
+                .is_some_and(|clock| clock.ne(&get_clock_text()))
+        {
+            return self.render_screensaver(settings.as_ref());
+        }
+        Ok(())
+    }
+
+    /// Blanks all keys, the clock screensaver shows the time on the center key.
+    fn render_screensaver(
+        &mut self,
+        settings: Option<&StreamdeckDisplaySettings>,
+    ) -> anyhow::Result<()> {
+        let model = self.model();
+        let clock_key = match settings.and_then(|settings| settings.screensaver.clone()) {
+            Some(StreamdeckScreensaver::Clock) => {
+                model.key_index(model.columns() / 2, model.rows() / 2)
+            }
+            _ => None,
+        };
+        self.screensaver_clock = clock_key.map(|_| get_clock_text());
+
+        let clock_style = StreamdeckKeyStyle {
+            foreground_color: Some("FFFFFF".to_owned()),
+            background_color: Some("000000".to_owned()),
+            ..Default::default()
+        };
+        for key in 0..model.key_count() {
+            match &self.screensaver_clock {
+                Some(clock) if clock_key == Some(key) => {
+                    self.streamdeck_client
+                        .set_button(key, clock, None, &clock_style)?
+                }
+                _ => self.streamdeck_client.clear_button(key)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn get_current_folder(&self) -> Option<String> {
+        self.display
+            .lock()
+            .unwrap()
+            .current_folder()
+            .map(str::to_owned)
+    }
+
+    fn get_display_settings(&self) -> Option<StreamdeckDisplaySettings> {
+        self.button_configuration_manager
+            .read()
+            .unwrap()
+            .get_configuration()
+            .display
+            .clone()
+    }
+
+    fn on_display_command(&mut self, command: StreamdeckDisplayCommand) -> anyhow::Result<()> {
+        let settings = self.get_display_settings();
+        {
+            let mut display = self.display.lock().unwrap();
+            match command {
+                StreamdeckDisplayCommand::SetBrightness { brightness } => {
+                    display.set_brightness(brightness);
+                    display.wake(Instant::now());
+                }
+                StreamdeckDisplayCommand::Wake => display.wake(Instant::now()),
+                StreamdeckDisplayCommand::Sleep => display.sleep(),
+            }
+            display.update(Instant::now(), settings.as_ref());
+        }
+        self.fill_streamdeck()
+    }
+
+    /// Renders the button with the text, image and style of its currently active state.
+    fn render_button(
+        &mut self,
+        button_configuration: &StreamdeckButtonConfiguration,
+        folder_id: Option<&str>,
+        device_style: Option<&StreamdeckKeyStyle>,
+    ) -> anyhow::Result<()> {
+        let state_value = button_configuration
+            .state_binding
+            .as_ref()
+            .and_then(|state_binding| self.states.get(&state_binding.state_id));
+        let state = button_configuration.find_state(state_value);
 
+        let mut text = state
+            .and_then(|state| state.text.as_ref())
+            .unwrap_or(&button_configuration.text)
+            .clone();
+        // the value of a live key is shown below the button text
+        if let Some(live_key) = &button_configuration.live_key {
+            let value = self.live_keys.get_value(
+                folder_id,
+                button_configuration.key,
+                live_key,
+                Instant::now(),
+            );
+            let live_text = get_live_key_text(live_key, &value);
+            text = match text.is_empty() {
+                true => live_text,
+                false => format!("{}\n{}", text, live_text),
+            };
+        }
+        // while offline, buttons are shown without images in grey to make clear that they do not work
+        let offline_style = get_offline_style();
+        let online = self.connection.is_connected();
+        let image_name = state
+            .and_then(|state| state.image.as_ref())
+            .or(button_configuration.image.as_ref())
+            .filter(|_| online);
+        let style = [
+            (!online).then_some(&offline_style),
+            state.and_then(|state| state.style.as_ref()),
+            button_configuration.style.as_ref(),
+            device_style,
+        ]
+        .into_iter()
+        .flatten()
+        .fold(StreamdeckKeyStyle::default(), |style, fallback| {
+            style.merge(fallback)
+        });
+
+        let image = match image_name {
+            Some(image_name) => self
+                .asset_cache
+                .load_image(image_name)
+                .unwrap_or_else(|err| {
+                    warn!("Could not load image for button: {}.", err);
+                    None
+                }),
+            None => None,
+        };
+        self.streamdeck_client
+            .set_button(button_configuration.key, &text, image.as_ref(), &style)
+    }
+
+    /// Shows on the key that its macro was not sent because the server is not reachable.
+    pub fn show_rejected(&mut self, key: u8) -> anyhow::Result<()> {
+        let current_folder = self.get_current_folder();
+        let text = self
+            .button_configuration_manager
+            .read()
+            .unwrap()
+            .get_configuration()
+            .find_button_configuration(current_folder.as_deref(), key)
+            .map(|button_configuration| button_configuration.text.clone())
+            .unwrap_or_default();
+        let style = StreamdeckKeyStyle {
+            foreground_color: Some("FFFFFF".to_owned()),
+            background_color: Some("B00020".to_owned()),
+            ..Default::default()
+        };
+        self.streamdeck_client.set_button(key, &text, None, &style)
+    }
+
+    /// Stores the changed states and re-renders only the buttons which are bound to one of them.
+    fn update_states(&mut self, states: Vec<AutomationState>) -> anyhow::Result<()> {
+        for state in &states {
+            self.states.insert(state.id.clone(), state.value.clone());
+        }
+        if self.display.lock().unwrap().mode() == DisplayMode::Screensaver {
+            return Ok(());
+        }
+
+        let current_folder = self.get_current_folder();
+        let button_configuration_manager = self.button_configuration_manager.clone();
+        let configuration_manager = button_configuration_manager.read().unwrap();
+        let configuration = configuration_manager.get_configuration();
+        let affected_buttons = configuration
+            .get_button_configurations(current_folder.as_deref())
+            .iter()
+            .filter(|button_configuration| {
+                button_configuration
+                    .state_binding
+                    .as_ref()
+                    .is_some_and(|state_binding| {
+                        states
+                            .iter()
+                            .any(|state| state.id.eq(&state_binding.state_id))
+                    })
+            });
+        for button_configuration in affected_buttons {
+            self.render_button(
+                button_configuration,
+                current_folder.as_deref(),
+                configuration.style.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn on_live_key_gesture(
+        &mut self,
+        folder_id: Option<&str>,
+        gesture_event: GestureEvent,
+    ) -> anyhow::Result<()> {
+        let live_key = self
+            .button_configuration_manager
+            .read()
+            .unwrap()
+            .get_configuration()
+            .find_button_configuration(folder_id, gesture_event.key)
+            .and_then(|button_configuration| button_configuration.live_key.clone());
+        if let Some(live_key) = live_key {
+            self.live_keys.on_gesture(
+                folder_id,
+                gesture_event.key,
+                &live_key,
+                gesture_event.gesture,
+                Instant::now(),
+            );
+        }
+        self.update_live_keys()
+    }
+
+    /// Re-renders the visible live keys whose value changed and reports the changed values of all live keys
+    /// to the server, so that other clients can show them.
+    pub fn update_live_keys(&mut self) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let (current_folder, mode) = {
+            let display = self.display.lock().unwrap();
+            (display.current_folder().map(str::to_owned), display.mode())
+        };
+        let button_configuration_manager = self.button_configuration_manager.clone();
+        let configuration_manager = button_configuration_manager.read().unwrap();
         let configuration = configuration_manager.get_configuration();
+        let buttons = configuration
+            .button_configurations
+            .iter()
+            .map(|button_configuration| (None, button_configuration))
+            .chain(configuration.folders.iter().flat_map(|folder| {
+                folder
+                    .button_configurations
+                    .iter()
+                    .map(|button_configuration| (Some(folder.id.as_str()), button_configuration))
+            }));
 
-        for button_configuration in &configuration.button_configurations {
-            self.streamdeck_client
-                .set_button_text(button_configuration.key, &button_configuration.text)?;
+        let mut changed_states = Vec::new();
+        for (folder_id, button_configuration) in buttons {
+            let live_key = match &button_configuration.live_key {
+                Some(live_key) => live_key,
+                None => continue,
+            };
+            let value =
+                self.live_keys
+                    .get_value(folder_id, button_configuration.key, live_key, now);
+            let state_id =
+                streamdeck_key_state_id(&self.device_id, folder_id, button_configuration.key);
+            if self.reported_live_values.get(&state_id) == Some(&value) {
+                continue;
+            }
+            if folder_id == current_folder.as_deref() && mode != DisplayMode::Screensaver {
+                self.render_button(
+                    button_configuration,
+                    folder_id,
+                    configuration.style.as_ref(),
+                )?;
+            }
+            self.reported_live_values
+                .insert(state_id.clone(), value.clone());
+            changed_states.push(AutomationState::new(state_id, value));
         }
 
+        if !changed_states.is_empty() && self.connection.is_connected() {
+            self.connection.send(AutomationMessage::ReportStates {
+                states: changed_states,
+            })?;
+        }
         Ok(())
     }
+
+    fn on_devices_configuration_reloaded(&mut self, configuration: StreamdeckDevicesConfiguration) {
+        match configuration
+            .devices
+            .into_iter()
+            .find(|client_configuration| client_configuration.device_id.eq(&self.device_id))
+        {
+            Some(device_configuration) => {
+                if let Err(err) = device_configuration.configuration.validate(self.model()) {
+                    error!(
+                        "Rejecting streamdeck button configuration for client id {}: {}",
+                        &self.device_id, err
+                    );
+                    return;
+                }
+
+                self.display
+                    .lock()
+                    .unwrap()
+                    .close_removed_folders(&device_configuration.configuration);
+                let mut button_configuration_manager_guard =
+                    self.button_configuration_manager.write().unwrap();
+                button_configuration_manager_guard
+                    .set_configuration(device_configuration.configuration);
+
+                if let Err(err) = button_configuration_manager_guard.persist_configuration() {
+                    error!(
+                        "Could not persist new streamdeck button configuration: {}",
+                        err
+                    );
+                } else {
+                    drop(button_configuration_manager_guard);
+                    if let Err(err) = self.fill_streamdeck() {
+                        error!("Could not fill streamdeck: {}.", err);
+                    }
+                    self.fetch_missing_assets();
+                }
+            }
+            None => error!(
+                "Could not find configuration in server configuration with client id: {}",
+                &self.device_id
+            ),
+        }
+    }
+
+    /// Fetches images which are not cached yet in the background and requests a refresh of the streamdeck once they are available.
+    fn fetch_missing_assets(&self) {
+        let configuration_manager = self.button_configuration_manager.read().unwrap();
+        let missing_assets: Vec<String> = configuration_manager
+            .get_configuration()
+            .all_button_configurations()
+            .flat_map(|button_configuration| button_configuration.images())
+            .filter(|image_name| !self.asset_cache.is_cached(image_name))
+            .cloned()
+            .collect();
+        if missing_assets.is_empty() {
+            return;
+        }
+
+        let asset_cache = self.asset_cache.clone();
+        let render_sender = self.render_sender.clone();
+        tokio::spawn(async move {
+            match asset_cache.fetch_missing_assets(missing_assets).await {
+                Ok(true) => {
+                    if let Err(err) = render_sender.send(RenderRequest::Refresh) {
+                        error!("Could not request streamdeck refresh: {}.", err);
+                    }
+                }
+                Ok(false) => {}
+                Err(err) => error!("Could not fetch assets from server: {}.", err),
+            }
+        });
+    }
+}
+
+pub type SharedStreamdeckAutomationClient = Arc<std::sync::Mutex<StreamdeckAutomationClient>>;
+
+/// Shares the websocket connection between the clients of all streamdecks which are attached to this host.
+#[derive(Clone)]
+pub struct StreamdeckAutomationClients {
+    clients: Vec<SharedStreamdeckAutomationClient>,
+}
+
+impl StreamdeckAutomationClients {
+    pub fn new(clients: Vec<SharedStreamdeckAutomationClient>) -> StreamdeckAutomationClients {
+        StreamdeckAutomationClients { clients }
+    }
+
+    pub fn get_connected_streamdecks(&self) -> Vec<ConnectedStreamdeck> {
+        self.clients
+            .iter()
+            .flat_map(|client| client.lock().unwrap().get_connected_streamdecks())
+            .collect()
+    }
+}
+
+impl AutomationStatusUpdateHandler for StreamdeckAutomationClients {
+    fn on_connected(&mut self, sender: UnboundedSender<AutomationMessage>) {
+        for client in &self.clients {
+            client.lock().unwrap().on_connected(sender.clone());
+        }
+    }
+
+    fn on_disconnected(&mut self) {
+        for client in &self.clients {
+            client.lock().unwrap().on_disconnected();
+        }
+    }
+
+    fn on_status_update(&mut self, status_update: AutomationStatusUpdate) {
+        for client in &self.clients {
+            client
+                .lock()
+                .unwrap()
+                .on_status_update(status_update.clone());
+        }
+    }
+}
+
+fn get_offline_style() -> StreamdeckKeyStyle {
+    StreamdeckKeyStyle {
+        foreground_color: Some("AAAAAA".to_owned()),
+        background_color: Some("3A3A3A".to_owned()),
+        ..Default::default()
+    }
+}
+
+fn get_clock_text() -> String {
+    chrono::Local::now().format("%H:%M").to_string()
 }
 
 impl AutomationStatusUpdateHandler for StreamdeckAutomationClient {
+    fn on_connected(&mut self, sender: UnboundedSender<AutomationMessage>) {
+        self.connection.connect(sender);
+        if let Err(err) = self.fill_streamdeck() {
+            error!("Could not fill streamdeck after connecting: {}.", err);
+        }
+        // the server does not know any live key values of this connection yet
+        self.reported_live_values.clear();
+        if let Err(err) = self.update_live_keys() {
+            error!("Could not report live keys after connecting: {}.", err);
+        }
+    }
+
+    fn on_disconnected(&mut self) {
+        self.connection.disconnect();
+        if let Err(err) = self.fill_streamdeck() {
+            error!("Could not show disconnected state on streamdeck: {}.", err);
+        }
+    }
+
     fn on_status_update(&mut self, status_update: AutomationStatusUpdate) {
-        if let AutomationStatusUpdate::StreamdeckClientReloadedDevicesConfiguration(configuration) =
-            status_update
-        {
-            match configuration
-                .devices
-                .into_iter()
-                .find(|client_configuration| {
-                    client_configuration
-                        .device_id
-                        .eq(&self.configuration.device_id)
-                }) {
-                Some(device_configuration) => {
-                    let mut button_configuration_manager_guard =
-                        self.button_configuration_manager.write().unwrap();
-                    button_configuration_manager_guard
-                        .set_configuration(device_configuration.configuration);
-
-                    if let Err(err) = button_configuration_manager_guard.persist_configuration() {
-                        error!(
-                            "Could not persist new streamdeck button configuration: {}",
-                            err
-                        );
-                    } else {
-                        drop(button_configuration_manager_guard);
-                        if let Err(err) = self.fill_streamdeck() {
-                            error!("Could not fill streamdeck: {}.", err);
-                        }
-                    }
+        match status_update {
+            AutomationStatusUpdate::StreamdeckClientReloadedDevicesConfiguration(configuration) => {
+                self.on_devices_configuration_reloaded(configuration)
+            }
+            AutomationStatusUpdate::StreamdeckClientDisplayCommand { device_id, command }
+                if device_id.eq(&self.device_id) =>
+            {
+                if let Err(err) = self.on_display_command(command) {
+                    error!("Could not execute streamdeck display command: {}.", err);
+                }
+            }
+            AutomationStatusUpdate::StatesChanged { states } => {
+                if let Err(err) = self.update_states(states) {
+                    error!("Could not update streamdeck states: {}.", err);
                 }
-                None => error!(
-                    "Could not find configuration in server configuration with client id: {}",
-                    &self.configuration.device_id
-                ),
             }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use home_automation_common::automacro::AutomationMacro;
+    use home_automation_common::automodule::streamdeck::{
+        StreamdeckButtonState, StreamdeckButtonStateBinding, StreamdeckClientConfiguration,
+        StreamdeckDeviceConfiguration, StreamdeckLiveKey, StreamdeckModel,
+    };
+    use home_automation_common::fs;
+    use home_automation_common::test::TestContext;
+    use image::Rgb;
+
+    use super::*;
+    use crate::device::simulator::VirtualStreamdeck;
+    use crate::display::DisplayController;
+    use crate::gesture::Gesture;
+
+    const DEVICE_ID: &str = "test_device";
+    const OTHER_DEVICE_ID: &str = "other_test_device";
+    const STATE_ID: &str = "test_state";
+
+    struct TestData {
+        path: PathBuf,
+        deck: VirtualStreamdeck,
+        client: StreamdeckAutomationClient,
+    }
+
+    fn create_client(
+        path: &Path,
+        deck: &VirtualStreamdeck,
+        device_id: &str,
+    ) -> StreamdeckAutomationClient {
+        let button_configuration_manager = Arc::new(RwLock::new(
+            ConfigurationManager::load(path, &format!("buttons_{}.json", device_id)).unwrap(),
+        ));
+        let asset_cache =
+            AssetCache::new(path, &StreamdeckClientConfiguration::default(), None).unwrap();
+        let (render_sender, _) = tokio::sync::mpsc::unbounded_channel();
+
+        StreamdeckAutomationClient::new(
+            deck.shared(),
+            device_id.to_owned(),
+            button_configuration_manager,
+            asset_cache,
+            render_sender,
+            Arc::new(std::sync::Mutex::new(
+                DisplayController::new(Instant::now()),
+            )),
+            ServerConnection::default(),
+        )
+        .unwrap()
+    }
+
+    fn setup(model: StreamdeckModel) -> TestData {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let deck = VirtualStreamdeck::new(model);
+        let mut client = create_client(&path, &deck, DEVICE_ID);
+        let (message_sender, _) = tokio::sync::mpsc::unbounded_channel();
+        client.on_connected(message_sender);
+        TestData { path, deck, client }
+    }
+
+    fn teardown(test_data: &mut TestData) {
+        fs::util::delete_temp_folder(&test_data.path).unwrap();
+    }
+
+    fn button(key: u8) -> StreamdeckButtonConfiguration {
+        StreamdeckButtonConfiguration {
+            key,
+            text: format!("Key {}", key),
+            image: None,
+            style: None,
+            state_binding: None,
+            press_macro: AutomationMacro::new("test".to_owned(), vec![]),
+            release_macro: None,
+            long_press_macro: None,
+            double_press_macro: None,
+            repeat_macro: None,
+            gesture_timing: None,
+            offline_action: None,
+            offline_queue_seconds: None,
+            folder_action: None,
+            live_key: None,
         }
     }
+
+    fn reload_configuration(
+        client: &mut StreamdeckAutomationClient,
+        button_configurations: Vec<StreamdeckButtonConfiguration>,
+    ) {
+        client.on_status_update(
+            AutomationStatusUpdate::StreamdeckClientReloadedDevicesConfiguration(
+                StreamdeckDevicesConfiguration {
+                    devices: vec![StreamdeckDeviceConfiguration {
+                        device_id: DEVICE_ID.to_owned(),
+                        configuration: StreamdeckAutomationConfiguration {
+                            button_configurations,
+                            ..Default::default()
+                        },
+                        layouts: Vec::new(),
+                    }],
+                },
+            ),
+        );
+    }
+
+    fn background_colour(deck: &VirtualStreamdeck, key: u8) -> Rgb<u8> {
+        *deck.key_image(key).unwrap().to_rgb8().get_pixel(0, 0)
+    }
+
+    #[test]
+    fn render_reloaded_configuration() {
+        let mut context = TestContext::setup(&|| setup(StreamdeckModel::Original), &teardown);
+        let test_data = &mut context.test_data;
+
+        reload_configuration(&mut test_data.client, vec![button(0), button(14)]);
+
+        assert_eq!(15, test_data.deck.rendered_keys().len());
+        assert_eq!(
+            Rgb([0x1B, 0x5B, 0x88]),
+            background_colour(&test_data.deck, 0)
+        );
+        assert_eq!(
+            Rgb([0x1B, 0x5B, 0x88]),
+            background_colour(&test_data.deck, 14)
+        );
+        assert_eq!(Rgb([0, 0, 0]), background_colour(&test_data.deck, 1));
+        let persisted_keys: Vec<u8> = test_data
+            .client
+            .button_configuration_manager
+            .read()
+            .unwrap()
+            .get_configuration()
+            .button_configurations
+            .iter()
+            .map(|button_configuration| button_configuration.key)
+            .collect();
+        assert_eq!(vec![0, 14], persisted_keys);
+    }
+
+    #[test]
+    fn reject_configuration_for_other_model() {
+        let mut context = TestContext::setup(&|| setup(StreamdeckModel::Mini), &teardown);
+        let test_data = &mut context.test_data;
+
+        reload_configuration(&mut test_data.client, vec![button(0), button(14)]);
+
+        assert_eq!(Rgb([0, 0, 0]), background_colour(&test_data.deck, 0));
+        assert!(test_data
+            .client
+            .button_configuration_manager
+            .read()
+            .unwrap()
+            .get_configuration()
+            .button_configurations
+            .is_empty());
+    }
+
+    #[test]
+    fn render_state_changes() {
+        let mut context = TestContext::setup(&|| setup(StreamdeckModel::Mini), &teardown);
+        let test_data = &mut context.test_data;
+
+        let mut bound_button = button(1);
+        bound_button.state_binding = Some(StreamdeckButtonStateBinding {
+            state_id: STATE_ID.to_owned(),
+            states: vec![StreamdeckButtonState {
+                value: Some(AutomationStateValue::Bool(true)),
+                min: None,
+                max: None,
+                text: Some("On".to_owned()),
+                image: None,
+                style: Some(StreamdeckKeyStyle {
+                    background_color: Some("#FF0000".to_owned()),
+                    ..Default::default()
+                }),
+            }],
+        });
+        reload_configuration(&mut test_data.client, vec![button(0), bound_button]);
+        assert_eq!(
+            Rgb([0x1B, 0x5B, 0x88]),
+            background_colour(&test_data.deck, 1)
+        );
+
+        test_data
+            .client
+            .on_status_update(AutomationStatusUpdate::StatesChanged {
+                states: vec![AutomationState::new(
+                    STATE_ID.to_owned(),
+                    AutomationStateValue::Bool(true),
+                )],
+            });
+
+        assert_eq!(
+            Rgb([0xFF, 0x00, 0x00]),
+            background_colour(&test_data.deck, 1)
+        );
+        assert_eq!(
+            Rgb([0x1B, 0x5B, 0x88]),
+            background_colour(&test_data.deck, 0)
+        );
+    }
+
+    #[test]
+    fn sleep_and_wake_on_display_command() {
+        let mut context = TestContext::setup(&|| setup(StreamdeckModel::Mini), &teardown);
+        let test_data = &mut context.test_data;
+        reload_configuration(&mut test_data.client, vec![button(0)]);
+
+        let send_command = |client: &mut StreamdeckAutomationClient, device_id: &str, command| {
+            client.on_status_update(AutomationStatusUpdate::StreamdeckClientDisplayCommand {
+                device_id: device_id.to_owned(),
+                command,
+            });
+        };
+
+        send_command(
+            &mut test_data.client,
+            DEVICE_ID,
+            StreamdeckDisplayCommand::Sleep,
+        );
+        assert_eq!(10, test_data.deck.brightness());
+        assert_eq!(Rgb([0, 0, 0]), background_colour(&test_data.deck, 0));
+
+        send_command(
+            &mut test_data.client,
+            DEVICE_ID,
+            StreamdeckDisplayCommand::SetBrightness { brightness: 60 },
+        );
+        assert_eq!(60, test_data.deck.brightness());
+        assert_eq!(
+            Rgb([0x1B, 0x5B, 0x88]),
+            background_colour(&test_data.deck, 0)
+        );
+
+        // commands for other decks are ignored
+        send_command(
+            &mut test_data.client,
+            "other_device",
+            StreamdeckDisplayCommand::Sleep,
+        );
+        assert_eq!(60, test_data.deck.brightness());
+    }
+
+    #[test]
+    fn show_offline_state() {
+        let mut context = TestContext::setup(&|| setup(StreamdeckModel::Mini), &teardown);
+        let test_data = &mut context.test_data;
+        reload_configuration(&mut test_data.client, vec![button(0)]);
+
+        test_data.client.on_disconnected();
+        assert_eq!(
+            Rgb([0x3A, 0x3A, 0x3A]),
+            background_colour(&test_data.deck, 0)
+        );
+        assert!(!test_data.client.connection.is_connected());
+
+        test_data.client.show_rejected(0).unwrap();
+        assert_eq!(
+            Rgb([0xB0, 0x00, 0x20]),
+            background_colour(&test_data.deck, 0)
+        );
+
+        let (message_sender, _) = tokio::sync::mpsc::unbounded_channel();
+        test_data.client.on_connected(message_sender);
+        assert_eq!(
+            Rgb([0x1B, 0x5B, 0x88]),
+            background_colour(&test_data.deck, 0)
+        );
+        assert!(test_data.client.connection.is_connected());
+    }
+
+    #[test]
+    fn report_live_key_values() {
+        let mut context = TestContext::setup(&|| setup(StreamdeckModel::Mini), &teardown);
+        let test_data = &mut context.test_data;
+        let mut counter_button = button(2);
+        counter_button.live_key = Some(StreamdeckLiveKey::Counter);
+        reload_configuration(&mut test_data.client, vec![counter_button]);
+        let (message_sender, mut message_receiver) = tokio::sync::mpsc::unbounded_channel();
+        test_data.client.on_connected(message_sender);
+
+        let state_id = streamdeck_key_state_id(DEVICE_ID, None, 2);
+        assert_eq!(
+            Ok(AutomationMessage::ReportStates {
+                states: vec![AutomationState::new(
+                    state_id.clone(),
+                    AutomationStateValue::Number(0)
+                )],
+            }),
+            message_receiver.try_recv()
+        );
+
+        let press = |gesture| GestureEvent { key: 2, gesture };
+        test_data
+            .client
+            .on_live_key_gesture(None, press(Gesture::Press))
+            .unwrap();
+        test_data
+            .client
+            .on_live_key_gesture(None, press(Gesture::Press))
+            .unwrap();
+        test_data.client.update_live_keys().unwrap();
+        let reported_values: Vec<AutomationMessage> =
+            std::iter::from_fn(|| message_receiver.try_recv().ok()).collect();
+        assert_eq!(
+            vec![1, 2]
+                .into_iter()
+                .map(|count| AutomationMessage::ReportStates {
+                    states: vec![AutomationState::new(
+                        state_id.clone(),
+                        AutomationStateValue::Number(count)
+                    )],
+                })
+                .collect::<Vec<AutomationMessage>>(),
+            reported_values
+        );
+    }
+
+    #[test]
+    fn route_updates_to_multiple_decks() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let deck = VirtualStreamdeck::new(StreamdeckModel::Original);
+        let other_deck = VirtualStreamdeck::new(StreamdeckModel::Mini);
+        let mut clients = StreamdeckAutomationClients::new(vec![
+            Arc::new(std::sync::Mutex::new(create_client(
+                &path, &deck, DEVICE_ID,
+            ))),
+            Arc::new(std::sync::Mutex::new(create_client(
+                &path,
+                &other_deck,
+                OTHER_DEVICE_ID,
+            ))),
+        ]);
+        let (message_sender, _) = tokio::sync::mpsc::unbounded_channel();
+        clients.on_connected(message_sender);
+
+        clients.on_status_update(
+            AutomationStatusUpdate::StreamdeckClientReloadedDevicesConfiguration(
+                StreamdeckDevicesConfiguration {
+                    devices: vec![
+                        StreamdeckDeviceConfiguration {
+                            device_id: DEVICE_ID.to_owned(),
+                            configuration: StreamdeckAutomationConfiguration {
+                                button_configurations: vec![button(14)],
+                                ..Default::default()
+                            },
+                            layouts: Vec::new(),
+                        },
+                        StreamdeckDeviceConfiguration {
+                            device_id: OTHER_DEVICE_ID.to_owned(),
+                            configuration: StreamdeckAutomationConfiguration {
+                                button_configurations: vec![button(1)],
+                                ..Default::default()
+                            },
+                            layouts: Vec::new(),
+                        },
+                    ],
+                },
+            ),
+        );
+        assert_eq!(Rgb([0x1B, 0x5B, 0x88]), background_colour(&deck, 14));
+        assert_eq!(Rgb([0, 0, 0]), background_colour(&deck, 1));
+        assert_eq!(Rgb([0x1B, 0x5B, 0x88]), background_colour(&other_deck, 1));
+
+        clients.on_status_update(AutomationStatusUpdate::StreamdeckClientDisplayCommand {
+            device_id: OTHER_DEVICE_ID.to_owned(),
+            command: StreamdeckDisplayCommand::SetBrightness { brightness: 30 },
+        });
+        assert_eq!(100, deck.brightness());
+        assert_eq!(30, other_deck.brightness());
+
+        let device_ids: Vec<String> = clients
+            .get_connected_streamdecks()
+            .into_iter()
+            .map(|streamdeck| streamdeck.device_id)
+            .collect();
+        assert_eq!(
+            vec![DEVICE_ID.to_owned(), OTHER_DEVICE_ID.to_owned()],
+            device_ids
+        );
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
 }
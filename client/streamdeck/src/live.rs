@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use home_automation_common::automodule::streamdeck::StreamdeckLiveKey;
+use home_automation_common::state::AutomationStateValue;
+use tokio::time::Instant;
+
+use crate::gesture::Gesture;
+
+const DEFAULT_CLOCK_FORMAT: &str = "%H:%M";
+
+/// Keeps the values of the timers, stopwatches and counters of one streamdeck, identified by folder and key.
+/// The values survive switching folders and reloading the configuration.
+#[derive(Default)]
+pub struct LiveKeys {
+    values: HashMap<(Option<String>, u8), LiveKeyValue>,
+}
+
+#[derive(Default)]
+struct LiveKeyValue {
+    count: i64,
+    elapsed: Duration,
+    running_since: Option<Instant>,
+}
+
+impl LiveKeyValue {
+    fn elapsed(&self, now: Instant) -> Duration {
+        self.elapsed
+            + self
+                .running_since
+                .map(|running_since| now.saturating_duration_since(running_since))
+                .unwrap_or_default()
+    }
+
+    fn toggle(&mut self, now: Instant) {
+        match self.running_since.take() {
+            Some(running_since) => {
+                self.elapsed += now.saturating_duration_since(running_since);
+            }
+            None => self.running_since = Some(now),
+        }
+    }
+}
+
+impl LiveKeys {
+    /// A press increments counters and starts or pauses timers and stopwatches, a long press resets them.
+    pub fn on_gesture(
+        &mut self,
+        folder_id: Option<&str>,
+        key: u8,
+        live_key: &StreamdeckLiveKey,
+        gesture: Gesture,
+        now: Instant,
+    ) {
+        let value = self
+            .values
+            .entry((folder_id.map(str::to_owned), key))
+            .or_default();
+        match (live_key, gesture) {
+            (StreamdeckLiveKey::Clock { .. }, _) => {}
+            (_, Gesture::LongPress) => *value = LiveKeyValue::default(),
+            (StreamdeckLiveKey::Counter, Gesture::Press) => value.count += 1,
+            (StreamdeckLiveKey::Timer { duration_seconds }, Gesture::Press)
+                if value.elapsed(now) >= Duration::from_secs(*duration_seconds) =>
+            {
+                // pressing a finished timer starts it again
+                *value = LiveKeyValue {
+                    running_since: Some(now),
+                    ..Default::default()
+                };
+            }
+            (StreamdeckLiveKey::Timer { .. } | StreamdeckLiveKey::Stopwatch, Gesture::Press) => {
+                value.toggle(now)
+            }
+            _ => {}
+        }
+    }
+
+    /// Timers report the remaining and stopwatches the elapsed seconds.
+    pub fn get_value(
+        &self,
+        folder_id: Option<&str>,
+        key: u8,
+        live_key: &StreamdeckLiveKey,
+        now: Instant,
+    ) -> AutomationStateValue {
+        let default_value = LiveKeyValue::default();
+        let value = self
+            .values
+            .get(&(folder_id.map(str::to_owned), key))
+            .unwrap_or(&default_value);
+        match live_key {
+            StreamdeckLiveKey::Clock { format } => AutomationStateValue::Text(get_clock_text(
+                format.as_deref().unwrap_or(DEFAULT_CLOCK_FORMAT),
+            )),
+            StreamdeckLiveKey::Timer { duration_seconds } => {
+                let remaining = Duration::from_secs(*duration_seconds)
+                    .saturating_sub(value.elapsed(now))
+                    .as_secs_f64()
+                    .ceil();
+                AutomationStateValue::Number(remaining as i64)
+            }
+            StreamdeckLiveKey::Stopwatch => {
+                AutomationStateValue::Number(value.elapsed(now).as_secs() as i64)
+            }
+            StreamdeckLiveKey::Counter => AutomationStateValue::Number(value.count),
+        }
+    }
+}
+
+/// The text which is shown on the key for a value of the live key.
+pub fn get_live_key_text(live_key: &StreamdeckLiveKey, value: &AutomationStateValue) -> String {
+    match (live_key, value) {
+        (
+            StreamdeckLiveKey::Timer { .. } | StreamdeckLiveKey::Stopwatch,
+            AutomationStateValue::Number(seconds),
+        ) => {
+            let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+            if hours > 0 {
+                format!("{}:{:02}:{:02}", hours, minutes, seconds)
+            } else {
+                format!("{:02}:{:02}", minutes, seconds)
+            }
+        }
+        (_, AutomationStateValue::Number(number)) => number.to_string(),
+        (_, AutomationStateValue::Text(text)) => text.clone(),
+        (_, AutomationStateValue::Bool(value)) => value.to_string(),
+    }
+}
+
+fn get_clock_text(format: &str) -> String {
+    let mut text = String::new();
+    // formatting fails for invalid format strings instead of panicking like to_string
+    if write!(text, "{}", chrono::Local::now().format(format)).is_err() {
+        warn!("Invalid clock format: {}.", format);
+        text.clear();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_presses() {
+        let mut live_keys = LiveKeys::default();
+        let now = Instant::now();
+        let counter = StreamdeckLiveKey::Counter;
+
+        live_keys.on_gesture(None, 1, &counter, Gesture::Press, now);
+        live_keys.on_gesture(None, 1, &counter, Gesture::Press, now);
+        live_keys.on_gesture(Some("folder"), 1, &counter, Gesture::Press, now);
+        assert_eq!(
+            AutomationStateValue::Number(2),
+            live_keys.get_value(None, 1, &counter, now)
+        );
+        assert_eq!(
+            AutomationStateValue::Number(1),
+            live_keys.get_value(Some("folder"), 1, &counter, now)
+        );
+
+        live_keys.on_gesture(None, 1, &counter, Gesture::LongPress, now);
+        assert_eq!(
+            AutomationStateValue::Number(0),
+            live_keys.get_value(None, 1, &counter, now)
+        );
+    }
+
+    #[test]
+    fn run_timer_and_stopwatch() {
+        let mut live_keys = LiveKeys::default();
+        let start = Instant::now();
+        let timer = StreamdeckLiveKey::Timer {
+            duration_seconds: 90,
+        };
+        let stopwatch = StreamdeckLiveKey::Stopwatch;
+        let after = |seconds| start + Duration::from_secs(seconds);
+
+        assert_eq!(
+            "01:30",
+            get_live_key_text(&timer, &live_keys.get_value(None, 0, &timer, start))
+        );
+        live_keys.on_gesture(None, 0, &timer, Gesture::Press, start);
+        live_keys.on_gesture(None, 1, &stopwatch, Gesture::Press, start);
+        assert_eq!(
+            AutomationStateValue::Number(60),
+            live_keys.get_value(None, 0, &timer, after(30))
+        );
+
+        // paused for ten seconds
+        live_keys.on_gesture(None, 0, &timer, Gesture::Press, after(30));
+        live_keys.on_gesture(None, 1, &stopwatch, Gesture::Press, after(30));
+        live_keys.on_gesture(None, 0, &timer, Gesture::Press, after(40));
+        live_keys.on_gesture(None, 1, &stopwatch, Gesture::Press, after(40));
+        assert_eq!(
+            AutomationStateValue::Number(30),
+            live_keys.get_value(None, 0, &timer, after(70))
+        );
+        assert_eq!(
+            "01:00",
+            get_live_key_text(
+                &stopwatch,
+                &live_keys.get_value(None, 1, &stopwatch, after(70))
+            )
+        );
+        assert_eq!(
+            AutomationStateValue::Number(0),
+            live_keys.get_value(None, 0, &timer, after(200))
+        );
+
+        // a finished timer starts again
+        live_keys.on_gesture(None, 0, &timer, Gesture::Press, after(200));
+        assert_eq!(
+            AutomationStateValue::Number(80),
+            live_keys.get_value(None, 0, &timer, after(210))
+        );
+        live_keys.on_gesture(None, 1, &stopwatch, Gesture::LongPress, after(210));
+        assert_eq!(
+            AutomationStateValue::Number(0),
+            live_keys.get_value(None, 1, &stopwatch, after(220))
+        );
+    }
+
+    #[test]
+    fn show_clock_with_invalid_format() {
+        let live_keys = LiveKeys::default();
+        let clock = StreamdeckLiveKey::Clock {
+            format: Some("%Q".to_owned()),
+        };
+        assert_eq!(
+            AutomationStateValue::Text(String::new()),
+            live_keys.get_value(None, 0, &clock, Instant::now())
+        );
+    }
+}
@@ -2,35 +2,51 @@
 
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate serde_derive;
 
 use anyhow::{anyhow, Context};
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use hidapi::HidApi;
-use home_automation_client_lib::websocket::{WebsocketClientInfo, WebsocketRunner};
-use home_automation_common::automacro::AutomationMacro;
+use home_automation_client_lib::websocket::{
+    SharedWebsocketClientInfo, WebsocketClientInfo, WebsocketRunner,
+};
 use home_automation_common::automodule::streamdeck::{
-    StreamdeckAutomationConfiguration, StreamdeckButtonConfiguration,
+    StreamdeckAutomationConfiguration, StreamdeckClientConfiguration,
 };
-use home_automation_common::config::ConfigurationManager;
-use home_automation_common::websocket::dto::{AutomationMessage, ClientDeviceType};
+use home_automation_common::config::{ConfigurationManager, Secrets};
+use home_automation_common::websocket::dto::ClientDeviceType;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::Mutex;
+use tokio::time::Instant;
 
-use crate::device::handler::{handle_button_presses, ButtonEvent};
-use crate::device::StreamdeckClient;
-use crate::handler::StreamdeckAutomationClient;
+use crate::assets::AssetCache;
+use crate::connection::ServerConnection;
+use crate::device::handler::{handle_button_presses, ButtonEvent, DeviceEvent};
+use crate::device::hid::{connect_to_streamdecks, HidStreamdeck};
+use crate::device::SharedStreamdeckDevice;
+use crate::dispatch::handle_button_events;
+use crate::display::{DisplayController, SharedDisplayController};
+use crate::handler::{
+    RenderRequest, SharedStreamdeckAutomationClient, StreamdeckAutomationClient,
+    StreamdeckAutomationClients,
+};
 
 const CONFIG_FILE_NAME: &str = "automationStreamdeckClientConfig.json";
 const BUTTON_CONFIG_FILE_NAME: &str = "buttonAutomationStreamdeckClientConfig.json";
 const APPLICATION_NAME: &str = "automation-streamdeck-client";
+const DISPLAY_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+const REJECTED_FEEDBACK_DURATION: Duration = Duration::from_secs(1);
 
-mod config;
+mod assets;
+mod connection;
 mod device;
+mod dispatch;
+mod display;
+mod gesture;
 mod handler;
+mod live;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
@@ -42,26 +58,22 @@ async fn main() {
     home_automation_client_lib::logging::init_logger(APPLICATION_NAME);
 
     // client config
-    let configuration_manager =
-        ConfigurationManager::<config::Configuration>::load(&application_folder, CONFIG_FILE_NAME)
-            .unwrap_or_else(|err| {
-                panic!("Could not prepare configuration manager: {}", err);
-            });
+    let configuration_manager = ConfigurationManager::<StreamdeckClientConfiguration>::load(
+        &application_folder,
+        CONFIG_FILE_NAME,
+    )
+    .unwrap_or_else(|err| {
+        panic!("Could not prepare configuration manager: {}", err);
+    });
     let configuration = configuration_manager.get_configuration().clone();
-
-    // button config
-    let button_configuration_manager = Arc::new(RwLock::new(
-        ConfigurationManager::<StreamdeckAutomationConfiguration>::load(
-            &application_folder,
-            BUTTON_CONFIG_FILE_NAME,
-        )
+    let auth_token = Secrets::load(&application_folder)
+        .and_then(|secrets| configuration.resolve_token(&secrets))
         .unwrap_or_else(|err| {
-            panic!(
-                "Could not prepare configuration manager for button config: {}",
-                err
-            );
-        }),
-    ));
+            panic!("Could not resolve the token of the client: {}", err);
+        });
+    if auth_token.is_none() {
+        warn!("No token is configured, the server will reject the connection.");
+    }
 
     let hid_api = Arc::new(std::sync::Mutex::new(HidApi::new().unwrap_or_else(|err| {
         panic!("Could not initialize HID API: {}.", err);
@@ -70,19 +82,62 @@ async fn main() {
         panic!("Could not print HID list: {}.", err);
     });
 
-    let streamdeck_automation_client = StreamdeckAutomationClient::new(
-        hid_api.clone(),
-        button_configuration_manager.clone(),
-        configuration.clone(),
-    )
-    .unwrap_or_else(|err| {
-        panic!(
-            "Could not initialize streamdeck automation client: {}.",
-            err
-        )
-    });
+    let asset_cache = AssetCache::new(&application_folder, &configuration, auth_token.clone())
+        .unwrap_or_else(|err| {
+            panic!("Could not prepare asset cache: {}", err);
+        });
 
-    let message_handler = Arc::new(Mutex::new(streamdeck_automation_client));
+    let connection = ServerConnection::default();
+    let mut streamdecks = Vec::new();
+    for device in connect_to_streamdecks(hid_api.clone()).unwrap_or_else(|err| {
+        panic!("Could not connect to streamdecks: {}.", err);
+    }) {
+        let device_id = match configuration.get_device_id(device.serial()) {
+            Some(device_id) => device_id,
+            None => {
+                warn!(
+                    "Ignoring streamdeck with serial {} because it is not configured.",
+                    device.serial().unwrap_or("unknown")
+                );
+                continue;
+            }
+        };
+        let button_config_file_name = get_button_config_file_name(&configuration, &device_id);
+        streamdecks.push(
+            Streamdeck::new(
+                device,
+                device_id,
+                &application_folder,
+                &button_config_file_name,
+                asset_cache.clone(),
+                connection.clone(),
+            )
+            .unwrap_or_else(|err| {
+                panic!("Could not initialize streamdeck: {}.", err);
+            }),
+        );
+    }
+    if streamdecks.is_empty() {
+        panic!("Could not find any configured streamdeck.");
+    }
+
+    let clients = StreamdeckAutomationClients::new(
+        streamdecks
+            .iter()
+            .map(|streamdeck| streamdeck.client.clone())
+            .collect(),
+    );
+    // the client is named after the first deck
+    let button_configuration_manager = streamdecks[0].button_configuration_manager.clone();
+    let client_info = Arc::new(RwLock::new(WebsocketClientInfo {
+        client_name: get_device_name(&button_configuration_manager),
+        client_type: ClientDeviceType::Streamdeck,
+        streamdecks: clients.get_connected_streamdecks(),
+    }));
+    for streamdeck in streamdecks {
+        streamdeck.start(clients.clone(), client_info.clone(), connection.clone());
+    }
+    let message_handler = Arc::new(Mutex::new(clients));
 
     loop {
         info!("Connecting to automation server.");
@@ -92,95 +147,219 @@ async fn main() {
             configuration.server_ip, configuration.server_port
         );
 
-        let client_info = get_client_info(&button_configuration_manager);
-        let websocket_runner =
-            WebsocketRunner::new(client_info, ws_server_url, message_handler.clone());
+        // the device name might have been changed by the server in the meantime
+        client_info.write().unwrap().client_name = get_device_name(&button_configuration_manager);
+        let websocket_runner = WebsocketRunner::new(
+            client_info.clone(),
+            ws_server_url,
+            auth_token.clone(),
+            message_handler.clone(),
+        );
+
+        websocket_runner.stop().await;
+
+        info!("Websocket terminated, reconnecting...");
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Button configuration and tasks of a single streamdeck.
+struct Streamdeck {
+    device: SharedStreamdeckDevice,
+    client: SharedStreamdeckAutomationClient,
+    button_configuration_manager:
+        Arc<RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>>,
+    display: SharedDisplayController,
+    render_sender: UnboundedSender<RenderRequest>,
+    render_receiver: UnboundedReceiver<RenderRequest>,
+}
+
+impl Streamdeck {
+    fn new(
+        device: HidStreamdeck,
+        device_id: String,
+        application_folder: &Path,
+        button_config_file_name: &str,
+        asset_cache: AssetCache,
+        connection: ServerConnection,
+    ) -> anyhow::Result<Streamdeck> {
+        let button_configuration_manager = Arc::new(RwLock::new(
+            ConfigurationManager::<StreamdeckAutomationConfiguration>::load(
+                application_folder,
+                button_config_file_name,
+            )
+            .context("Could not prepare configuration manager for button config")?,
+        ));
+        let device: SharedStreamdeckDevice = Arc::new(std::sync::Mutex::new(Box::new(device)));
+        let display = Arc::new(std::sync::Mutex::new(
+            DisplayController::new(Instant::now()),
+        ));
+        let (render_sender, render_receiver) =
+            tokio::sync::mpsc::unbounded_channel::<RenderRequest>();
+        let client = StreamdeckAutomationClient::new(
+            device.clone(),
+            device_id,
+            button_configuration_manager.clone(),
+            asset_cache,
+            render_sender.clone(),
+            display.clone(),
+            connection,
+        )
+        .context("Could not initialize streamdeck automation client")?;
+
+        Ok(Streamdeck {
+            device,
+            client: Arc::new(std::sync::Mutex::new(client)),
+            button_configuration_manager,
+            display,
+            render_sender,
+            render_receiver,
+        })
+    }
+
+    /// Starts the reader thread and the render tasks of the deck.
+    fn start(
+        self,
+        clients: StreamdeckAutomationClients,
+        client_info: SharedWebsocketClientInfo,
+        connection: ServerConnection,
+    ) {
+        tokio::spawn(handle_render_requests(
+            self.render_receiver,
+            self.render_sender.clone(),
+            self.client.clone(),
+        ));
+        tokio::spawn(handle_display_updates(self.client.clone()));
 
         let (button_event_tx, button_event_rx) =
             tokio::sync::mpsc::unbounded_channel::<ButtonEvent>();
-
-        handle_button_presses(hid_api.clone(), button_event_tx);
+        let (device_event_tx, device_event_rx) =
+            tokio::sync::mpsc::unbounded_channel::<DeviceEvent>();
+        handle_button_presses(self.device, button_event_tx, device_event_tx);
         tokio::spawn(handle_button_events(
             button_event_rx,
-            websocket_runner.get_ws_sender(),
-            button_configuration_manager.clone(),
+            connection.clone(),
+            self.button_configuration_manager,
+            self.display,
+            self.render_sender,
         ));
+        tokio::spawn(handle_device_events(
+            device_event_rx,
+            self.client,
+            clients,
+            client_info,
+            connection,
+        ));
+    }
+}
 
-        websocket_runner.stop().await;
-
-        info!("Websocket terminated, reconnecting...");
-        tokio::time::sleep(Duration::from_secs(5)).await;
+/// Decks which are mapped by their serial each get their own button configuration.
+fn get_button_config_file_name(
+    configuration: &StreamdeckClientConfiguration,
+    device_id: &str,
+) -> String {
+    if configuration.streamdecks.is_empty() {
+        return BUTTON_CONFIG_FILE_NAME.to_owned();
     }
+    format!(
+        "{}_{}.json",
+        BUTTON_CONFIG_FILE_NAME.trim_end_matches(".json"),
+        device_id
+    )
 }
 
-fn get_client_info(
+fn get_device_name(
     button_configuration_manager: &Arc<
         RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>,
     >,
-) -> WebsocketClientInfo {
+) -> String {
     let locked_configuration_manager = button_configuration_manager.read().unwrap();
-    let button_configuration = locked_configuration_manager.get_configuration();
-    WebsocketClientInfo {
-        client_name: button_configuration.device_name.clone(),
-        client_type: ClientDeviceType::Streamdeck,
+    locked_configuration_manager
+        .get_configuration()
+        .device_name
+        .clone()
+}
+
+/// Renders all keys again after the deck was plugged back in and reports the presence of the decks to the server.
+async fn handle_device_events(
+    mut receiver: UnboundedReceiver<DeviceEvent>,
+    client: SharedStreamdeckAutomationClient,
+    clients: StreamdeckAutomationClients,
+    client_info: SharedWebsocketClientInfo,
+    connection: ServerConnection,
+) {
+    while let Some(event) = receiver.recv().await {
+        if event == DeviceEvent::Connected {
+            if let Err(err) = client.lock().unwrap().fill_streamdeck() {
+                error!("Could not fill reconnected streamdeck: {}.", err);
+            }
+        }
+
+        let message = {
+            let mut locked_client_info = client_info.write().unwrap();
+            locked_client_info.streamdecks = clients.get_connected_streamdecks();
+            locked_client_info.create_update_message()
+        };
+        if let Err(err) = connection.send(message) {
+            debug!("Could not report streamdeck presence to server: {}.", err);
+        }
     }
 }
 
-fn find_button_configuration(
-    configuration: &StreamdeckAutomationConfiguration,
-    key: u8,
-) -> Option<&StreamdeckButtonConfiguration> {
-    configuration
-        .button_configurations
-        .iter()
-        .find(|config| config.key == key)
+async fn handle_display_updates(client: SharedStreamdeckAutomationClient) {
+    let mut interval = tokio::time::interval(DISPLAY_UPDATE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut locked_client = client.lock().unwrap();
+        if let Err(err) = locked_client.update_display() {
+            error!("Could not update streamdeck display: {}.", err);
+        }
+        if let Err(err) = locked_client.update_live_keys() {
+            error!("Could not update live keys: {}.", err);
+        }
+    }
 }
 
-async fn handle_button_events(
-    mut receiver: UnboundedReceiver<ButtonEvent>,
-    sender: UnboundedSender<AutomationMessage>,
-    button_configuration_manager: Arc<
-        RwLock<ConfigurationManager<StreamdeckAutomationConfiguration>>,
-    >,
+async fn handle_render_requests(
+    mut receiver: UnboundedReceiver<RenderRequest>,
+    sender: UnboundedSender<RenderRequest>,
+    client: SharedStreamdeckAutomationClient,
 ) {
-    while let Some(event) = receiver.recv().await {
-        let locked_configuration_manager = button_configuration_manager.read().unwrap();
-        let configuration = locked_configuration_manager.get_configuration();
-        match event {
-            ButtonEvent::ButtonPressed(key) => {
-                if let Some(button_config) = find_button_configuration(configuration, key) {
-                    if let Err(err) = execute_macro(&sender, button_config.press_macro.clone()) {
-                        warn!("Could not execute press macro: {}.", err);
-                        break;
-                    }
+    while let Some(request) = receiver.recv().await {
+        let mut locked_client = client.lock().unwrap();
+        match request {
+            RenderRequest::Refresh => {
+                if let Err(err) = locked_client.fill_streamdeck() {
+                    error!("Could not refresh streamdeck: {}.", err);
                 }
             }
-            ButtonEvent::ButtonReleased(key) => {
-                if let Some(button_config) = find_button_configuration(configuration, key) {
-                    if let Some(release_macro) = &button_config.release_macro {
-                        if let Err(err) = execute_macro(&sender, release_macro.clone()) {
-                            warn!("Could not execute release macro: {}.", err);
-                            break;
-                        }
+            RenderRequest::ShowRejected(key) => {
+                if let Err(err) = locked_client.show_rejected(key) {
+                    error!("Could not show rejected press on key {}: {}.", key, err);
+                }
+                // the regular key is shown again after a moment
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(REJECTED_FEEDBACK_DURATION).await;
+                    if let Err(err) = sender.send(RenderRequest::Refresh) {
+                        error!("Could not request refresh: {}.", err);
                     }
+                });
+            }
+            RenderRequest::LiveKeyGesture {
+                folder_id,
+                gesture_event,
+            } => {
+                if let Err(err) =
+                    locked_client.on_live_key_gesture(folder_id.as_deref(), gesture_event)
+                {
+                    error!("Could not update live key {}: {}.", gesture_event.key, err);
                 }
             }
         }
     }
 }
 
-fn execute_macro(
-    sender: &UnboundedSender<AutomationMessage>,
-    auto_macro: AutomationMacro,
-) -> anyhow::Result<()> {
-    let message = AutomationMessage::ExecuteMacro { mac: auto_macro };
-
-    sender
-        .send(message)
-        .context("Could not send websocket message.")?;
-    Ok(())
-}
-
 fn print_hid_devices(hid_api: &Arc<std::sync::Mutex<HidApi>>) -> anyhow::Result<()> {
     let mut locked_hid = hid_api
         .lock()
@@ -1,17 +1,51 @@
-use crate::automodule::streamdeck::StreamdeckDevicesConfiguration;
+use schemars::JsonSchema;
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+use crate::automodule::streamdeck::{StreamdeckDevicesConfiguration, StreamdeckDisplayCommand};
+use crate::state::AutomationState;
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
 #[serde(tag = "tag", content = "payload")]
 pub enum AutomationAction {
     PlaySound,
     /* Can be sent to instruct the server to reload a certain streamdeck's configuration and send it to the connected streamdeck. */
     StreamdeckClientReloadDeviceConfiguration,
+    /* Sets the brightness of, wakes or sleeps the streamdeck with the given device id. */
+    #[serde(rename_all = "camelCase")]
+    StreamdeckClientControlDisplay {
+        device_id: String,
+        command: StreamdeckDisplayCommand,
+    },
+    /* Sets the server mode (e.g. guest or night) which can activate streamdeck layouts, no mode clears it. */
+    SetServerMode {
+        mode: Option<String>,
+    },
+    /* Shows the layout on the streamdeck instead of the automatically selected one, no layout id returns to the automatic selection. */
+    #[serde(rename_all = "camelCase")]
+    StreamdeckClientSelectLayout {
+        device_id: String,
+        layout_id: Option<String>,
+    },
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 #[serde(tag = "tag", content = "payload")]
 pub enum AutomationStatusUpdate {
     // TODO remove
-    SoundPlayed { sound: String },
+    SoundPlayed {
+        sound: String,
+    },
     StreamdeckClientReloadedDevicesConfiguration(StreamdeckDevicesConfiguration),
+    #[serde(rename_all = "camelCase")]
+    StreamdeckClientDisplayCommand {
+        device_id: String,
+        command: StreamdeckDisplayCommand,
+    },
+    /* Sent with all known states when a client connects and with the changed states afterwards. */
+    StatesChanged {
+        states: Vec<AutomationState>,
+    },
+    /* Sent when a module applied a changed configuration file, clients showing data of the module should fetch it again. */
+    ConfigurationReloaded {
+        module: String,
+    },
 }
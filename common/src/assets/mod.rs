@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+
+const SUPPORTED_IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+/// Asset names are plain file names with a supported image extension, they must not contain any path components.
+pub fn validate_asset_name(name: &str) -> anyhow::Result<()> {
+    let is_plain_file_name = !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if name.is_empty() || !is_plain_file_name {
+        return Err(anyhow!("Asset name {} is not a valid file name.", name));
+    }
+
+    let extension = get_asset_extension(name)
+        .ok_or_else(|| anyhow!("Asset {} does not have a file extension.", name))?;
+    if !SUPPORTED_IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        return Err(anyhow!(
+            "Asset {} does not have a supported file type.",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the lowercase file extension of an asset name.
+pub fn get_asset_extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_asset_names() {
+        assert!(validate_asset_name("light.png").is_ok());
+        assert!(validate_asset_name("Light_On-2.JPG").is_ok());
+        assert!(validate_asset_name("light.gif").is_err());
+        assert!(validate_asset_name("light").is_err());
+        assert!(validate_asset_name("../light.png").is_err());
+        assert!(validate_asset_name("icons/light.png").is_err());
+        assert!(validate_asset_name(".png").is_err());
+        assert!(validate_asset_name("").is_err());
+    }
+}
@@ -1,6 +1,8 @@
+use schemars::JsonSchema;
+
 use crate::action::AutomationAction;
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
 pub struct AutomationMacro {
     name: String,
     pub actions: Vec<AutomationAction>,
@@ -10,4 +12,8 @@ impl AutomationMacro {
     pub fn new(name: String, actions: Vec<AutomationAction>) -> AutomationMacro {
         AutomationMacro { name, actions }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
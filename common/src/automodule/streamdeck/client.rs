@@ -0,0 +1,64 @@
+use schemars::JsonSchema;
+
+use crate::config::{Secret, Secrets, VersionedConfiguration};
+
+/* Configuration of a streamdeck client, the buttons of its decks are configured separately. */
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckClientConfiguration {
+    pub server_ip: String,
+    pub server_port: u32,
+    /* used for the only attached deck if no streamdecks are configured */
+    pub device_id: String,
+    /* identifies the client at the server, which rejects clients without a configured token */
+    #[serde(default)]
+    pub token: Secret,
+    /* maps the decks attached to this host to devices on the server */
+    #[serde(default)]
+    pub streamdecks: Vec<StreamdeckMapping>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckMapping {
+    /* HID serial number of the deck */
+    pub serial: String,
+    pub device_id: String,
+}
+
+impl VersionedConfiguration for StreamdeckClientConfiguration {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl StreamdeckClientConfiguration {
+    /// Returns the server device id of the deck with the given serial or None if the deck should not be used.
+    pub fn get_device_id(&self, serial: Option<&str>) -> Option<String> {
+        if self.streamdecks.is_empty() {
+            return Some(self.device_id.clone());
+        }
+        self.streamdecks
+            .iter()
+            .find(|mapping| serial.is_some_and(|serial| mapping.serial.eq(serial)))
+            .map(|mapping| mapping.device_id.clone())
+    }
+
+    /// Returns the token which is passed to the server, None if no token is configured.
+    pub fn resolve_token(&self, secrets: &Secrets) -> anyhow::Result<Option<String>> {
+        let mut token = self.token.clone();
+        token.resolve(secrets)?;
+        let token = token.expose()?;
+        Ok((!token.is_empty()).then(|| token.to_owned()))
+    }
+}
+
+impl Default for StreamdeckClientConfiguration {
+    fn default() -> Self {
+        StreamdeckClientConfiguration {
+            server_ip: "127.0.0.1".to_owned(),
+            server_port: 80,
+            device_id: String::from("default_device_id"),
+            token: Secret::default(),
+            streamdecks: Vec::new(),
+        }
+    }
+}
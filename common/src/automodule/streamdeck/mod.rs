@@ -1,30 +1,820 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use chrono::NaiveTime;
+use schemars::JsonSchema;
+
 use crate::automacro::AutomationMacro;
+use crate::config::VersionedConfiguration;
+use crate::state::AutomationStateValue;
+
+pub use self::client::{StreamdeckClientConfiguration, StreamdeckMapping};
+
+mod client;
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct StreamdeckDevicesConfiguration {
     pub devices: Vec<StreamdeckDeviceConfiguration>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+impl VersionedConfiguration for StreamdeckDevicesConfiguration {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl StreamdeckDevicesConfiguration {
+    /// The model of a device is only known to its client, so the keys are checked against the largest model.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut device_ids = HashSet::new();
+        for device in &self.devices {
+            if !device_ids.insert(device.device_id.as_str()) {
+                return Err(anyhow!(
+                    "Device {} is configured more than once.",
+                    device.device_id
+                ));
+            }
+            device
+                .configuration
+                .validate(StreamdeckModel::Xl)
+                .map_err(|err| anyhow!("Invalid device {}: {}", device.device_id, err))?;
+            let mut layout_ids = HashSet::new();
+            for layout in &device.layouts {
+                if !layout_ids.insert(layout.id.as_str()) {
+                    return Err(anyhow!(
+                        "Layout {} of device {} is configured more than once.",
+                        layout.id,
+                        device.device_id
+                    ));
+                }
+                for rule in &layout.rules {
+                    rule.get_time_range().map_err(|err| {
+                        anyhow!(
+                            "Invalid rule of layout {} of device {}: {}",
+                            layout.id,
+                            device.device_id,
+                            err
+                        )
+                    })?;
+                }
+                layout
+                    .configuration
+                    .validate(StreamdeckModel::Xl)
+                    .map_err(|err| {
+                        anyhow!(
+                            "Invalid layout {} of device {}: {}",
+                            layout.id,
+                            device.device_id,
+                            err
+                        )
+                    })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct StreamdeckDeviceConfiguration {
     pub device_id: String,
     pub configuration: StreamdeckAutomationConfiguration,
+    /* Layouts which replace the configuration while one of their rules matches, the first active layout is shown. */
+    #[serde(default)]
+    pub layouts: Vec<StreamdeckLayout>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, Eq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckLayout {
+    pub id: String,
+    /* The layout is active if any of its rules matches, layouts without rules are only shown when selected manually. */
+    #[serde(default)]
+    pub rules: Vec<StreamdeckLayoutRule>,
+    /* The device name of the device configuration is kept. */
+    pub configuration: StreamdeckAutomationConfiguration,
+}
+
+/* A rule matches if all of its conditions match. */
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckLayoutRule {
+    /* Start of the time of day as HH:MM, the range can wrap around midnight, e.g. from 22:00 to 06:00. */
+    pub from: Option<String>,
+    /* End of the time of day as HH:MM (exclusive). */
+    pub to: Option<String>,
+    /* The rule matches on all weekdays if none are given. */
+    #[serde(default)]
+    pub weekdays: Vec<StreamdeckWeekday>,
+    /* Server mode which has to be set, e.g. guest or night. */
+    pub mode: Option<String>,
+}
+
+impl StreamdeckLayoutRule {
+    /// The parsed start and end of the time of day, unset times are None.
+    pub fn get_time_range(&self) -> anyhow::Result<(Option<NaiveTime>, Option<NaiveTime>)> {
+        Ok((parse_time_of_day(&self.from)?, parse_time_of_day(&self.to)?))
+    }
+}
+
+fn parse_time_of_day(time: &Option<String>) -> anyhow::Result<Option<NaiveTime>> {
+    time.as_ref()
+        .map(|time| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|err| anyhow!("Invalid time of day {}: {}", time, err))
+        })
+        .transpose()
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamdeckWeekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Default, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct StreamdeckAutomationConfiguration {
+    #[serde(default)]
     pub device_name: String,
+    /* Default style for all buttons of the device. */
+    pub style: Option<StreamdeckKeyStyle>,
+    /* Default gesture timing for all buttons of the device. */
+    pub gesture_timing: Option<StreamdeckGestureTiming>,
+    /* Brightness, dimming and screensaver of the device. */
+    pub display: Option<StreamdeckDisplaySettings>,
+    pub button_configurations: Vec<StreamdeckButtonConfiguration>,
+    /* Folders can be opened with buttons, the buttons of the open folder replace all buttons of the device. */
+    #[serde(default)]
+    pub folders: Vec<StreamdeckFolder>,
+}
+
+impl VersionedConfiguration for StreamdeckAutomationConfiguration {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl StreamdeckAutomationConfiguration {
+    /// Checks that all configured keys exist on the given model, that no key is configured twice
+    /// and that all opened folders exist.
+    pub fn validate(&self, model: StreamdeckModel) -> anyhow::Result<()> {
+        validate_buttons(&self.button_configurations, model)?;
+        let mut folder_ids = HashSet::new();
+        for folder in &self.folders {
+            if !folder_ids.insert(folder.id.as_str()) {
+                return Err(anyhow!(
+                    "Folder {} is configured more than once.",
+                    folder.id
+                ));
+            }
+            validate_buttons(&folder.button_configurations, model)
+                .map_err(|err| anyhow!("Invalid folder {}: {}", folder.id, err))?;
+        }
+
+        let opened_folders = self
+            .all_button_configurations()
+            .filter_map(
+                |button_configuration| match &button_configuration.folder_action {
+                    Some(StreamdeckFolderAction::Open { folder_id }) => Some(folder_id),
+                    _ => None,
+                },
+            );
+        for folder_id in opened_folders {
+            if !folder_ids.contains(folder_id.as_str()) {
+                return Err(anyhow!("Opened folder {} does not exist.", folder_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the buttons of the given folder or of the device itself if no folder is given.
+    pub fn get_button_configurations(
+        &self,
+        folder_id: Option<&str>,
+    ) -> &[StreamdeckButtonConfiguration] {
+        match folder_id {
+            Some(folder_id) => self
+                .folders
+                .iter()
+                .find(|folder| folder.id.eq(folder_id))
+                .map(|folder| folder.button_configurations.as_slice())
+                .unwrap_or_default(),
+            None => &self.button_configurations,
+        }
+    }
+
+    pub fn find_button_configuration(
+        &self,
+        folder_id: Option<&str>,
+        key: u8,
+    ) -> Option<&StreamdeckButtonConfiguration> {
+        self.get_button_configurations(folder_id)
+            .iter()
+            .find(|button_configuration| button_configuration.key == key)
+    }
+
+    /// The buttons of the device and of all folders.
+    pub fn all_button_configurations(
+        &self,
+    ) -> impl Iterator<Item = &StreamdeckButtonConfiguration> {
+        self.button_configurations.iter().chain(
+            self.folders
+                .iter()
+                .flat_map(|folder| folder.button_configurations.iter()),
+        )
+    }
+
+    pub fn has_folder(&self, folder_id: &str) -> bool {
+        self.folders.iter().any(|folder| folder.id.eq(folder_id))
+    }
+}
+
+fn validate_buttons(
+    button_configurations: &[StreamdeckButtonConfiguration],
+    model: StreamdeckModel,
+) -> anyhow::Result<()> {
+    let mut configured_keys = HashSet::new();
+    for button_configuration in button_configurations {
+        if button_configuration.key >= model.key_count() {
+            return Err(anyhow!(
+                "Key {} does not exist on a {:?} streamdeck with {} keys.",
+                button_configuration.key,
+                model,
+                model.key_count()
+            ));
+        }
+        if !configured_keys.insert(button_configuration.key) {
+            return Err(anyhow!(
+                "Key {} is configured more than once.",
+                button_configuration.key
+            ));
+        }
+        if let Some(StreamdeckLiveKey::Timer {
+            duration_seconds: 0,
+        }) = button_configuration.live_key
+        {
+            return Err(anyhow!(
+                "Timer on key {} has no duration.",
+                button_configuration.key
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckFolder {
+    pub id: String,
+    pub name: String,
     pub button_configurations: Vec<StreamdeckButtonConfiguration>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+/* Navigation between folders, handled on the device without executing any macro. */
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "tag", content = "payload")]
+pub enum StreamdeckFolderAction {
+    #[serde(rename_all = "camelCase")]
+    Open {
+        folder_id: String,
+    },
+    /* Goes back to the folder which was open before. */
+    Back,
+}
+
+/* The value of a live key is reported to the server as the state streamdeck.<device id>.key.<key>,
+ * or streamdeck.<device id>.folder.<folder id>.key.<key> for buttons in a folder. */
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "tag", content = "payload")]
+pub enum StreamdeckLiveKey {
+    /* Shows the current time in a strftime format, %H:%M if no format is given. */
+    Clock {
+        format: Option<String>,
+    },
+    /* Counts down, a press starts or pauses the timer and a long press resets it. */
+    #[serde(rename_all = "camelCase")]
+    Timer {
+        duration_seconds: u64,
+    },
+    /* A press starts or pauses the stopwatch and a long press resets it. */
+    Stopwatch,
+    /* A press increments the counter and a long press resets it. */
+    Counter,
+}
+
+impl StreamdeckLiveKey {
+    /// Whether presses on the key are handled by the live key instead of executing the press macro.
+    pub fn handles_presses(&self) -> bool {
+        !matches!(self, StreamdeckLiveKey::Clock { .. })
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckDisplaySettings {
+    /* Brightness in percent while the device is in use. */
+    pub brightness: Option<u8>,
+    /* The device is dimmed after it has not been used for this many minutes. */
+    pub dim_after_minutes: Option<u32>,
+    /* Brightness in percent while the device is dimmed or shows the screensaver. */
+    pub dimmed_brightness: Option<u8>,
+    /* The screensaver is shown after the device has not been used for this many minutes. */
+    pub screensaver_after_minutes: Option<u32>,
+    pub screensaver: Option<StreamdeckScreensaver>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamdeckOfflineAction {
+    /* The macro is dropped and the button shows that it could not be executed. */
+    #[default]
+    Reject,
+    /* The macro is sent once the server is reachable again. */
+    Queue,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamdeckScreensaver {
+    #[default]
+    Blank,
+    Clock,
+}
+
+/* Can be sent by the server to control the display of a streamdeck. */
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "tag", content = "payload")]
+pub enum StreamdeckDisplayCommand {
+    /* Sets the brightness in percent which is used while the device is in use. */
+    SetBrightness { brightness: u8 },
+    Wake,
+    /* Shows the screensaver until the device is used or woken up again. */
+    Sleep,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamdeckModel {
+    Original,
+    OriginalV2,
+    Mini,
+    Xl,
+    Mk2,
+}
+
+impl StreamdeckModel {
+    pub fn key_count(&self) -> u8 {
+        self.columns() * self.rows()
+    }
+
+    pub fn columns(&self) -> u8 {
+        match self {
+            StreamdeckModel::Mini => 3,
+            StreamdeckModel::Original | StreamdeckModel::OriginalV2 | StreamdeckModel::Mk2 => 5,
+            StreamdeckModel::Xl => 8,
+        }
+    }
+
+    pub fn rows(&self) -> u8 {
+        match self {
+            StreamdeckModel::Mini => 2,
+            StreamdeckModel::Original | StreamdeckModel::OriginalV2 | StreamdeckModel::Mk2 => 3,
+            StreamdeckModel::Xl => 4,
+        }
+    }
+
+    /// Keys are numbered from left to right and top to bottom, starting at 0.
+    pub fn key_index(&self, column: u8, row: u8) -> Option<u8> {
+        if column >= self.columns() || row >= self.rows() {
+            return None;
+        }
+        Some(row * self.columns() + column)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct StreamdeckButtonConfiguration {
     pub key: u8,
     pub text: String,
+    /* Name of an image asset (PNG or JPEG) in the server asset store which is drawn behind the text. */
+    pub image: Option<String>,
+    pub style: Option<StreamdeckKeyStyle>,
+    /* Binds the button to a server side state, the first matching state overrides text, image and style. */
+    pub state_binding: Option<StreamdeckButtonStateBinding>,
+    /* Executed on a short press. If no long press or double press macro is set it is executed immediately when the button is pressed. */
     pub press_macro: AutomationMacro,
     pub release_macro: Option<AutomationMacro>,
+    pub long_press_macro: Option<AutomationMacro>,
+    pub double_press_macro: Option<AutomationMacro>,
+    /* Executed repeatedly while the button is held down. */
+    pub repeat_macro: Option<AutomationMacro>,
+    pub gesture_timing: Option<StreamdeckGestureTiming>,
+    /* What happens to macros which are triggered while the server is not reachable. */
+    pub offline_action: Option<StreamdeckOfflineAction>,
+    /* Queued macros are discarded if the server is not reachable again within this many seconds. */
+    pub offline_queue_seconds: Option<u64>,
+    /* Opens or closes a folder when the button is pressed instead of executing the press macro. */
+    pub folder_action: Option<StreamdeckFolderAction>,
+    /* Shows a value which is kept up to date by the device itself instead of the button text. */
+    pub live_key: Option<StreamdeckLiveKey>,
+}
+
+impl StreamdeckButtonConfiguration {
+    /// All image assets which can be displayed on this button.
+    pub fn images(&self) -> Vec<&String> {
+        let mut images: Vec<&String> = self.image.iter().collect();
+        if let Some(state_binding) = &self.state_binding {
+            images.extend(
+                state_binding
+                    .states
+                    .iter()
+                    .filter_map(|state| state.image.as_ref()),
+            );
+        }
+        images
+    }
+
+    pub fn find_state(
+        &self,
+        value: Option<&AutomationStateValue>,
+    ) -> Option<&StreamdeckButtonState> {
+        let state_binding = self.state_binding.as_ref()?;
+        let value = value?;
+        state_binding
+            .states
+            .iter()
+            .find(|state| state.matches(value))
+    }
+}
+
+/* Durations in milliseconds used to detect button gestures, unset values are taken from the device or the defaults. */
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckGestureTiming {
+    pub long_press_millis: Option<u64>,
+    pub double_press_millis: Option<u64>,
+    pub repeat_delay_millis: Option<u64>,
+    pub repeat_interval_millis: Option<u64>,
+}
+
+impl StreamdeckGestureTiming {
+    /// Returns a timing with the values of this timing, using the values of the fallback where they are not set.
+    pub fn merge(&self, fallback: &StreamdeckGestureTiming) -> StreamdeckGestureTiming {
+        StreamdeckGestureTiming {
+            long_press_millis: self.long_press_millis.or(fallback.long_press_millis),
+            double_press_millis: self.double_press_millis.or(fallback.double_press_millis),
+            repeat_delay_millis: self.repeat_delay_millis.or(fallback.repeat_delay_millis),
+            repeat_interval_millis: self
+                .repeat_interval_millis
+                .or(fallback.repeat_interval_millis),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckButtonStateBinding {
+    pub state_id: String,
+    pub states: Vec<StreamdeckButtonState>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckButtonState {
+    /* The state matches if the value is equal. Numeric values can be matched with min and max (inclusive) instead. */
+    pub value: Option<AutomationStateValue>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub text: Option<String>,
+    pub image: Option<String>,
+    pub style: Option<StreamdeckKeyStyle>,
+}
+
+impl StreamdeckButtonState {
+    pub fn matches(&self, value: &AutomationStateValue) -> bool {
+        if let Some(expected_value) = &self.value {
+            return expected_value.eq(value);
+        }
+        match value {
+            AutomationStateValue::Number(number) => {
+                self.min.is_none_or(|min| *number >= min)
+                    && self.max.is_none_or(|max| *number <= max)
+            }
+            _ => false,
+        }
+    }
+}
+
+/* Style of a button, unset values are taken from the next less specific style (state, button, device). */
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckKeyStyle {
+    /* Colours in hex form: RRGGBB */
+    pub foreground_color: Option<String>,
+    pub background_color: Option<String>,
+    /* Maximum font size in pixels, the text is shrunk until it fits on the button. */
+    pub font_size: Option<u8>,
+    pub horizontal_alignment: Option<StreamdeckHorizontalAlignment>,
+    pub vertical_alignment: Option<StreamdeckVerticalAlignment>,
+}
+
+impl StreamdeckKeyStyle {
+    /// Returns a style with the values of this style, using the values of the fallback where they are not set.
+    pub fn merge(&self, fallback: &StreamdeckKeyStyle) -> StreamdeckKeyStyle {
+        StreamdeckKeyStyle {
+            foreground_color: self
+                .foreground_color
+                .clone()
+                .or_else(|| fallback.foreground_color.clone()),
+            background_color: self
+                .background_color
+                .clone()
+                .or_else(|| fallback.background_color.clone()),
+            font_size: self.font_size.or(fallback.font_size),
+            horizontal_alignment: self
+                .horizontal_alignment
+                .clone()
+                .or_else(|| fallback.horizontal_alignment.clone()),
+            vertical_alignment: self
+                .vertical_alignment
+                .clone()
+                .or_else(|| fallback.vertical_alignment.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamdeckHorizontalAlignment {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamdeckVerticalAlignment {
+    Top,
+    #[default]
+    Middle,
+    Bottom,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigurationManager;
+
+    use super::*;
+
+    fn state(
+        value: Option<AutomationStateValue>,
+        min: Option<i64>,
+        max: Option<i64>,
+    ) -> StreamdeckButtonState {
+        StreamdeckButtonState {
+            value,
+            min,
+            max,
+            text: None,
+            image: None,
+            style: None,
+        }
+    }
+
+    #[test]
+    fn test_match_state_value() {
+        let on_state = state(Some(AutomationStateValue::Bool(true)), None, None);
+        assert!(on_state.matches(&AutomationStateValue::Bool(true)));
+        assert!(!on_state.matches(&AutomationStateValue::Bool(false)));
+        assert!(!on_state.matches(&AutomationStateValue::Number(1)));
+    }
+
+    #[test]
+    fn test_match_state_range() {
+        let dimmed_state = state(None, Some(1), Some(50));
+        assert!(dimmed_state.matches(&AutomationStateValue::Number(1)));
+        assert!(dimmed_state.matches(&AutomationStateValue::Number(50)));
+        assert!(!dimmed_state.matches(&AutomationStateValue::Number(0)));
+        assert!(!dimmed_state.matches(&AutomationStateValue::Number(51)));
+        assert!(!dimmed_state.matches(&AutomationStateValue::Bool(true)));
+
+        let bright_state = state(None, Some(51), None);
+        assert!(bright_state.matches(&AutomationStateValue::Number(100)));
+    }
+
+    fn button(key: u8) -> StreamdeckButtonConfiguration {
+        StreamdeckButtonConfiguration {
+            key,
+            text: format!("Key {}", key),
+            image: None,
+            style: None,
+            state_binding: None,
+            press_macro: AutomationMacro::new("test".to_owned(), vec![]),
+            release_macro: None,
+            long_press_macro: None,
+            double_press_macro: None,
+            repeat_macro: None,
+            gesture_timing: None,
+            offline_action: None,
+            offline_queue_seconds: None,
+            folder_action: None,
+            live_key: None,
+        }
+    }
+
+    fn configuration(keys: &[u8]) -> StreamdeckAutomationConfiguration {
+        StreamdeckAutomationConfiguration {
+            button_configurations: keys.iter().map(|key| button(*key)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_store_configuration_in_all_formats() {
+        let path = crate::fs::util::prepare_temp_folder().unwrap();
+        let mut configuration = configuration(&[0, 1]);
+        configuration.button_configurations[1].live_key = Some(StreamdeckLiveKey::Timer {
+            duration_seconds: 60,
+        });
+        configuration.folders.push(StreamdeckFolder {
+            id: "lights".to_owned(),
+            name: "Lights".to_owned(),
+            button_configurations: vec![button(2)],
+        });
+
+        for file_name in ["buttons.json", "buttons.toml", "buttons.yaml"] {
+            let mut manager =
+                ConfigurationManager::<StreamdeckAutomationConfiguration>::load(&path, file_name)
+                    .unwrap();
+            manager.set_configuration(configuration.clone());
+            manager.persist_configuration().unwrap();
+
+            let manager =
+                ConfigurationManager::<StreamdeckAutomationConfiguration>::load(&path, file_name)
+                    .unwrap();
+            assert_eq!(&configuration, manager.get_configuration());
+            std::fs::remove_file(path.join(file_name)).unwrap();
+        }
+
+        crate::fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_model_layout() {
+        assert_eq!(6, StreamdeckModel::Mini.key_count());
+        assert_eq!(15, StreamdeckModel::Mk2.key_count());
+        assert_eq!(32, StreamdeckModel::Xl.key_count());
+        assert_eq!(Some(7), StreamdeckModel::Original.key_index(2, 1));
+        assert_eq!(None, StreamdeckModel::Mini.key_index(3, 0));
+    }
+
+    #[test]
+    fn test_validate_configuration_against_model() {
+        assert!(configuration(&[0, 5, 14])
+            .validate(StreamdeckModel::Original)
+            .is_ok());
+        assert!(configuration(&[0, 5, 14])
+            .validate(StreamdeckModel::Mini)
+            .is_err());
+        assert!(configuration(&[0, 31])
+            .validate(StreamdeckModel::Xl)
+            .is_ok());
+        assert!(configuration(&[1, 1])
+            .validate(StreamdeckModel::Original)
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate_live_keys() {
+        let mut timer_button = button(2);
+        timer_button.live_key = Some(StreamdeckLiveKey::Timer {
+            duration_seconds: 0,
+        });
+        let mut configuration = configuration(&[0]);
+        configuration.button_configurations.push(timer_button);
+        assert!(configuration.validate(StreamdeckModel::Original).is_err());
+
+        configuration.button_configurations[1].live_key = Some(StreamdeckLiveKey::Timer {
+            duration_seconds: 300,
+        });
+        assert!(configuration.validate(StreamdeckModel::Original).is_ok());
+
+        let live_key: StreamdeckLiveKey =
+            serde_json::from_str(r#"{"tag":"Timer","payload":{"durationSeconds":300}}"#).unwrap();
+        assert!(live_key.handles_presses());
+        let live_key: StreamdeckLiveKey =
+            serde_json::from_str(r#"{"tag":"Clock","payload":{"format":"%H:%M:%S"}}"#).unwrap();
+        assert!(!live_key.handles_presses());
+    }
+
+    #[test]
+    fn test_validate_folders() {
+        let mut folder_button = button(3);
+        folder_button.folder_action = Some(StreamdeckFolderAction::Open {
+            folder_id: "lights".to_owned(),
+        });
+        let mut configuration = configuration(&[0]);
+        configuration.button_configurations.push(folder_button);
+        assert!(configuration.validate(StreamdeckModel::Original).is_err());
+
+        let mut back_button = button(0);
+        back_button.folder_action = Some(StreamdeckFolderAction::Back);
+        configuration.folders.push(StreamdeckFolder {
+            id: "lights".to_owned(),
+            name: "Lights".to_owned(),
+            button_configurations: vec![back_button, button(7)],
+        });
+        assert!(configuration.validate(StreamdeckModel::Original).is_ok());
+        assert!(configuration.validate(StreamdeckModel::Mini).is_err());
+        assert_eq!(
+            2,
+            configuration
+                .get_button_configurations(Some("lights"))
+                .len()
+        );
+        assert_eq!(
+            Some(7),
+            configuration
+                .find_button_configuration(Some("lights"), 7)
+                .map(|button_configuration| button_configuration.key)
+        );
+        assert!(configuration
+            .get_button_configurations(Some("unknown"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_merge_styles() {
+        let button_style = StreamdeckKeyStyle {
+            foreground_color: Some("FFFFFF".to_owned()),
+            font_size: Some(14),
+            ..Default::default()
+        };
+        let device_style = StreamdeckKeyStyle {
+            foreground_color: Some("000000".to_owned()),
+            background_color: Some("1B5B88".to_owned()),
+            vertical_alignment: Some(StreamdeckVerticalAlignment::Top),
+            ..Default::default()
+        };
+
+        let style = button_style.merge(&device_style);
+
+        assert_eq!(Some("FFFFFF".to_owned()), style.foreground_color);
+        assert_eq!(Some("1B5B88".to_owned()), style.background_color);
+        assert_eq!(Some(14), style.font_size);
+        assert_eq!(None, style.horizontal_alignment);
+        assert_eq!(
+            Some(StreamdeckVerticalAlignment::Top),
+            style.vertical_alignment
+        );
+    }
+
+    #[test]
+    fn test_deserialize_button_without_state_binding() {
+        let button: StreamdeckButtonConfiguration = serde_json::from_str(
+            r#"{"key":1,"text":"Light","pressMacro":{"name":"light","actions":[]}}"#,
+        )
+        .unwrap();
+        assert_eq!(None, button.state_binding);
+        assert_eq!(None, button.long_press_macro);
+        assert_eq!(None, button.double_press_macro);
+        assert_eq!(None, button.repeat_macro);
+        assert_eq!(
+            None,
+            button.find_state(Some(&AutomationStateValue::Bool(true)))
+        );
+    }
+
+    #[test]
+    fn test_validate_layouts() {
+        let layout = |id: &str, from: &str| StreamdeckLayout {
+            id: id.to_owned(),
+            rules: vec![StreamdeckLayoutRule {
+                from: Some(from.to_owned()),
+                to: Some("06:00".to_owned()),
+                ..Default::default()
+            }],
+            configuration: configuration(&[0]),
+        };
+        let mut devices_configuration = StreamdeckDevicesConfiguration {
+            devices: vec![StreamdeckDeviceConfiguration {
+                device_id: "desk".to_owned(),
+                configuration: configuration(&[0]),
+                layouts: vec![layout("night", "22:00"), layout("evening", "18:30")],
+            }],
+        };
+        assert!(devices_configuration.validate().is_ok());
+
+        devices_configuration.devices[0].layouts[1] = layout("evening", "25:00");
+        assert!(devices_configuration.validate().is_err());
+
+        devices_configuration.devices[0].layouts[1] = layout("night", "18:30");
+        assert!(devices_configuration.validate().is_err());
+    }
 }
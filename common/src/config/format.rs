@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde_json::Value;
+
+/// All extensions which are recognized, JSON files are preferred.
+pub(super) const FILE_EXTENSIONS: [&str; 4] = ["json", "toml", "yaml", "yml"];
+
+/// The file format of a configuration, which is chosen by the file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigurationFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigurationFormat {
+    /// Files without a known extension are JSON files.
+    pub fn from_path(path: &Path) -> ConfigurationFormat {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ConfigurationFormat::from_extension)
+            .unwrap_or(ConfigurationFormat::Json)
+    }
+
+    pub fn from_extension(extension: &str) -> Option<ConfigurationFormat> {
+        match extension.to_lowercase().as_str() {
+            "json" => Some(ConfigurationFormat::Json),
+            "toml" => Some(ConfigurationFormat::Toml),
+            "yaml" | "yml" => Some(ConfigurationFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            ConfigurationFormat::Json => "json",
+            ConfigurationFormat::Toml => "toml",
+            ConfigurationFormat::Yaml => "yaml",
+        }
+    }
+
+    pub(super) fn parse(&self, content: &str) -> anyhow::Result<Value> {
+        match self {
+            ConfigurationFormat::Json => {
+                serde_json::from_str(content).map_err(|err| anyhow!("Invalid JSON: {}", err))
+            }
+            ConfigurationFormat::Toml => {
+                toml::from_str(content).map_err(|err| anyhow!("Invalid TOML: {}", err))
+            }
+            ConfigurationFormat::Yaml => {
+                serde_yaml::from_str(content).map_err(|err| anyhow!("Invalid YAML: {}", err))
+            }
+        }
+    }
+
+    /// Editors read the schema of TOML and YAML files from a comment in the first line.
+    pub(super) fn get_schema_comment(&self, schema_reference: &str) -> Option<String> {
+        match self {
+            ConfigurationFormat::Json => None,
+            ConfigurationFormat::Toml => Some(format!("#:schema {}\n", schema_reference)),
+            ConfigurationFormat::Yaml => Some(format!(
+                "# yaml-language-server: $schema={}\n",
+                schema_reference
+            )),
+        }
+    }
+
+    pub(super) fn serialize<T>(&self, value: &T) -> anyhow::Result<String>
+    where
+        T: serde::Serialize,
+    {
+        match self {
+            ConfigurationFormat::Json => serde_json::to_string_pretty(value)
+                .map_err(|err| anyhow!("Could not serialize JSON: {}", err)),
+            ConfigurationFormat::Toml => toml::to_string_pretty(value)
+                .map_err(|err| anyhow!("Could not serialize TOML: {}", err)),
+            ConfigurationFormat::Yaml => serde_yaml::to_string(value)
+                .map_err(|err| anyhow!("Could not serialize YAML: {}", err)),
+        }
+    }
+}
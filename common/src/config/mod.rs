@@ -1,5 +1,26 @@
-use std::io::Read;
+use std::ffi::OsString;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+
+pub use crate::config::format::ConfigurationFormat;
+pub use crate::config::schema::{get_schema, get_schema_file_name, SCHEMA_FOLDER_NAME};
+pub use crate::config::secret::{Secret, SecretSource, Secrets};
+use crate::config::version::VersionedConfigurationFile;
+pub use crate::config::version::{ConfigurationMigration, VersionedConfiguration};
+pub use crate::config::watch::ConfigurationWatcher;
+
+mod format;
+mod schema;
+mod secret;
+mod version;
+mod watch;
+
+const BACKUP_FOLDER_NAME: &str = "backups";
+const BACKUP_COUNT: usize = 5;
 
 pub struct ConfigurationManager<T>
 where
@@ -11,7 +32,11 @@ where
 
 impl<T> ConfigurationManager<T>
 where
-    T: Default + serde::Serialize + serde::de::DeserializeOwned,
+    T: Default
+        + serde::Serialize
+        + serde::de::DeserializeOwned
+        + VersionedConfiguration
+        + JsonSchema,
 {
     pub fn load(
         application_folder: &Path,
@@ -20,6 +45,10 @@ where
         let configuration_loader = ConfigurationLoader::new(application_folder, config_file_name);
 
         let configuration = Self::load_configuration(&configuration_loader)?;
+        // the schema only helps editing the file, the configuration can be used without it
+        if let Err(err) = configuration_loader.store_schema::<T>() {
+            warn!("Could not store configuration schema: {}", err);
+        }
         Ok(ConfigurationManager {
             configuration,
             configuration_loader,
@@ -29,7 +58,22 @@ where
     fn load_configuration(configuration_loader: &ConfigurationLoader) -> anyhow::Result<T> {
         let configuration;
         if configuration_loader.config_exists() {
-            configuration = configuration_loader.load_config()?;
+            configuration = match configuration_loader.load_config_with_version() {
+                Ok((configuration, file_version)) => {
+                    // migrated files are written back, the original file is kept as a backup
+                    if file_version != T::SCHEMA_VERSION {
+                        info!(
+                            "Migrating configuration {} from schema version {} to {}.",
+                            configuration_loader.config_file_path.display(),
+                            file_version,
+                            T::SCHEMA_VERSION
+                        );
+                        configuration_loader.store_config(&configuration)?;
+                    }
+                    configuration
+                }
+                Err(err) => Self::recover_configuration(configuration_loader, err)?,
+            };
         } else {
             configuration = T::default();
             configuration_loader.store_config(&configuration)?;
@@ -37,6 +81,96 @@ where
         Ok(configuration)
     }
 
+    /// Falls back to the newest backup which can be loaded and stores it as the configuration again.
+    fn recover_configuration(
+        configuration_loader: &ConfigurationLoader,
+        load_error: anyhow::Error,
+    ) -> anyhow::Result<T> {
+        error!(
+            "Could not load configuration {}: {} Trying to recover it from a backup.",
+            configuration_loader.config_file_path.display(),
+            load_error
+        );
+        for backup_path in configuration_loader.get_backups()?.iter().rev() {
+            match configuration_loader.load_config_from(backup_path) {
+                Ok(configuration) => {
+                    error!(
+                        "RECOVERED configuration {} from backup {}, changes made after the backup are lost.",
+                        configuration_loader.config_file_path.display(),
+                        backup_path.display()
+                    );
+                    configuration_loader.store_config(&configuration)?;
+                    return Ok(configuration);
+                }
+                Err(err) => warn!("Skipping invalid backup: {}", err),
+            }
+        }
+        Err(load_error.context("No valid backup of the configuration was found."))
+    }
+
+    /// Rewrites the stored configuration in another format. The original file is moved to the backups,
+    /// so that only the converted file is loaded afterwards. Returns the path of the converted file.
+    pub fn convert(
+        application_folder: &Path,
+        config_file_name: &str,
+        format: ConfigurationFormat,
+    ) -> anyhow::Result<PathBuf> {
+        let source_loader = ConfigurationLoader::new(application_folder, config_file_name);
+        if !source_loader.config_exists() {
+            return Err(anyhow!(
+                "Configuration {} does not exist.",
+                source_loader.config_file_path.display()
+            ));
+        }
+        if source_loader.format == format {
+            return Err(anyhow!(
+                "Configuration {} is already stored as {}.",
+                source_loader.config_file_path.display(),
+                format.get_extension()
+            ));
+        }
+        let configuration: T = source_loader.load_config()?;
+
+        let target_loader = ConfigurationLoader::with_path(
+            source_loader
+                .config_file_path
+                .with_extension(format.get_extension()),
+            source_loader.backup_folder_path.clone(),
+        );
+        if target_loader.config_exists() {
+            return Err(anyhow!(
+                "Configuration {} already exists.",
+                target_loader.config_file_path.display()
+            ));
+        }
+        target_loader.store_config(&configuration)?;
+
+        let source_content = std::fs::read_to_string(&source_loader.config_file_path)?;
+        source_loader.backup_config(&source_content)?;
+        std::fs::remove_file(&source_loader.config_file_path).with_context(|| {
+            format!(
+                "Could not remove {}.",
+                source_loader.config_file_path.display()
+            )
+        })?;
+        Ok(target_loader.config_file_path)
+    }
+
+    /// Serializes the configuration as JSON with its schema version, independent of the format of the file.
+    pub fn export_configuration(configuration: &T) -> anyhow::Result<String> {
+        ConfigurationFormat::Json.serialize(&VersionedConfigurationFile {
+            schema: None,
+            schema_version: T::SCHEMA_VERSION,
+            configuration,
+        })
+    }
+
+    /// Parses an exported configuration, exports of older schema versions are migrated.
+    pub fn import_configuration(content: &str) -> anyhow::Result<T> {
+        parse_versioned_config(ConfigurationFormat::Json, content)
+            .map(|(configuration, _)| configuration)
+    }
+
     pub fn persist_configuration(&self) -> anyhow::Result<()> {
         self.configuration_loader.store_config(&self.configuration)
     }
@@ -54,43 +188,304 @@ where
     pub fn set_configuration(&mut self, configuration: T) {
         self.configuration = configuration;
     }
+
+    /// Reads the file again and replaces the configuration if the file can be parsed, is valid and differs
+    /// from the current configuration. Returns whether the configuration was replaced.
+    pub fn reload_changed_configuration<V>(&mut self, validate: V) -> anyhow::Result<bool>
+    where
+        V: FnOnce(&T) -> anyhow::Result<()>,
+    {
+        let configuration: T = self.configuration_loader.load_config()?;
+        validate(&configuration)?;
+        if serde_json::to_value(&configuration)? == serde_json::to_value(&self.configuration)? {
+            return Ok(false);
+        }
+        self.configuration = configuration;
+        Ok(true)
+    }
+
+    /// Calls the subscriber when the file was changed on disk and did not change again for the debounce duration.
+    /// This includes changes which were persisted by the manager itself.
+    pub fn watch<F>(
+        &self,
+        debounce: Duration,
+        subscriber: F,
+    ) -> anyhow::Result<ConfigurationWatcher>
+    where
+        F: Fn() + Send + 'static,
+    {
+        watch::watch_file(
+            &self.configuration_loader.config_file_path,
+            debounce,
+            subscriber,
+        )
+    }
 }
 
+/// Writes configuration files atomically and keeps the last versions of every file in the backup folder.
+/// The format of the file is chosen by its extension.
 struct ConfigurationLoader {
     config_file_path: PathBuf,
+    backup_folder_path: PathBuf,
+    format: ConfigurationFormat,
 }
 
 impl ConfigurationLoader {
+    /// Uses an existing file with the same name in another format if the file with the given name does not exist.
     pub fn new(application_folder: &Path, config_file_name: &str) -> ConfigurationLoader {
-        let mut config_file_path = application_folder.to_owned();
-        config_file_path.push(config_file_name);
-        ConfigurationLoader { config_file_path }
+        let mut backup_folder_path = application_folder.to_owned();
+        backup_folder_path.push(BACKUP_FOLDER_NAME);
+        ConfigurationLoader::with_path(
+            find_config_file(application_folder, config_file_name),
+            backup_folder_path,
+        )
+    }
+
+    fn with_path(config_file_path: PathBuf, backup_folder_path: PathBuf) -> ConfigurationLoader {
+        let format = ConfigurationFormat::from_path(&config_file_path);
+        ConfigurationLoader {
+            config_file_path,
+            backup_folder_path,
+            format,
+        }
     }
 
     pub fn load_config<T>(&self) -> anyhow::Result<T>
     where
-        T: serde::de::DeserializeOwned,
+        T: serde::de::DeserializeOwned + VersionedConfiguration,
+    {
+        self.load_config_from(&self.config_file_path)
+    }
+
+    /// Returns the configuration migrated to the current schema version and the version of the file.
+    fn load_config_with_version<T>(&self) -> anyhow::Result<(T, u32)>
+    where
+        T: serde::de::DeserializeOwned + VersionedConfiguration,
     {
-        let mut file = std::fs::File::open(&self.config_file_path)?;
+        self.load_versioned_config_from(&self.config_file_path)
+    }
+
+    fn load_config_from<T>(&self, path: &Path) -> anyhow::Result<T>
+    where
+        T: serde::de::DeserializeOwned + VersionedConfiguration,
+    {
+        self.load_versioned_config_from(path)
+            .map(|(config, _)| config)
+    }
+
+    fn load_versioned_config_from<T>(&self, path: &Path) -> anyhow::Result<(T, u32)>
+    where
+        T: serde::de::DeserializeOwned + VersionedConfiguration,
+    {
+        let mut file = std::fs::File::open(path)
+            .with_context(|| format!("Could not open {}.", path.display()))?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
-        let config = serde_json::from_str(&contents)?;
-        Ok(config)
+        parse_versioned_config(self.format, &contents)
+            .with_context(|| format!("Could not load {}.", path.display()))
     }
 
     pub fn config_exists(&self) -> bool {
         self.config_file_path.exists()
     }
 
+    /// Writes the configuration to a temporary file which replaces the configuration file once it is
+    /// completely on disk, so that a crash never leaves a truncated file behind.
     pub fn store_config<T>(&self, config: &T) -> anyhow::Result<()>
     where
-        T: serde::Serialize,
+        T: serde::Serialize + VersionedConfiguration + JsonSchema,
     {
-        let serialized_data = serde_json::to_string_pretty(config)?;
-        std::fs::write(&self.config_file_path, serialized_data)?;
+        // JSON files reference their schema with a property, the other formats with a comment
+        let schema_reference = schema::get_schema_reference::<T>();
+        let mut serialized_data = self
+            .format
+            .get_schema_comment(&schema_reference)
+            .unwrap_or_default();
+        serialized_data.push_str(&self.format.serialize(&VersionedConfigurationFile {
+            schema: (self.format == ConfigurationFormat::Json).then_some(&schema_reference),
+            schema_version: T::SCHEMA_VERSION,
+            configuration: config,
+        })?);
+        if let Ok(current_data) = std::fs::read_to_string(&self.config_file_path) {
+            if current_data == serialized_data {
+                return Ok(());
+            }
+            self.backup_config(&current_data)?;
+        }
+
+        write_file_atomically(&self.config_file_path, &serialized_data, false)
+    }
+
+    /// Writes the schema which is referenced by the configuration file if it changed.
+    fn store_schema<T>(&self) -> anyhow::Result<()>
+    where
+        T: JsonSchema,
+    {
+        let schema_folder_path = self
+            .config_file_path
+            .with_file_name(schema::SCHEMA_FOLDER_NAME);
+        std::fs::create_dir_all(&schema_folder_path)
+            .context("Could not create configuration schema folder.")?;
+        let schema_path = schema_folder_path.join(get_schema_file_name::<T>());
+        let schema = serde_json::to_string_pretty(&get_schema::<T>())?;
+        if std::fs::read_to_string(&schema_path).ok().as_ref() != Some(&schema) {
+            std::fs::write(&schema_path, schema)
+                .with_context(|| format!("Could not write schema {}.", schema_path.display()))?;
+        }
         Ok(())
     }
+
+    /// Stores the data as the newest backup and removes the oldest backups.
+    fn backup_config(&self, data: &str) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.backup_folder_path)
+            .context("Could not create configuration backup folder.")?;
+        let backup_name = format!(
+            "{}{}",
+            self.get_backup_prefix(),
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let mut backup_path = self.backup_folder_path.join(&backup_name);
+        // backups which are written within the same millisecond get a counter, which sorts after the first one
+        let mut counter = 1;
+        while backup_path.exists() {
+            backup_path = self
+                .backup_folder_path
+                .join(format!("{}-{:03}", backup_name, counter));
+            counter += 1;
+        }
+        std::fs::write(&backup_path, data)
+            .with_context(|| format!("Could not write backup {}.", backup_path.display()))?;
+
+        let backups = self.get_backups()?;
+        for backup in backups
+            .iter()
+            .take(backups.len().saturating_sub(BACKUP_COUNT))
+        {
+            std::fs::remove_file(backup)
+                .with_context(|| format!("Could not remove backup {}.", backup.display()))?;
+        }
+        Ok(())
+    }
+
+    /// The backups of this configuration from the oldest to the newest.
+    fn get_backups(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if !self.backup_folder_path.exists() {
+            return Ok(Vec::new());
+        }
+        let prefix = self.get_backup_prefix();
+        let mut backups = Vec::new();
+        for entry in std::fs::read_dir(&self.backup_folder_path)
+            .context("Could not read configuration backup folder.")?
+        {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                backups.push(entry.path());
+            }
+        }
+        // the timestamps sort in chronological order
+        backups.sort();
+        Ok(backups)
+    }
+
+    fn get_backup_prefix(&self) -> String {
+        let file_name = self
+            .config_file_path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        format!("{}.", file_name)
+    }
+}
+
+fn get_sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Writes the data to a temporary file which replaces the file once it is completely on disk, so that a crash
+/// never leaves a truncated file behind. Private files can only be accessed by their owner.
+fn write_file_atomically(path: &Path, data: &str, private: bool) -> anyhow::Result<()> {
+    let temp_file_path = get_sibling_path(path, ".tmp");
+    let mut temp_file = create_file(&temp_file_path, private)
+        .with_context(|| format!("Could not create {}.", temp_file_path.display()))?;
+    temp_file.write_all(data.as_bytes())?;
+    temp_file
+        .sync_all()
+        .with_context(|| format!("Could not flush {} to disk.", temp_file_path.display()))?;
+    std::fs::rename(&temp_file_path, path)
+        .with_context(|| format!("Could not replace {}.", path.display()))?;
+    sync_folder(path.parent())
+}
+
+#[cfg(unix)]
+fn create_file(path: &Path, private: bool) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    if private {
+        // the permissions only apply to new files, a left over file could be readable by others
+        if let Err(err) = std::fs::remove_file(path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                return Err(err);
+            }
+        }
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+#[cfg(not(unix))]
+fn create_file(path: &Path, _: bool) -> std::io::Result<std::fs::File> {
+    std::fs::File::create(path)
+}
+
+/// Parses the content, migrates it to the current schema version and returns it with the version of the content.
+fn parse_versioned_config<T>(
+    format: ConfigurationFormat,
+    contents: &str,
+) -> anyhow::Result<(T, u32)>
+where
+    T: serde::de::DeserializeOwned + VersionedConfiguration,
+{
+    let content = format.parse(contents)?;
+    let (content, file_version) =
+        version::migrate::<T>(content).context("Could not migrate configuration.")?;
+    let config =
+        serde_json::from_value(content).map_err(|err| anyhow!("Invalid configuration: {}", err))?;
+    Ok((config, file_version))
+}
+
+/// The file with the given name, or the first existing file with the same name and another extension.
+fn find_config_file(application_folder: &Path, config_file_name: &str) -> PathBuf {
+    let mut config_file_path = application_folder.to_owned();
+    config_file_path.push(config_file_name);
+    if config_file_path.exists() {
+        return config_file_path;
+    }
+    format::FILE_EXTENSIONS
+        .iter()
+        .map(|extension| config_file_path.with_extension(extension))
+        .find(|path| path.exists())
+        .unwrap_or(config_file_path)
+}
+
+/// Makes the rename of the configuration file durable.
+#[cfg(unix)]
+fn sync_folder(folder: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(folder) = folder {
+        std::fs::File::open(folder)
+            .and_then(|folder| folder.sync_all())
+            .map_err(|err| anyhow!("Could not flush configuration folder to disk: {}", err))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_folder(_: Option<&Path>) -> anyhow::Result<()> {
+    Ok(())
 }
 
 #[cfg(test)]
@@ -102,7 +497,7 @@ mod tests {
     const DEFAULT_STRING_VALUE: &str = "This is the default value";
     const CONFIG_FILE_NAME: &str = "config.json";
 
-    #[derive(Serialize, Deserialize, Clone)]
+    #[derive(Serialize, Deserialize, JsonSchema, Clone)]
     struct TestConfig {
         string_value: String,
     }
@@ -115,6 +510,43 @@ mod tests {
         }
     }
 
+    impl VersionedConfiguration for TestConfig {
+        const SCHEMA_VERSION: u32 = 1;
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Default)]
+    struct MigratedTestConfig {
+        string_value: String,
+        number_value: u32,
+    }
+
+    impl VersionedConfiguration for MigratedTestConfig {
+        const SCHEMA_VERSION: u32 = 3;
+
+        fn migrations() -> Vec<ConfigurationMigration> {
+            vec![
+                ConfigurationMigration {
+                    from_version: 2,
+                    migrate: |mut content| {
+                        content["number_value"] = serde_json::json!(42);
+                        Ok(content)
+                    },
+                },
+                ConfigurationMigration {
+                    from_version: 1,
+                    migrate: |mut content| {
+                        let value = content
+                            .as_object_mut()
+                            .and_then(|object| object.remove("value"))
+                            .ok_or_else(|| anyhow!("Value is missing."))?;
+                        content["string_value"] = value;
+                        Ok(content)
+                    },
+                },
+            ]
+        }
+    }
+
     #[test]
     fn test_load_config_not_existing() {
         let path = fs::util::prepare_temp_folder().unwrap();
@@ -163,6 +595,286 @@ mod tests {
         fs::util::delete_temp_folder(&path).unwrap();
     }
 
+    #[test]
+    fn test_reload_changed_config() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let mut manager =
+            ConfigurationManager::<TestConfig>::load(&path, CONFIG_FILE_NAME).unwrap();
+        let loader = ConfigurationLoader::new(&path, CONFIG_FILE_NAME);
+        let accept = |_: &TestConfig| Ok(());
+
+        assert!(!manager.reload_changed_configuration(accept).unwrap());
+
+        std::fs::write(&loader.config_file_path, "{").unwrap();
+        assert!(manager.reload_changed_configuration(accept).is_err());
+        let changed_config = TestConfig {
+            string_value: "changed".to_owned(),
+        };
+        loader.store_config(&changed_config).unwrap();
+        assert!(manager
+            .reload_changed_configuration(|_| Err(anyhow::anyhow!("invalid")))
+            .is_err());
+        assert_eq!(DEFAULT_STRING_VALUE, manager.configuration.string_value);
+
+        assert!(manager.reload_changed_configuration(accept).unwrap());
+        assert_eq!("changed", manager.configuration.string_value);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_watch_config() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let manager = ConfigurationManager::<TestConfig>::load(&path, CONFIG_FILE_NAME).unwrap();
+        let (change_sender, change_receiver) = std::sync::mpsc::channel();
+        let _watcher = manager
+            .watch(Duration::from_millis(100), move || {
+                change_sender.send(()).unwrap();
+            })
+            .unwrap();
+
+        // other files in the folder are ignored
+        std::fs::write(path.join("other.json"), "{}").unwrap();
+        assert!(change_receiver
+            .recv_timeout(Duration::from_millis(500))
+            .is_err());
+
+        // several writes are reported once
+        for value in ["first", "second"] {
+            let config = TestConfig {
+                string_value: value.to_owned(),
+            };
+            ConfigurationLoader::new(&path, CONFIG_FILE_NAME)
+                .store_config(&config)
+                .unwrap();
+        }
+        change_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert!(change_receiver
+            .recv_timeout(Duration::from_millis(500))
+            .is_err());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keep_rotating_backups() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let loader = ConfigurationLoader::new(&path, CONFIG_FILE_NAME);
+
+        for index in 0..BACKUP_COUNT + 3 {
+            let config = TestConfig {
+                string_value: format!("Value {}", index),
+            };
+            loader.store_config(&config).unwrap();
+            // storing the same configuration again does not create a backup
+            loader.store_config(&config).unwrap();
+        }
+
+        let backups = loader.get_backups().unwrap();
+        assert_eq!(BACKUP_COUNT, backups.len());
+        let newest_backup: TestConfig = loader.load_config_from(&backups[4]).unwrap();
+        assert_eq!("Value 6", newest_backup.string_value);
+        assert!(!get_sibling_path(&loader.config_file_path, ".tmp").exists());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover_config_from_backup() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let loader = ConfigurationLoader::new(&path, CONFIG_FILE_NAME);
+        for value in ["first", "second"] {
+            let config = TestConfig {
+                string_value: value.to_owned(),
+            };
+            loader.store_config(&config).unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // a truncated file and a broken newer backup
+        std::fs::write(&loader.config_file_path, "{\"string_va").unwrap();
+        std::fs::write(
+            loader
+                .backup_folder_path
+                .join(format!("{}99999999", loader.get_backup_prefix())),
+            "",
+        )
+        .unwrap();
+
+        let manager = ConfigurationManager::<TestConfig>::load(&path, CONFIG_FILE_NAME).unwrap();
+        assert_eq!("first", manager.configuration.string_value);
+        let stored_config: TestConfig = loader.load_config().unwrap();
+        assert_eq!("first", stored_config.string_value);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_config() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let loader = ConfigurationLoader::new(&path, CONFIG_FILE_NAME);
+        let legacy_content = r#"{"value":"legacy"}"#;
+        std::fs::write(&loader.config_file_path, legacy_content).unwrap();
+
+        let manager =
+            ConfigurationManager::<MigratedTestConfig>::load(&path, CONFIG_FILE_NAME).unwrap();
+        assert_eq!("legacy", manager.configuration.string_value);
+        assert_eq!(42, manager.configuration.number_value);
+
+        let stored_content = std::fs::read_to_string(&loader.config_file_path).unwrap();
+        assert!(stored_content.starts_with(
+            "{\n  \"$schema\": \"schemas/MigratedTestConfig.schema.json\",\n  \"schemaVersion\": 3,"
+        ));
+        let backups = loader.get_backups().unwrap();
+        assert_eq!(
+            legacy_content,
+            std::fs::read_to_string(&backups[0]).unwrap()
+        );
+
+        // files of newer versions are not loaded
+        std::fs::write(
+            &loader.config_file_path,
+            r#"{"schemaVersion":4,"string_value":"","number_value":0}"#,
+        )
+        .unwrap();
+        assert!(loader.load_config::<MigratedTestConfig>().is_err());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_convert_config() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let mut manager =
+            ConfigurationManager::<TestConfig>::load(&path, CONFIG_FILE_NAME).unwrap();
+        manager.set_configuration(TestConfig {
+            string_value: "converted".to_owned(),
+        });
+        manager.persist_configuration().unwrap();
+
+        assert!(ConfigurationManager::<TestConfig>::convert(
+            &path,
+            CONFIG_FILE_NAME,
+            ConfigurationFormat::Json
+        )
+        .is_err());
+        let toml_path = ConfigurationManager::<TestConfig>::convert(
+            &path,
+            CONFIG_FILE_NAME,
+            ConfigurationFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(path.join("config.toml"), toml_path);
+        assert!(!path.join(CONFIG_FILE_NAME).exists());
+        assert_eq!(
+            "#:schema schemas/TestConfig.schema.json\nschemaVersion = 1\nstring_value = \"converted\"\n",
+            std::fs::read_to_string(&toml_path).unwrap()
+        );
+
+        // the converted file is found by the original name
+        let manager = ConfigurationManager::<TestConfig>::load(&path, CONFIG_FILE_NAME).unwrap();
+        assert_eq!("converted", manager.configuration.string_value);
+        assert_eq!(
+            ConfigurationFormat::Toml,
+            manager.configuration_loader.format
+        );
+
+        let yaml_path = ConfigurationManager::<TestConfig>::convert(
+            &path,
+            CONFIG_FILE_NAME,
+            ConfigurationFormat::Yaml,
+        )
+        .unwrap();
+        assert_eq!(
+            "# yaml-language-server: $schema=schemas/TestConfig.schema.json\nschemaVersion: 1\nstring_value: converted\n",
+            std::fs::read_to_string(yaml_path).unwrap()
+        );
+        let loader = ConfigurationLoader::new(&path, CONFIG_FILE_NAME);
+        assert_eq!(ConfigurationFormat::Yaml, loader.format);
+        // the default and the converted JSON file and the converted TOML file
+        assert_eq!(
+            3,
+            std::fs::read_dir(&loader.backup_folder_path)
+                .unwrap()
+                .count()
+        );
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_config_formats() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let contents = [
+            ("config.toml", "# comment\nstring_value = \"toml\"\n"),
+            ("config.yml", "# comment\nstring_value: yaml\n"),
+        ];
+        for (file_name, content) in contents {
+            std::fs::write(path.join(file_name), content).unwrap();
+            let loader = ConfigurationLoader::new(&path, file_name);
+            let config: TestConfig = loader.load_config().unwrap();
+            assert_eq!(file_name[7..].replace("yml", "yaml"), config.string_value);
+        }
+        // files without version are not rewritten, which keeps their comments
+        let manager = ConfigurationManager::<TestConfig>::load(&path, "config.toml").unwrap();
+        assert_eq!("toml", manager.configuration.string_value);
+        assert_eq!(
+            contents[0].1,
+            std::fs::read_to_string(path.join("config.toml")).unwrap()
+        );
+
+        std::fs::write(path.join("config.unknown"), "{\"string_value\":\"json\"}").unwrap();
+        let config: TestConfig = ConfigurationLoader::new(&path, "config.unknown")
+            .load_config()
+            .unwrap();
+        assert_eq!("json", config.string_value);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_store_schema() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let manager = ConfigurationManager::<TestConfig>::load(&path, CONFIG_FILE_NAME).unwrap();
+
+        let schema_path = path.join("schemas").join("TestConfig.schema.json");
+        let schema: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(schema_path).unwrap()).unwrap();
+        assert_eq!("TestConfig", schema["title"]);
+        assert_eq!("string", schema["properties"]["string_value"]["type"]);
+
+        // the reference is ignored when loading the file
+        let stored_content =
+            std::fs::read_to_string(&manager.configuration_loader.config_file_path).unwrap();
+        assert!(stored_content.contains("\"$schema\": \"schemas/TestConfig.schema.json\""));
+        let loaded_config: TestConfig = manager.configuration_loader.load_config().unwrap();
+        assert_eq!(DEFAULT_STRING_VALUE, loaded_config.string_value);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_export_and_import_config() {
+        let config = TestConfig {
+            string_value: "exported".to_owned(),
+        };
+        let exported = ConfigurationManager::<TestConfig>::export_configuration(&config).unwrap();
+        assert_eq!(
+            "{\n  \"schemaVersion\": 1,\n  \"string_value\": \"exported\"\n}",
+            exported
+        );
+        let imported = ConfigurationManager::<TestConfig>::import_configuration(&exported).unwrap();
+        assert_eq!("exported", imported.string_value);
+
+        let imported =
+            ConfigurationManager::<MigratedTestConfig>::import_configuration(r#"{"value":"old"}"#)
+                .unwrap();
+        assert_eq!("old", imported.string_value);
+        assert_eq!(42, imported.number_value);
+        assert!(ConfigurationManager::<TestConfig>::import_configuration("{}").is_err());
+    }
+
     #[test]
     fn test_create_config_manager() {
         let path = fs::util::prepare_temp_folder().unwrap();
@@ -0,0 +1,27 @@
+use schemars::JsonSchema;
+
+/// Folder next to the configuration files which contains the schemas they reference.
+pub const SCHEMA_FOLDER_NAME: &str = "schemas";
+
+/// The JSON Schema of a configuration type, editors use it to validate and complete configuration files.
+pub fn get_schema<T>() -> serde_json::Value
+where
+    T: JsonSchema,
+{
+    serde_json::to_value(schemars::schema_for!(T)).unwrap_or_default()
+}
+
+pub fn get_schema_file_name<T>() -> String
+where
+    T: JsonSchema,
+{
+    format!("{}.schema.json", T::schema_name())
+}
+
+/// The reference to the schema relative to the configuration file.
+pub(super) fn get_schema_reference<T>() -> String
+where
+    T: JsonSchema,
+{
+    format!("{}/{}", SCHEMA_FOLDER_NAME, get_schema_file_name::<T>())
+}
@@ -0,0 +1,369 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::format::ConfigurationFormat;
+
+const SECRETS_FILE_NAME: &str = "secrets.json";
+/// Set by systemd for services with LoadCredential= or SetCredential=.
+const CREDENTIALS_DIRECTORY_VAR: &str = "CREDENTIALS_DIRECTORY";
+const REDACTED_VALUE: &str = "<redacted>";
+
+/// Where the value of a secret is read from, plain values are stored in the configuration file itself.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SecretSource {
+    /* Environment variable with the value. */
+    Environment { env: String },
+    /* Entry of the secrets file in the application folder. */
+    SecretsFile { secret: String },
+    /* Name of a systemd credential of the service. */
+    SystemdCredential { credential: String },
+    Plain(String),
+}
+
+/// A credential in a configuration. Its value is never written to the debug output and has to be resolved
+/// with the secrets of the application before it can be used.
+#[derive(Clone)]
+pub struct Secret {
+    source: SecretSource,
+    value: Option<String>,
+}
+
+impl Secret {
+    pub fn new(source: SecretSource) -> Secret {
+        Secret {
+            source,
+            value: None,
+        }
+    }
+
+    pub fn is_plain(&self) -> bool {
+        matches!(self.source, SecretSource::Plain(_))
+    }
+
+    /// Name of the entry in the secrets file if the value is stored there.
+    pub fn get_secrets_file_entry(&self) -> Option<&str> {
+        match &self.source {
+            SecretSource::SecretsFile { secret } => Some(secret),
+            _ => None,
+        }
+    }
+
+    /// Whether the value of a plain secret was replaced by [Secret::redacted].
+    pub fn is_redacted(&self) -> bool {
+        matches!(&self.source, SecretSource::Plain(value) if value == REDACTED_VALUE)
+    }
+
+    /// Reads the value of secrets which are not stored in the configuration file.
+    pub fn resolve(&mut self, secrets: &Secrets) -> anyhow::Result<()> {
+        self.value = match &self.source {
+            SecretSource::Plain(_) => None,
+            SecretSource::Environment { env } => {
+                Some(std::env::var(env).map_err(|err| {
+                    anyhow!("Could not read environment variable {}: {}", env, err)
+                })?)
+            }
+            SecretSource::SecretsFile { secret } => Some(secrets.get_secret(secret)?.to_owned()),
+            SecretSource::SystemdCredential { credential } => {
+                Some(secrets.read_credential(credential)?)
+            }
+        };
+        Ok(())
+    }
+
+    pub fn expose(&self) -> anyhow::Result<&str> {
+        match (&self.source, &self.value) {
+            (SecretSource::Plain(value), _) => Ok(value),
+            (_, Some(value)) => Ok(value),
+            (_, None) => Err(anyhow!("Secret {:?} was not resolved.", self)),
+        }
+    }
+
+    /// The secret without its value, references to the value are kept.
+    pub fn redacted(&self) -> Secret {
+        let source = match &self.source {
+            SecretSource::Plain(_) => SecretSource::Plain(REDACTED_VALUE.to_owned()),
+            source => source.clone(),
+        };
+        Secret::new(source)
+    }
+}
+
+impl Default for Secret {
+    fn default() -> Self {
+        Secret::new(SecretSource::Plain(String::new()))
+    }
+}
+
+/// Secrets are equal if they reference the same value.
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Secret {}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            SecretSource::Environment { env } => write!(f, "Secret(env {})", env),
+            SecretSource::SecretsFile { secret } => write!(f, "Secret(secrets file {})", secret),
+            SecretSource::SystemdCredential { credential } => {
+                write!(f, "Secret(credential {})", credential)
+            }
+            SecretSource::Plain(_) => write!(f, "Secret({})", REDACTED_VALUE),
+        }
+    }
+}
+
+/// Only the source is stored, the resolved value never ends up in a file.
+impl Serialize for Secret {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.source.serialize(serializer)
+    }
+}
+
+impl JsonSchema for Secret {
+    fn schema_name() -> String {
+        SecretSource::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        SecretSource::json_schema(gen)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        SecretSource::deserialize(deserializer).map(Secret::new)
+    }
+}
+
+/// The secrets file of an application and the systemd credentials of the process.
+pub struct Secrets {
+    secrets_file_path: PathBuf,
+    values: HashMap<String, String>,
+    credentials_folder: Option<PathBuf>,
+}
+
+impl Secrets {
+    /// The secrets file is optional, but if it exists it must only be accessible by its owner.
+    pub fn load(application_folder: &Path) -> anyhow::Result<Secrets> {
+        let secrets_file_path = super::find_config_file(application_folder, SECRETS_FILE_NAME);
+        let values = if secrets_file_path.exists() {
+            check_permissions(&secrets_file_path)?;
+            let contents = std::fs::read_to_string(&secrets_file_path)
+                .with_context(|| format!("Could not read {}.", secrets_file_path.display()))?;
+            let content = ConfigurationFormat::from_path(&secrets_file_path)
+                .parse(&contents)
+                .with_context(|| format!("Could not parse {}.", secrets_file_path.display()))?;
+            serde_json::from_value(content).with_context(|| {
+                format!(
+                    "Secrets file {} must only contain text values.",
+                    secrets_file_path.display()
+                )
+            })?
+        } else {
+            HashMap::new()
+        };
+        Ok(Secrets {
+            secrets_file_path,
+            values,
+            credentials_folder: std::env::var_os(CREDENTIALS_DIRECTORY_VAR).map(PathBuf::from),
+        })
+    }
+
+    /// Adds the secret to the secrets file, which is created with permissions 0600 if it does not exist yet.
+    /// Comments of TOML and YAML secrets files are not kept.
+    pub fn store_secret(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        self.values.insert(name.to_owned(), value.to_owned());
+        self.persist()
+    }
+
+    /// Returns false if the secrets file does not contain the secret.
+    pub fn remove_secret(&mut self, name: &str) -> anyhow::Result<bool> {
+        if self.values.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.persist()?;
+        Ok(true)
+    }
+
+    fn persist(&self) -> anyhow::Result<()> {
+        let sorted_values: BTreeMap<_, _> = self.values.iter().collect();
+        let content =
+            ConfigurationFormat::from_path(&self.secrets_file_path).serialize(&sorted_values)?;
+        super::write_file_atomically(&self.secrets_file_path, &content, true)
+    }
+
+    fn get_secret(&self, name: &str) -> anyhow::Result<&str> {
+        self.values.get(name).map(String::as_str).ok_or_else(|| {
+            anyhow!(
+                "Secret {} is missing in {}.",
+                name,
+                self.secrets_file_path.display()
+            )
+        })
+    }
+
+    fn read_credential(&self, name: &str) -> anyhow::Result<String> {
+        let credentials_folder = self.credentials_folder.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Credential {} can not be read because {} is not set.",
+                name,
+                CREDENTIALS_DIRECTORY_VAR
+            )
+        })?;
+        let credential = std::fs::read_to_string(credentials_folder.join(name))
+            .with_context(|| format!("Could not read credential {}.", name))?;
+        Ok(credential.trim_end_matches(['\r', '\n']).to_owned())
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)
+        .with_context(|| format!("Could not read permissions of {}.", path.display()))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(anyhow!(
+            "Secrets file {} can be accessed by other users, its permissions must be 0600.",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct TestConfig {
+        key: Secret,
+    }
+
+    fn secret(json: &str) -> Secret {
+        serde_json::from_str::<TestConfig>(json).unwrap().key
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let config = TestConfig {
+            key: secret(r#"{"key":"plain value"}"#),
+        };
+        assert_eq!("plain value", config.key.expose().unwrap());
+        assert_eq!(
+            "TestConfig { key: Secret(<redacted>) }",
+            format!("{:?}", config)
+        );
+        assert_eq!(
+            r#"{"key":"<redacted>"}"#,
+            serde_json::to_string(&TestConfig {
+                key: config.key.redacted()
+            })
+            .unwrap()
+        );
+        assert!(config.key.redacted().is_redacted());
+        assert!(!config.key.is_redacted());
+
+        let mut env_secret = secret(r#"{"key":{"env":"SECRET_TEST_VALUE"}}"#);
+        assert!(env_secret.expose().is_err());
+        std::env::set_var("SECRET_TEST_VALUE", "from env");
+        env_secret
+            .resolve(&Secrets::load(Path::new("/nonexistent")).unwrap())
+            .unwrap();
+        assert_eq!("from env", env_secret.expose().unwrap());
+        assert_eq!("Secret(env SECRET_TEST_VALUE)", format!("{:?}", env_secret));
+        // the resolved value is not stored
+        assert_eq!(
+            r#"{"env":"SECRET_TEST_VALUE"}"#,
+            serde_json::to_string(&env_secret).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_secrets_file() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let secrets_file_path = path.join("secrets.toml");
+        std::fs::write(&secrets_file_path, "hueApiKey = \"from file\"\n").unwrap();
+        let mut file_secret = secret(r#"{"key":{"secret":"hueApiKey"}}"#);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&secrets_file_path, std::fs::Permissions::from_mode(0o644))
+                .unwrap();
+            assert!(Secrets::load(&path).is_err());
+            std::fs::set_permissions(&secrets_file_path, std::fs::Permissions::from_mode(0o600))
+                .unwrap();
+        }
+
+        let secrets = Secrets::load(&path).unwrap();
+        file_secret.resolve(&secrets).unwrap();
+        assert_eq!("from file", file_secret.expose().unwrap());
+        assert!(secret(r#"{"key":{"secret":"missing"}}"#)
+            .resolve(&secrets)
+            .is_err());
+
+        std::fs::write(path.join("hue-api-key"), "from credential\n").unwrap();
+        let secrets = Secrets {
+            credentials_folder: Some(path.clone()),
+            ..secrets
+        };
+        let mut credential_secret = secret(r#"{"key":{"credential":"hue-api-key"}}"#);
+        credential_secret.resolve(&secrets).unwrap();
+        assert_eq!("from credential", credential_secret.expose().unwrap());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn test_store_secret() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let mut secrets = Secrets::load(&path).unwrap();
+        secrets.store_secret("first", "first value").unwrap();
+        secrets.store_secret("second", "second value").unwrap();
+
+        // the created file passes the permission check
+        let secrets = Secrets::load(&path).unwrap();
+        let mut file_secret = secret(r#"{"key":{"secret":"first"}}"#);
+        file_secret.resolve(&secrets).unwrap();
+        assert_eq!("first value", file_secret.expose().unwrap());
+        assert_eq!("second value", secrets.get_secret("second").unwrap());
+
+        let mut secrets = secrets;
+        assert!(secrets.remove_secret("first").unwrap());
+        assert!(!secrets.remove_secret("first").unwrap());
+        let secrets = Secrets::load(&path).unwrap();
+        assert!(secrets.get_secret("first").is_err());
+        assert_eq!("second value", secrets.get_secret("second").unwrap());
+        assert!(!path.join("secrets.json.tmp").exists());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::anyhow;
+use serde_json::Value;
+
+const SCHEMA_VERSION_KEY: &str = "schemaVersion";
+const SCHEMA_KEY: &str = "$schema";
+/// Files which were written before schema versions were introduced.
+const UNVERSIONED_SCHEMA_VERSION: u32 = 1;
+
+/// A configuration which is stored with its schema version, so that files of older versions can be migrated.
+pub trait VersionedConfiguration {
+    /// The version which is written to the configuration file.
+    const SCHEMA_VERSION: u32;
+
+    /// Registry of the steps which migrate the files of older versions to the current one.
+    fn migrations() -> Vec<ConfigurationMigration> {
+        Vec::new()
+    }
+}
+
+/// Migrates the JSON content of a configuration file from one version to the next.
+pub struct ConfigurationMigration {
+    pub from_version: u32,
+    pub migrate: fn(Value) -> anyhow::Result<Value>,
+}
+
+/// The serialized configuration, which is always a JSON object, with its version.
+#[derive(Serialize)]
+pub(super) struct VersionedConfigurationFile<'a, T> {
+    #[serde(rename = "$schema", skip_serializing_if = "Option::is_none")]
+    pub schema: Option<&'a String>,
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub configuration: &'a T,
+}
+
+/// Removes the version from the JSON content and applies all migrations to reach the current version.
+/// Returns the migrated content and the version of the file, files without version have the first version.
+pub(super) fn migrate<T>(mut content: Value) -> anyhow::Result<(Value, u32)>
+where
+    T: VersionedConfiguration,
+{
+    if let Some(object) = content.as_object_mut() {
+        object.remove(SCHEMA_KEY);
+    }
+    let stored_version = content
+        .as_object_mut()
+        .and_then(|object| object.remove(SCHEMA_VERSION_KEY))
+        .map(|version| {
+            version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| anyhow!("Invalid schema version {}.", version))
+        })
+        .transpose()?;
+    let file_version = stored_version.unwrap_or(UNVERSIONED_SCHEMA_VERSION);
+    if file_version > T::SCHEMA_VERSION {
+        return Err(anyhow!(
+            "Schema version {} is newer than the supported version {}.",
+            file_version,
+            T::SCHEMA_VERSION
+        ));
+    }
+
+    let migrations = T::migrations();
+    for version in file_version..T::SCHEMA_VERSION {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.from_version == version)
+            .ok_or_else(|| anyhow!("No migration from schema version {} found.", version))?;
+        content = (migration.migrate)(content)
+            .map_err(|err| anyhow!("Could not migrate from schema version {}: {}", version, err))?;
+    }
+    Ok((content, file_version))
+}
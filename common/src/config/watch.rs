@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Watches a configuration file until it is dropped.
+pub struct ConfigurationWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// The folder of the file is watched because editors often replace the file instead of writing to it.
+pub(super) fn watch_file<F>(
+    config_file_path: &Path,
+    debounce: Duration,
+    subscriber: F,
+) -> anyhow::Result<ConfigurationWatcher>
+where
+    F: Fn() + Send + 'static,
+{
+    let folder = config_file_path
+        .parent()
+        .context("Configuration file has no parent folder.")?;
+    let file_name = config_file_path
+        .file_name()
+        .context("Configuration file has no file name.")?
+        .to_owned();
+
+    let (change_sender, change_receiver) = std::sync::mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+            Ok(event) => {
+                let changes_file = !matches!(event.kind, EventKind::Access(_))
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == Some(file_name.as_os_str()));
+                if changes_file {
+                    // fails only after the debounce thread stopped
+                    let _ = change_sender.send(());
+                }
+            }
+            Err(err) => error!("Could not watch configuration file: {}.", err),
+        })
+        .map_err(|err| anyhow!("Could not create file watcher: {}", err))?;
+    watcher
+        .watch(folder, RecursiveMode::NonRecursive)
+        .map_err(|err| anyhow!("Could not watch folder {}: {}", folder.display(), err))?;
+
+    // the channel is closed when the watcher is dropped
+    std::thread::spawn(move || {
+        while change_receiver.recv().is_ok() {
+            loop {
+                match change_receiver.recv_timeout(debounce) {
+                    Ok(()) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            subscriber();
+        }
+    });
+
+    Ok(ConfigurationWatcher { _watcher: watcher })
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Context};
 
@@ -8,7 +8,11 @@ const ORGANIZATION_SUBFOLDER_NAME: &str = "mariokaufmann";
 const LOGS_SUBFOLDER_NAME: &str = "logs";
 
 pub fn get_log_folder_path(application_name: &str) -> anyhow::Result<PathBuf> {
-    let mut path = get_application_folder(application_name)?;
+    get_log_folder_path_in(&get_application_folder(application_name)?)
+}
+
+pub fn get_log_folder_path_in(application_folder: &Path) -> anyhow::Result<PathBuf> {
+    let mut path = application_folder.to_owned();
     path.push(LOGS_SUBFOLDER_NAME);
 
     if !path.exists() {
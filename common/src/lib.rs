@@ -6,10 +6,12 @@ extern crate log;
 extern crate serde_derive;
 
 pub mod action;
+pub mod assets;
 pub mod automacro;
 pub mod automodule;
 pub mod config;
 pub mod fs;
+pub mod state;
 pub mod test;
 pub mod types;
 pub mod websocket;
@@ -0,0 +1,74 @@
+use schemars::JsonSchema;
+
+/* Server side state values which can be displayed by clients, identified by a dotted id, e.g.
+ * philipshue.group.<group id>.on, philipshue.group.<group id>.brightness or client.<client name>.connected */
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AutomationState {
+    pub id: String,
+    pub value: AutomationStateValue,
+}
+
+impl AutomationState {
+    pub fn new(id: String, value: AutomationStateValue) -> AutomationState {
+        AutomationState { id, value }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AutomationStateValue {
+    Bool(bool),
+    Number(i64),
+    Text(String),
+}
+
+pub fn philips_hue_group_on_state_id(group_id: &str) -> String {
+    format!("philipshue.group.{}.on", group_id)
+}
+
+pub fn philips_hue_group_brightness_state_id(group_id: &str) -> String {
+    format!("philipshue.group.{}.brightness", group_id)
+}
+
+pub fn server_mode_state_id() -> String {
+    "server.mode".to_owned()
+}
+
+pub fn client_connected_state_id(client_name: &str) -> String {
+    format!("client.{}.connected", client_name)
+}
+
+pub fn streamdeck_connected_state_id(device_id: &str) -> String {
+    format!("streamdeck.{}.connected", device_id)
+}
+
+pub fn streamdeck_key_state_id(device_id: &str, folder_id: Option<&str>, key: u8) -> String {
+    match folder_id {
+        Some(folder_id) => format!("streamdeck.{}.folder.{}.key.{}", device_id, folder_id, key),
+        None => format!("streamdeck.{}.key.{}", device_id, key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_state_values() {
+        let state = AutomationState::new(
+            philips_hue_group_on_state_id("living_room"),
+            AutomationStateValue::Bool(true),
+        );
+        let serialized = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            r#"{"id":"philipshue.group.living_room.on","value":true}"#,
+            serialized
+        );
+
+        let number: AutomationStateValue = serde_json::from_str("42").unwrap();
+        assert_eq!(AutomationStateValue::Number(42), number);
+        let text: AutomationStateValue = serde_json::from_str(r#""night""#).unwrap();
+        assert_eq!(AutomationStateValue::Text("night".to_owned()), text);
+    }
+}
@@ -1,8 +1,20 @@
 use std::path::Path;
 use ts_rs::TS;
 
+const UI_FOLDER: &str = "../server/ui/src";
+
 pub fn export_type<T: TS>() {
     let file_name = format!("{}.d.ts", T::name());
-    let path = Path::new("../server/ui/src/types").join(file_name);
+    let path = Path::new(UI_FOLDER).join("types").join(file_name);
     T::export_to(path).unwrap()
 }
+
+pub fn export_schema(file_name: &str, schema: &serde_json::Value) {
+    let folder = Path::new(UI_FOLDER).join("schemas");
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::write(
+        folder.join(file_name),
+        serde_json::to_string_pretty(schema).unwrap(),
+    )
+    .unwrap()
+}
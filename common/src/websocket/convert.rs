@@ -1,50 +1,150 @@
-use crate::websocket::dto::{AutomationMessage, MessageHeader};
+use crate::websocket::dto::{AutomationMessage, IdentifiedMessage, MessageEnvelope, MessageHeader};
 use anyhow::anyhow;
 
-const PROTOCOL_VERSION: u32 = 1;
 const HEADER_MESSAGE_SEPARATOR: &str = "||";
+const MESSAGE_TAG_KEY: &str = "tag";
+const MESSAGE_PAYLOAD_KEY: &str = "payload";
+
+/// Version of the websocket protocol, it is negotiated per connection with the websocket subprotocol.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ProtocolVersion {
+    /// A JSON header and the JSON message separated by `||`, used if the client requests no subprotocol.
+    #[default]
+    V1,
+    /// A single JSON envelope with version, type, id and payload.
+    V2,
+}
+
+impl ProtocolVersion {
+    /// All supported versions, the newest version is preferred.
+    pub const SUPPORTED: [ProtocolVersion; 2] = [ProtocolVersion::V2, ProtocolVersion::V1];
+
+    pub fn number(&self) -> u32 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
+
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V1 => "home-automation.v1",
+            ProtocolVersion::V2 => "home-automation.v2",
+        }
+    }
+
+    pub fn from_subprotocol(subprotocol: &str) -> Option<ProtocolVersion> {
+        ProtocolVersion::SUPPORTED
+            .into_iter()
+            .find(|version| version.subprotocol() == subprotocol)
+    }
+}
 
 pub fn parse_message_from_string(text: &str) -> anyhow::Result<AutomationMessage> {
-    let mut header: Option<MessageHeader> = None;
-    for part in text.split(HEADER_MESSAGE_SEPARATOR) {
-        match header {
-            None => {
-                let parsed_header = serde_json::from_str::<MessageHeader>(part)?;
-                header = Some(parsed_header)
-            }
-            Some(header) => {
-                return parse_message_with_header(part, header);
-            }
+    parse_identified_message_from_string(text).map(|message| message.message)
+}
+
+/// Parses messages of all protocol versions, a text which is a single JSON value is an envelope of version 2.
+pub fn parse_identified_message_from_string(text: &str) -> anyhow::Result<IdentifiedMessage> {
+    match serde_json::from_str::<MessageEnvelope>(text) {
+        Ok(envelope) => parse_envelope(envelope),
+        Err(_) => {
+            let (header, message) = text
+                .split_once(HEADER_MESSAGE_SEPARATOR)
+                .ok_or_else(|| anyhow!("Message format was invalid, could not parse."))?;
+            let header = serde_json::from_str::<MessageHeader>(header)?;
+            parse_message_with_header(message, header)
+        }
+    }
+}
+
+/// Reads only the id of a message, so that requests can be answered even if their message could not be parsed.
+pub fn parse_message_id_from_string(text: &str) -> Option<u64> {
+    match serde_json::from_str::<MessageEnvelope>(text) {
+        Ok(envelope) => envelope.id,
+        Err(_) => {
+            let (header, _) = text.split_once(HEADER_MESSAGE_SEPARATOR)?;
+            serde_json::from_str::<MessageHeader>(header).ok()?.id
         }
     }
-    Err(anyhow!("Message format was invalid, could not parse."))
 }
 
 fn parse_message_with_header(
     text: &str,
     header: MessageHeader,
-) -> anyhow::Result<AutomationMessage> {
-    match header.version {
-        PROTOCOL_VERSION => {
-            let message = serde_json::from_str::<AutomationMessage>(text)?;
-            Ok(message)
-        }
-        _ => Err(anyhow!(
+) -> anyhow::Result<IdentifiedMessage> {
+    if header.version != ProtocolVersion::V1.number() {
+        return Err(anyhow!(
             "Could not parse message, unknown version number {}.",
             header.version
-        )),
+        ));
+    }
+    let message = serde_json::from_str::<AutomationMessage>(text)?;
+    Ok(IdentifiedMessage::new(header.id, message))
+}
+
+fn parse_envelope(envelope: MessageEnvelope) -> anyhow::Result<IdentifiedMessage> {
+    if envelope.version != ProtocolVersion::V2.number() {
+        return Err(anyhow!(
+            "Could not parse message, unknown version number {}.",
+            envelope.version
+        ));
+    }
+    let mut message = serde_json::Map::new();
+    message.insert(
+        MESSAGE_TAG_KEY.to_owned(),
+        serde_json::Value::String(envelope.message_type),
+    );
+    if let Some(payload) = envelope.payload {
+        message.insert(MESSAGE_PAYLOAD_KEY.to_owned(), payload);
+    }
+    let message = serde_json::from_value(serde_json::Value::Object(message))?;
+    Ok(IdentifiedMessage::new(envelope.id, message))
+}
+
+pub fn convert_message_to_text(
+    message: AutomationMessage,
+    version: ProtocolVersion,
+) -> anyhow::Result<String> {
+    convert_identified_message_to_text(IdentifiedMessage::from(message), version)
+}
+
+pub fn convert_identified_message_to_text(
+    message: IdentifiedMessage,
+    version: ProtocolVersion,
+) -> anyhow::Result<String> {
+    match version {
+        ProtocolVersion::V1 => {
+            let header = MessageHeader {
+                version: version.number(),
+                id: message.id,
+            };
+            let mut text = serialize_generic_message(header)?;
+            text.push_str(HEADER_MESSAGE_SEPARATOR);
+            let actual_message_text = serialize_generic_message(message.message)?;
+            text.push_str(&actual_message_text);
+            Ok(text)
+        }
+        ProtocolVersion::V2 => serialize_generic_message(create_envelope(message)?),
     }
 }
 
-pub fn convert_message_to_text(message: AutomationMessage) -> anyhow::Result<String> {
-    let header = MessageHeader {
-        version: PROTOCOL_VERSION,
+fn create_envelope(message: IdentifiedMessage) -> anyhow::Result<MessageEnvelope> {
+    let id = message.id;
+    let mut message = match serde_json::to_value(message.message)? {
+        serde_json::Value::Object(message) => message,
+        message => return Err(anyhow!("Message {} is not an object.", message)),
     };
-    let mut text = serialize_generic_message(header)?;
-    text.push_str(HEADER_MESSAGE_SEPARATOR);
-    let actual_message_text = serialize_generic_message(message)?;
-    text.push_str(&actual_message_text);
-    Ok(text)
+    let message_type = match message.remove(MESSAGE_TAG_KEY) {
+        Some(serde_json::Value::String(message_type)) => message_type,
+        _ => return Err(anyhow!("Message does not have a type.")),
+    };
+    Ok(MessageEnvelope {
+        version: ProtocolVersion::V2.number(),
+        message_type,
+        id,
+        payload: message.remove(MESSAGE_PAYLOAD_KEY),
+    })
 }
 
 fn serialize_generic_message<M>(message: M) -> anyhow::Result<String>
@@ -108,6 +208,99 @@ mod tests {
         do_message_parsing(&test_data);
     }
 
+    #[test]
+    fn test_convert_message_to_envelope() {
+        let test_message = AutomationMessage::StatusUpdate {
+            update: AutomationStatusUpdate::SoundPlayed {
+                sound: "a||b".to_string(),
+            },
+        };
+
+        let text = convert_message_to_text(test_message.clone(), ProtocolVersion::V2).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "version": 2,
+                "type": "StatusUpdate",
+                "payload": {"update": {"tag": "SoundPlayed", "payload": {"sound": "a||b"}}}
+            }),
+            serde_json::from_str::<serde_json::Value>(&text).unwrap()
+        );
+        assert_eq!(test_message, do_message_parsing(&text));
+
+        let text = convert_message_to_text(AutomationMessage::Ping, ProtocolVersion::V2).unwrap();
+        assert_eq!(r#"{"version":2,"type":"Ping"}"#, text);
+        assert_eq!(AutomationMessage::Ping, do_message_parsing(&text));
+        // the id is optional and not part of the message
+        assert_eq!(
+            AutomationMessage::RequestClientStates,
+            do_message_parsing(r#"{"version":2,"type":"RequestClientStates","id":7}"#)
+        );
+    }
+
+    #[test]
+    fn test_convert_message_with_id() {
+        let test_message = IdentifiedMessage::new(
+            Some(42),
+            AutomationMessage::Error {
+                error: "failed".to_owned(),
+            },
+        );
+        for version in ProtocolVersion::SUPPORTED {
+            let text = convert_identified_message_to_text(test_message.clone(), version).unwrap();
+            assert_eq!(
+                test_message,
+                parse_identified_message_from_string(&text).unwrap()
+            );
+        }
+        assert_eq!(
+            r#"{"version":1,"id":42}||{"tag":"Response"}"#,
+            convert_identified_message_to_text(
+                IdentifiedMessage::new(Some(42), AutomationMessage::Response),
+                ProtocolVersion::V1
+            )
+            .unwrap()
+        );
+        // messages without id are unchanged
+        assert_eq!(
+            r#"{"version":1}||{"tag":"Response"}"#,
+            convert_message_to_text(AutomationMessage::Response, ProtocolVersion::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_envelope() {
+        assert!(parse_message_from_string(r#"{"version":3,"type":"Ping"}"#).is_err());
+        assert!(parse_message_from_string(r#"{"version":2,"type":"Unknown"}"#).is_err());
+        assert!(parse_message_from_string(r#"{"version":2,"type":"StatusUpdate"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_message_id_of_invalid_message() {
+        assert_eq!(
+            Some(3),
+            parse_message_id_from_string(r#"{"version":2,"type":"Unknown","id":3}"#)
+        );
+        assert_eq!(
+            Some(4),
+            parse_message_id_from_string(r#"{"version":1,"id":4}||{"tag":"Unknown"}"#)
+        );
+        assert_eq!(
+            None,
+            parse_message_id_from_string(r#"{"version":2,"type":"Unknown"}"#)
+        );
+        assert_eq!(None, parse_message_id_from_string("invalid_header||{}"));
+    }
+
+    #[test]
+    fn test_negotiate_protocol_version() {
+        assert_eq!(
+            Some(ProtocolVersion::V2),
+            ProtocolVersion::from_subprotocol("home-automation.v2")
+        );
+        assert_eq!(None, ProtocolVersion::from_subprotocol("graphql-ws"));
+        assert_eq!(ProtocolVersion::V2, ProtocolVersion::SUPPORTED[0]);
+    }
+
     fn do_message_parsing(text: &str) -> AutomationMessage {
         parse_message_from_string(text).unwrap()
     }
@@ -124,7 +317,8 @@ mod tests {
     fn generate_test_data(valid_header: bool, message_text: &str, valid_separator: bool) -> String {
         let mut test_data = if valid_header {
             let test_header = MessageHeader {
-                version: PROTOCOL_VERSION,
+                version: ProtocolVersion::V1.number(),
+                id: None,
             };
             serde_json::to_string(&test_header).unwrap()
         } else {
@@ -1,9 +1,45 @@
-use crate::action::AutomationStatusUpdate;
+use crate::action::{AutomationAction, AutomationStatusUpdate};
 use crate::automacro::AutomationMacro;
+use crate::automodule::streamdeck::StreamdeckModel;
+use crate::state::AutomationState;
 
 #[derive(Serialize, Deserialize)]
 pub struct MessageHeader {
     pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+}
+
+/// A message of protocol version 2, the type and payload are the tag and payload of the [AutomationMessage].
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MessageEnvelope {
+    pub version: u32,
+    #[serde(rename = "type")]
+    pub message_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>,
+}
+
+/// A message with the id of the request it belongs to. Requests are sent with an id chosen by the client and
+/// their replies have the same id, other messages have no id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentifiedMessage {
+    pub id: Option<u64>,
+    pub message: AutomationMessage,
+}
+
+impl IdentifiedMessage {
+    pub fn new(id: Option<u64>, message: AutomationMessage) -> IdentifiedMessage {
+        IdentifiedMessage { id, message }
+    }
+}
+
+impl From<AutomationMessage> for IdentifiedMessage {
+    fn from(message: AutomationMessage) -> Self {
+        IdentifiedMessage { id: None, message }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -11,6 +47,16 @@ pub struct MessageHeader {
 pub struct SingleClientUpdate {
     pub name: String,
     pub device_type: ClientDeviceType,
+    /* Streamdecks which are attached to a streamdeck client. */
+    #[serde(default)]
+    pub streamdecks: Vec<ConnectedStreamdeck>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedStreamdeck {
+    pub device_id: String,
+    pub model: StreamdeckModel,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -25,8 +71,42 @@ pub enum ClientDeviceType {
 pub struct ClientState {
     pub connected_since: String,
     pub name: String,
+    /* Name of the client credentials the client authenticated with. */
+    #[serde(default)]
+    pub identity: String,
     pub macros_executed: u32,
     pub device_type: ClientDeviceType,
+    pub streamdecks: Vec<ConnectedStreamdeck>,
+}
+
+/// Outcome of a single action of an executed macro.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActionExecutionResult {
+    pub action: AutomationAction,
+    pub handled: bool,
+    /* Module which handled the action or failed to handle it. */
+    pub module: Option<String>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MacroExecutionReport {
+    pub macro_name: String,
+    /* Authenticated identity of the client which requested the execution. */
+    pub client_name: String,
+    pub executed_at: String,
+    pub actions: Vec<ActionExecutionResult>,
+}
+
+impl MacroExecutionReport {
+    pub fn is_successful(&self) -> bool {
+        self.actions
+            .iter()
+            .all(|action| action.handled && action.error.is_none())
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -48,4 +128,18 @@ pub enum AutomationMessage {
     ClientStates {
         states: Vec<ClientState>,
     },
+    /* States which are owned by a client, e.g. the values of live streamdeck keys. */
+    ReportStates {
+        states: Vec<AutomationState>,
+    },
+    /* Sent to the client which executed a macro, with the id of the request if it had one. */
+    MacroExecuted {
+        report: MacroExecutionReport,
+    },
+    /* Reply to a request which has no more specific reply, e.g. once states were reported. */
+    Response,
+    /* Reply to a request which could not be handled. */
+    Error {
+        error: String,
+    },
 }
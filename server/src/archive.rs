@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+
+use anyhow::{anyhow, Context};
+
+/// Reads the files of the zip archive, e.g. of a "backup" or "profile" archive as named by the description.
+/// Stops reading at the limits instead of trusting the sizes in the archive, which could be forged.
+pub fn read_limited_archive(
+    archive: &[u8],
+    description: &str,
+    file_size_limit: u64,
+    extracted_size_limit: u64,
+) -> anyhow::Result<BTreeMap<String, Vec<u8>>> {
+    let mut zip_archive = zip::ZipArchive::new(Cursor::new(archive))
+        .with_context(|| format!("Could not open {} archive.", description))?;
+    let mut files = BTreeMap::new();
+    let mut extracted_size = 0;
+    for index in 0..zip_archive.len() {
+        let mut file = zip_archive
+            .by_index(index)
+            .with_context(|| format!("Could not read file from {} archive.", description))?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_owned();
+        let mut content = Vec::new();
+        (&mut file)
+            .take(file_size_limit + 1)
+            .read_to_end(&mut content)
+            .with_context(|| format!("Could not read {} from {} archive.", name, description))?;
+        if content.len() as u64 > file_size_limit {
+            return Err(anyhow!(
+                "File {} of the {} archive is larger than {} bytes.",
+                name,
+                description,
+                file_size_limit
+            ));
+        }
+        extracted_size += content.len() as u64;
+        if extracted_size > extracted_size_limit {
+            return Err(anyhow!(
+                "The {} archive is larger than {} bytes when extracted.",
+                description,
+                extracted_size_limit
+            ));
+        }
+        files.insert(name, content);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn reject_oversized_archive() {
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for name in ["a", "b"] {
+            archive
+                .start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            archive.write_all(&[0; 100]).unwrap();
+        }
+        let archive = archive.finish().unwrap().into_inner();
+
+        let files = read_limited_archive(&archive, "test", 100, 200).unwrap();
+        assert_eq!(vec![0; 100], files["a"]);
+        assert_eq!(vec![0; 100], files["b"]);
+        assert!(read_limited_archive(&archive, "test", 99, 200).is_err());
+        assert!(read_limited_archive(&archive, "test", 100, 199).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use axum::body::Bytes;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Router;
+use home_automation_common::assets::{get_asset_extension, validate_asset_name};
+use hyper::StatusCode;
+
+const ASSETS_SUBFOLDER_NAME: &str = "assets";
+const PNG_SIGNATURE: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+const JPEG_SIGNATURE: &[u8] = &[0xFF, 0xD8, 0xFF];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetType {
+    Png,
+    Jpeg,
+}
+
+impl AssetType {
+    fn from_asset_name(name: &str) -> anyhow::Result<AssetType> {
+        validate_asset_name(name)?;
+        match get_asset_extension(name).as_deref() {
+            Some("png") => Ok(AssetType::Png),
+            Some("jpg") | Some("jpeg") => Ok(AssetType::Jpeg),
+            _ => Err(anyhow!("Asset {} does not have a known file type.", name)),
+        }
+    }
+
+    /// Detects the type of an image from its file signature.
+    pub fn from_content(content: &[u8]) -> Option<AssetType> {
+        [AssetType::Png, AssetType::Jpeg]
+            .into_iter()
+            .find(|asset_type| asset_type.matches_content(content))
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AssetType::Png => "png",
+            AssetType::Jpeg => "jpg",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            AssetType::Png => "image/png",
+            AssetType::Jpeg => "image/jpeg",
+        }
+    }
+
+    fn matches_content(&self, content: &[u8]) -> bool {
+        match self {
+            AssetType::Png => content.starts_with(PNG_SIGNATURE),
+            AssetType::Jpeg => content.starts_with(JPEG_SIGNATURE),
+        }
+    }
+}
+
+/// Stores asset files (e.g. streamdeck button images) in a subfolder of the application folder.
+pub struct AssetStore {
+    assets_folder: PathBuf,
+}
+
+impl AssetStore {
+    pub fn new(application_folder: &Path) -> anyhow::Result<AssetStore> {
+        let mut assets_folder = application_folder.to_owned();
+        assets_folder.push(ASSETS_SUBFOLDER_NAME);
+        if !assets_folder.exists() {
+            std::fs::create_dir_all(&assets_folder)
+                .context("Could not prepare assets subfolder.")?;
+        }
+        Ok(AssetStore { assets_folder })
+    }
+
+    pub fn get_routes(self) -> Router {
+        Router::new().nest(
+            "/assets",
+            Router::new()
+                .route("/:name", axum::routing::get(get_asset))
+                .route("/:name", axum::routing::put(put_asset))
+                .with_state(Arc::new(self)),
+        )
+    }
+
+    pub fn load_asset(&self, name: &str) -> anyhow::Result<(AssetType, Vec<u8>)> {
+        let asset_type = AssetType::from_asset_name(name)?;
+        let content = std::fs::read(self.assets_folder.join(name))
+            .with_context(|| format!("Could not read asset {}.", name))?;
+        Ok((asset_type, content))
+    }
+
+    pub fn store_asset(&self, name: &str, content: &[u8]) -> anyhow::Result<()> {
+        validate_asset(name, content)?;
+        std::fs::write(self.assets_folder.join(name), content)
+            .with_context(|| format!("Could not write asset {}.", name))?;
+        Ok(())
+    }
+
+    pub fn remove_asset(&self, name: &str) -> anyhow::Result<()> {
+        validate_asset_name(name)?;
+        std::fs::remove_file(self.assets_folder.join(name))
+            .with_context(|| format!("Could not remove asset {}.", name))
+    }
+
+    pub fn asset_exists(&self, name: &str) -> bool {
+        validate_asset_name(name).is_ok() && self.assets_folder.join(name).is_file()
+    }
+
+    /// The names of all stored assets in alphabetical order, other files in the folder are skipped.
+    pub fn list_assets(&self) -> anyhow::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in
+            std::fs::read_dir(&self.assets_folder).context("Could not read assets folder.")?
+        {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && AssetType::from_asset_name(&name).is_ok() {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+/// Checks that the name is a valid asset name and the content matches the file type of its extension.
+pub fn validate_asset(name: &str, content: &[u8]) -> anyhow::Result<()> {
+    let asset_type = AssetType::from_asset_name(name)?;
+    if !asset_type.matches_content(content) {
+        return Err(anyhow!(
+            "Content of asset {} does not match its file type.",
+            name
+        ));
+    }
+    Ok(())
+}
+
+async fn get_asset(
+    State(store): State<Arc<AssetStore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if validate_asset_name(&name).is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match store.load_asset(&name) {
+        Ok((asset_type, content)) => {
+            Ok(([(header::CONTENT_TYPE, asset_type.content_type())], content))
+        }
+        Err(err) => {
+            debug!("Could not load asset: {}.", err);
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+async fn put_asset(
+    State(store): State<Arc<AssetStore>>,
+    UrlPath(name): UrlPath<String>,
+    body: Bytes,
+) -> Result<(), StatusCode> {
+    store.store_asset(&name, &body).map_err(|err| {
+        warn!("Could not store asset: {}.", err);
+        StatusCode::BAD_REQUEST
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use home_automation_common::fs;
+
+    use super::*;
+
+    #[test]
+    fn store_and_load_asset() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let store = AssetStore::new(&path).unwrap();
+
+        let mut content = PNG_SIGNATURE.to_vec();
+        content.extend_from_slice(&[1, 2, 3]);
+        store.store_asset("light.png", &content).unwrap();
+
+        let (asset_type, loaded_content) = store.load_asset("light.png").unwrap();
+        assert_eq!(AssetType::Png, asset_type);
+        assert_eq!(content, loaded_content);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn store_asset_with_wrong_content() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let store = AssetStore::new(&path).unwrap();
+
+        let result = store.store_asset("light.jpg", PNG_SIGNATURE);
+        assert!(result.is_err());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn load_asset_outside_of_store() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let store = AssetStore::new(&path).unwrap();
+
+        let result = store.load_asset("../secret.png");
+        assert!(result.is_err());
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+}
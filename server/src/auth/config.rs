@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use home_automation_common::config::{Secret, Secrets, VersionedConfiguration};
+use schemars::JsonSchema;
+
+/// Generated and new tokens have at least this length so that they can not be guessed.
+pub const MIN_TOKEN_LENGTH: usize = 16;
+
+/// Clients which may use the websocket and the REST API.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthConfiguration {
+    pub clients: Vec<ClientCredentials>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCredentials {
+    /* Identity of the client, e.g. shown in the client states. */
+    pub name: String,
+    /* Passed by the client as bearer token or as token query parameter of the websocket URL. */
+    pub token: Secret,
+    /* Only admins may add and remove clients. */
+    #[serde(default)]
+    pub role: ClientRole,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ClientRole {
+    Admin,
+    #[default]
+    Client,
+}
+
+impl VersionedConfiguration for AuthConfiguration {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl AuthConfiguration {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.clients.is_empty() {
+            return Err(anyhow!(
+                "At least one client has to be configured, otherwise nobody could connect."
+            ));
+        }
+        let mut names = HashSet::new();
+        for client in &self.clients {
+            validate_client_name(&client.name)?;
+            if !names.insert(&client.name) {
+                return Err(anyhow!("Client {} is configured twice.", client.name));
+            }
+            if client.token.is_plain() {
+                validate_token(&client.name, client.token.expose()?)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn resolve_secrets(&mut self, secrets: &Secrets) -> anyhow::Result<()> {
+        for client in &mut self.clients {
+            client.token.resolve(secrets).map_err(|err| {
+                anyhow!("Could not resolve token of client {}: {}", client.name, err)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+pub fn validate_client_name(name: &str) -> anyhow::Result<()> {
+    if name.trim().is_empty() {
+        return Err(anyhow!("The name of a client must not be empty."));
+    }
+    Ok(())
+}
+
+pub fn validate_token(client_name: &str, token: &str) -> anyhow::Result<()> {
+    if token.chars().count() < MIN_TOKEN_LENGTH {
+        return Err(anyhow!(
+            "The token of client {} must have at least {} characters.",
+            client_name,
+            MIN_TOKEN_LENGTH
+        ));
+    }
+    Ok(())
+}
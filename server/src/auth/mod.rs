@@ -0,0 +1,615 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::anyhow;
+use axum::extract::{Extension, Query, State};
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use home_automation_common::config::{
+    ConfigurationManager, ConfigurationWatcher, Secret, SecretSource, Secrets,
+};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use crate::auth::config::{validate_client_name, validate_token, ClientCredentials};
+pub use crate::auth::config::{AuthConfiguration, ClientRole};
+use crate::automodule::CONFIGURATION_WATCH_DEBOUNCE;
+use crate::websocket::WEBSOCKET_PATH;
+
+mod config;
+
+const CONFIG_FILE_NAME: &str = "clientsConfig.json";
+/// Created on the first start, its token is stored in the secrets file like the tokens of all added clients.
+const INITIAL_CLIENT_NAME: &str = "admin";
+const GENERATED_TOKEN_LENGTH: usize = 32;
+
+/// Identity of the client which sent a request, added to the requests which passed [require_authentication].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedClient {
+    pub name: String,
+    pub role: ClientRole,
+}
+
+/// Checks the tokens of the clients against the clients configuration, which is reloaded when the file changes.
+pub struct ClientAuthenticator {
+    application_folder: PathBuf,
+    /* The configuration of the manager has resolved secrets. */
+    configuration_manager: RwLock<ConfigurationManager<AuthConfiguration>>,
+}
+
+impl ClientAuthenticator {
+    /// Creates a client with a generated token if no client is configured yet.
+    pub fn load(application_folder: &Path) -> anyhow::Result<ClientAuthenticator> {
+        let mut configuration_manager =
+            ConfigurationManager::<AuthConfiguration>::load(application_folder, CONFIG_FILE_NAME)?;
+        if configuration_manager.get_configuration().clients.is_empty() {
+            let secret_name = get_client_secret_name(INITIAL_CLIENT_NAME);
+            Secrets::load(application_folder)?.store_secret(&secret_name, &generate_token())?;
+            configuration_manager.set_configuration(AuthConfiguration {
+                clients: vec![ClientCredentials {
+                    name: INITIAL_CLIENT_NAME.to_owned(),
+                    token: Secret::new(SecretSource::SecretsFile {
+                        secret: secret_name.clone(),
+                    }),
+                    role: ClientRole::Admin,
+                }],
+            });
+            configuration_manager.persist_configuration()?;
+            warn!(
+                "No clients were configured, created client {} whose token is stored as {} in the secrets file. Every client has to pass a token to connect to the server.",
+                INITIAL_CLIENT_NAME, secret_name
+            );
+        }
+        configuration_manager.get_configuration().validate()?;
+        let configuration =
+            Self::resolve_configuration(&configuration_manager, application_folder)?;
+        configuration_manager.set_configuration(configuration);
+
+        Ok(ClientAuthenticator {
+            application_folder: application_folder.to_owned(),
+            configuration_manager: RwLock::new(configuration_manager),
+        })
+    }
+
+    fn resolve_configuration(
+        configuration_manager: &ConfigurationManager<AuthConfiguration>,
+        application_folder: &Path,
+    ) -> anyhow::Result<AuthConfiguration> {
+        let mut configuration = configuration_manager.get_configuration().clone();
+        configuration.resolve_secrets(&Secrets::load(application_folder)?)?;
+        Ok(configuration)
+    }
+
+    /// Applies valid changes of the configuration file, e.g. clients which were added by hand.
+    pub fn watch(self: &Arc<Self>) -> anyhow::Result<ConfigurationWatcher> {
+        let authenticator = self.clone();
+        self.configuration_manager
+            .read()
+            .unwrap()
+            .watch(CONFIGURATION_WATCH_DEBOUNCE, move || {
+                match authenticator.reload_configuration() {
+                    Ok(true) => info!("Applying changed clients config."),
+                    Ok(false) => {}
+                    Err(err) => error!(
+                        "Keeping the running clients config, the changed file is invalid: {}",
+                        err
+                    ),
+                }
+            })
+    }
+
+    fn reload_configuration(&self) -> anyhow::Result<bool> {
+        let mut configuration_manager = self.configuration_manager.write().unwrap();
+        if !configuration_manager.reload_changed_configuration(AuthConfiguration::validate)? {
+            return Ok(false);
+        }
+        let configuration =
+            Self::resolve_configuration(&configuration_manager, &self.application_folder)?;
+        configuration_manager.set_configuration(configuration);
+        Ok(true)
+    }
+
+    /// Returns the client with the given token.
+    pub fn authenticate(&self, token: &str) -> Option<AuthenticatedClient> {
+        let configuration_manager = self.configuration_manager.read().unwrap();
+        configuration_manager
+            .get_configuration()
+            .clients
+            .iter()
+            .find(|client| match client.token.expose() {
+                Ok(client_token) => tokens_equal(client_token.as_bytes(), token.as_bytes()),
+                Err(_) => false,
+            })
+            .map(|client| AuthenticatedClient {
+                name: client.name.clone(),
+                role: client.role,
+            })
+    }
+
+    pub fn get_client_names(&self) -> Vec<String> {
+        let configuration_manager = self.configuration_manager.read().unwrap();
+        configuration_manager
+            .get_configuration()
+            .clients
+            .iter()
+            .map(|client| client.name.clone())
+            .collect()
+    }
+
+    /// Stores a new client with its token in the secrets file, a token is generated if none is given. Returns the
+    /// token of the client.
+    pub fn add_client(
+        &self,
+        name: &str,
+        role: ClientRole,
+        token: Option<String>,
+    ) -> anyhow::Result<String> {
+        validate_client_name(name)?;
+        let token = token.unwrap_or_else(generate_token);
+        validate_token(name, &token)?;
+
+        let mut configuration_manager = self.configuration_manager.write().unwrap();
+        let mut configuration = configuration_manager.get_configuration().clone();
+        if configuration
+            .clients
+            .iter()
+            .any(|client| client.name.eq(name))
+        {
+            return Err(anyhow!("Client {} already exists.", name));
+        }
+        let secret_name = get_client_secret_name(name);
+        let mut secrets = Secrets::load(&self.application_folder)?;
+        secrets.store_secret(&secret_name, &token)?;
+        let mut client_token = Secret::new(SecretSource::SecretsFile {
+            secret: secret_name.clone(),
+        });
+        client_token.resolve(&secrets)?;
+        configuration.clients.push(ClientCredentials {
+            name: name.to_owned(),
+            token: client_token,
+            role,
+        });
+        let previous_configuration = configuration_manager.get_configuration().clone();
+        configuration_manager.set_configuration(configuration);
+        if let Err(err) = configuration_manager.persist_configuration() {
+            configuration_manager.set_configuration(previous_configuration);
+            secrets.remove_secret(&secret_name)?;
+            return Err(err);
+        }
+        Ok(token)
+    }
+
+    /// Removes the client, its open websocket connection is kept. The last client and the last admin
+    /// can not be removed.
+    pub fn remove_client(&self, name: &str) -> anyhow::Result<bool> {
+        let mut configuration_manager = self.configuration_manager.write().unwrap();
+        let mut configuration = configuration_manager.get_configuration().clone();
+        let count_admins = |configuration: &AuthConfiguration| {
+            configuration
+                .clients
+                .iter()
+                .filter(|client| client.role == ClientRole::Admin)
+                .count()
+        };
+        let admin_count = count_admins(&configuration);
+        let removed_client = match configuration
+            .clients
+            .iter()
+            .position(|client| client.name.eq(name))
+        {
+            Some(index) => configuration.clients.remove(index),
+            None => return Ok(false),
+        };
+        if admin_count > 0 && count_admins(&configuration) == 0 {
+            return Err(anyhow!(
+                "Client {} is the last admin, nobody could manage the clients anymore.",
+                name
+            ));
+        }
+        configuration.validate()?;
+        configuration_manager.set_configuration(configuration);
+        configuration_manager.persist_configuration()?;
+        if let Some(secret_name) = removed_client.token.get_secrets_file_entry() {
+            Secrets::load(&self.application_folder)?.remove_secret(secret_name)?;
+        }
+        Ok(true)
+    }
+}
+
+fn get_client_secret_name(client_name: &str) -> String {
+    format!("{}ClientToken", client_name)
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(GENERATED_TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Compares in constant time, so that the duration of a failed attempt does not reveal parts of a token.
+fn tokens_equal(expected: &[u8], actual: &[u8]) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .fold(0, |difference, (left, right)| difference | (left ^ right))
+            == 0
+}
+
+fn get_bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_owned())
+}
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Rejects requests without a valid token. Browsers can not set headers on websocket upgrades, so the token of
+/// the websocket can also be passed as query parameter. Other requests must not put tokens into URLs.
+pub async fn require_authentication<B>(
+    State(authenticator): State<Arc<ClientAuthenticator>>,
+    Query(query): Query<TokenQuery>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let query_token = query
+        .token
+        .filter(|_| request.uri().path() == WEBSOCKET_PATH);
+    let client = get_bearer_token(request.headers())
+        .or(query_token)
+        .and_then(|token| authenticator.authenticate(&token));
+    match client {
+        Some(client) => {
+            request.extensions_mut().insert(client);
+            next.run(request).await
+        }
+        None => {
+            warn!(
+                "Rejected unauthenticated request to {}.",
+                request.uri().path()
+            );
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Requires a token for all routes of the router except its fallback, which serves the static files of the web
+/// interface. The web interface asks for a token once the API rejects its requests.
+pub fn authenticate_routes(router: Router, authenticator: Arc<ClientAuthenticator>) -> Router {
+    router.route_layer(axum::middleware::from_fn_with_state(
+        authenticator,
+        require_authentication,
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddClientRequest {
+    name: String,
+    #[serde(default)]
+    role: ClientRole,
+    token: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddedClient {
+    name: String,
+    token: String,
+}
+
+pub fn get_routes(authenticator: Arc<ClientAuthenticator>) -> Router {
+    Router::new()
+        .route("/clients", axum::routing::get(get_clients).post(add_client))
+        .route("/clients/:name", axum::routing::delete(remove_client))
+        .with_state(authenticator)
+}
+
+async fn get_clients(State(authenticator): State<Arc<ClientAuthenticator>>) -> Json<Vec<String>> {
+    Json(authenticator.get_client_names())
+}
+
+/// Rejects clients which are not admins, the operation is e.g. "manage clients".
+pub fn require_admin(
+    client: &AuthenticatedClient,
+    operation: &str,
+) -> Result<(), (StatusCode, String)> {
+    if client.role != ClientRole::Admin {
+        warn!("Client {} is not allowed to {}.", client.name, operation);
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Only admin clients may {}.", operation),
+        ));
+    }
+    Ok(())
+}
+
+/// The token is only returned once, it is not readable afterwards.
+async fn add_client(
+    State(authenticator): State<Arc<ClientAuthenticator>>,
+    Extension(client): Extension<AuthenticatedClient>,
+    Json(request): Json<AddClientRequest>,
+) -> Result<(StatusCode, Json<AddedClient>), (StatusCode, String)> {
+    require_admin(&client, "manage clients")?;
+    let token = authenticator
+        .add_client(&request.name, request.role, request.token)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    info!("Added client {}.", request.name);
+    Ok((
+        StatusCode::CREATED,
+        Json(AddedClient {
+            name: request.name,
+            token,
+        }),
+    ))
+}
+
+async fn remove_client(
+    State(authenticator): State<Arc<ClientAuthenticator>>,
+    Extension(client): Extension<AuthenticatedClient>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    require_admin(&client, "manage clients")?;
+    if client.name.eq(&name) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Client {} can not remove itself.", name),
+        ));
+    }
+    match authenticator.remove_client(&name) {
+        Ok(true) => {
+            info!("Removed client {}.", name);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("Unknown client {}.", name))),
+        Err(err) => Err((StatusCode::BAD_REQUEST, err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use home_automation_common::fs;
+
+    use super::*;
+
+    fn admin(name: &str) -> AuthenticatedClient {
+        AuthenticatedClient {
+            name: name.to_owned(),
+            role: ClientRole::Admin,
+        }
+    }
+
+    fn read_client_token(path: &Path, client_name: &str) -> anyhow::Result<String> {
+        let mut token = Secret::new(SecretSource::SecretsFile {
+            secret: get_client_secret_name(client_name),
+        });
+        token.resolve(&Secrets::load(path)?)?;
+        Ok(token.expose()?.to_owned())
+    }
+
+    #[test]
+    fn create_initial_client() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let authenticator = ClientAuthenticator::load(&path).unwrap();
+        assert_eq!(vec![INITIAL_CLIENT_NAME], authenticator.get_client_names());
+        assert_eq!(None, authenticator.authenticate(""));
+
+        // the generated token is only stored in the secrets file
+        let configuration =
+            ConfigurationManager::<AuthConfiguration>::load(&path, CONFIG_FILE_NAME)
+                .unwrap()
+                .get_configuration()
+                .clone();
+        assert!(!configuration.clients[0].token.is_plain());
+        let token = read_client_token(&path, INITIAL_CLIENT_NAME).unwrap();
+        assert_eq!(GENERATED_TOKEN_LENGTH, token.len());
+        assert_eq!(
+            Some(admin(INITIAL_CLIENT_NAME)),
+            ClientAuthenticator::load(&path)
+                .unwrap()
+                .authenticate(&token)
+        );
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn manage_clients() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let authenticator = ClientAuthenticator::load(&path).unwrap();
+
+        let desk_token = "desk-token-0123456789".to_owned();
+        authenticator
+            .add_client("desk", ClientRole::Client, Some(desk_token.clone()))
+            .unwrap();
+        let kitchen_token = authenticator
+            .add_client("kitchen", ClientRole::Admin, None)
+            .unwrap();
+        assert!(authenticator
+            .add_client("desk", ClientRole::Client, None)
+            .is_err());
+        assert!(authenticator
+            .add_client("bedroom", ClientRole::Client, Some("short".to_owned()))
+            .is_err());
+
+        assert_eq!(
+            Some(AuthenticatedClient {
+                name: "desk".to_owned(),
+                role: ClientRole::Client,
+            }),
+            authenticator.authenticate(&desk_token)
+        );
+        assert_eq!(
+            Some(admin("kitchen")),
+            authenticator.authenticate(&kitchen_token)
+        );
+        assert_eq!(None, authenticator.authenticate("desk-token-012345678"));
+        // the tokens are only stored in the secrets file
+        let stored_configuration = std::fs::read_to_string(path.join(CONFIG_FILE_NAME)).unwrap();
+        assert!(!stored_configuration.contains(&desk_token));
+        assert!(!stored_configuration.contains(&kitchen_token));
+        assert_eq!(desk_token, read_client_token(&path, "desk").unwrap());
+
+        assert!(authenticator.remove_client(INITIAL_CLIENT_NAME).unwrap());
+        // nobody could manage the clients anymore
+        assert!(authenticator.remove_client("kitchen").is_err());
+        assert!(authenticator.remove_client("desk").unwrap());
+        assert!(!authenticator.remove_client("desk").unwrap());
+        assert_eq!(None, authenticator.authenticate(&desk_token));
+        assert!(read_client_token(&path, "desk").is_err());
+        // nobody could connect anymore
+        assert!(authenticator.remove_client("kitchen").is_err());
+
+        let reloaded_authenticator = ClientAuthenticator::load(&path).unwrap();
+        assert_eq!(vec!["kitchen"], reloaded_authenticator.get_client_names());
+        assert_eq!(
+            Some(admin("kitchen")),
+            reloaded_authenticator.authenticate(&kitchen_token)
+        );
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restrict_client_management() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let authenticator = Arc::new(ClientAuthenticator::load(&path).unwrap());
+        let desk = AuthenticatedClient {
+            name: "desk".to_owned(),
+            role: ClientRole::Client,
+        };
+        let add_request = || {
+            Json(AddClientRequest {
+                name: "kitchen".to_owned(),
+                role: ClientRole::Client,
+                token: None,
+            })
+        };
+
+        let (status, _) = add_client(
+            State(authenticator.clone()),
+            Extension(desk.clone()),
+            add_request(),
+        )
+        .await
+        .map(|_| ())
+        .unwrap_err();
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        let (status, _) = add_client(
+            State(authenticator.clone()),
+            Extension(admin(INITIAL_CLIENT_NAME)),
+            add_request(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::CREATED, status);
+
+        let (status, _) = remove_client(
+            State(authenticator.clone()),
+            Extension(desk),
+            axum::extract::Path("kitchen".to_owned()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        let (status, _) = remove_client(
+            State(authenticator.clone()),
+            Extension(admin(INITIAL_CLIENT_NAME)),
+            axum::extract::Path(INITIAL_CLIENT_NAME.to_owned()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(
+            vec![INITIAL_CLIENT_NAME, "kitchen"],
+            authenticator.get_client_names()
+        );
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serve_web_interface_without_token() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        std::fs::write(path.join("index.html"), "<html></html>").unwrap();
+        let authenticator = Arc::new(ClientAuthenticator::load(&path).unwrap());
+        let router = Router::new()
+            .fallback_service(
+                axum::routing::get_service(tower_http::services::ServeDir::new(&path))
+                    .handle_error(|err| async move {
+                        error!("error occurred when serving static file: {}.", err)
+                    }),
+            )
+            .nest("/api", get_routes(authenticator.clone()))
+            .route(WEBSOCKET_PATH, axum::routing::get(|| async {}));
+        let router = authenticate_routes(router, authenticator);
+
+        let handle = axum_server::Handle::new();
+        let server_task = axum_server::bind(std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
+            .handle(handle.clone())
+            .serve(router.into_make_service());
+        tokio::spawn(server_task);
+        let port = handle.listening().await.unwrap().port();
+
+        let client = hyper::Client::new();
+        let get = |url_path: &str, token: Option<&str>| {
+            let mut request = hyper::Request::get(format!("http://127.0.0.1:{}{}", port, url_path));
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            client.request(request.body(hyper::Body::empty()).unwrap())
+        };
+        let token = read_client_token(&path, INITIAL_CLIENT_NAME).unwrap();
+        assert_eq!(
+            StatusCode::OK,
+            get("/index.html", None).await.unwrap().status()
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            get("/api/clients", None).await.unwrap().status()
+        );
+        assert_eq!(
+            StatusCode::OK,
+            get("/api/clients", Some(&token)).await.unwrap().status()
+        );
+        // tokens are only accepted in the URL of the websocket
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            get(&format!("/api/clients?token={}", token), None)
+                .await
+                .unwrap()
+                .status()
+        );
+        assert_eq!(
+            StatusCode::OK,
+            get(&format!("{}?token={}", WEBSOCKET_PATH, token), None)
+                .await
+                .unwrap()
+                .status()
+        );
+
+        handle.shutdown();
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn read_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, get_bearer_token(&headers));
+        headers.insert(header::AUTHORIZATION, "Basic dXNlcg==".parse().unwrap());
+        assert_eq!(None, get_bearer_token(&headers));
+        headers.insert(header::AUTHORIZATION, "Bearer abc".parse().unwrap());
+        assert_eq!(Some("abc".to_owned()), get_bearer_token(&headers));
+    }
+}
@@ -1,4 +1,5 @@
 use std::path::Path;
+use std::time::Duration;
 
 use axum::Router;
 use home_automation_common::action::AutomationAction;
@@ -9,6 +10,9 @@ use crate::websocket::dto::AutomationServerStatusUpdate;
 pub mod philipshue;
 pub mod streamdeck;
 
+/// Configuration files are reloaded once they did not change for this duration, editors often write them in several steps.
+pub const CONFIGURATION_WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
 pub trait AutomationModule {
     fn new(
         application_folder: &Path,
@@ -16,9 +20,20 @@ pub trait AutomationModule {
     ) -> anyhow::Result<Self>
     where
         Self: Sized;
+    /// Identifies the module in execution reports.
+    fn name(&self) -> &'static str;
     fn get_routes(&self) -> Option<Router>;
     fn handle_action(&mut self, automation_action: &AutomationAction) -> anyhow::Result<bool>;
     fn send_initial_state(&self, client_id: usize) -> anyhow::Result<()>;
+    /// Reads the configuration files of the module again, e.g. after they were restored from a backup.
+    fn reload_configuration(&mut self) -> anyhow::Result<()>;
+}
+
+/// Outcome of passing an action to the modules of a [CompositeAutomationModule].
+pub struct ActionHandling {
+    /* Module which handled the action or failed to handle it. */
+    pub module: Option<&'static str>,
+    pub result: anyhow::Result<bool>,
 }
 
 pub struct CompositeAutomationModule {
@@ -29,6 +44,25 @@ impl CompositeAutomationModule {
     pub fn add_module(&mut self, module: Box<dyn AutomationModule + Send>) {
         self.modules.push(module);
     }
+
+    /// Passes the action to the modules until one of them handles it or fails.
+    pub fn dispatch_action(&mut self, automation_action: &AutomationAction) -> ActionHandling {
+        for module in &mut self.modules {
+            match module.handle_action(automation_action) {
+                Ok(false) => {}
+                result => {
+                    return ActionHandling {
+                        module: Some(module.name()),
+                        result,
+                    }
+                }
+            }
+        }
+        ActionHandling {
+            module: None,
+            result: Ok(false),
+        }
+    }
 }
 
 impl AutomationModule for CompositeAutomationModule {
@@ -38,26 +72,22 @@ impl AutomationModule for CompositeAutomationModule {
         })
     }
 
+    fn name(&self) -> &'static str {
+        "composite"
+    }
+
     fn get_routes(&self) -> Option<Router> {
         let routers_iter = self.modules.iter().filter_map(|module| module.get_routes());
 
-        let mut merged_child_routers = Router::new();
+        let mut merged_routes = Router::new();
         for router in routers_iter {
-            merged_child_routers = merged_child_routers.merge(router);
+            merged_routes = merged_routes.merge(router);
         }
-
-        let merged_routes = Router::new().nest("/api", merged_child_routers);
         Some(merged_routes)
     }
 
     fn handle_action(&mut self, automation_action: &AutomationAction) -> anyhow::Result<bool> {
-        for module in &mut self.modules {
-            let handled = module.handle_action(automation_action)?;
-            if handled {
-                return Ok(true);
-            }
-        }
-        Ok(false)
+        self.dispatch_action(automation_action).result
     }
 
     fn send_initial_state(&self, client_id: usize) -> anyhow::Result<()> {
@@ -66,6 +96,13 @@ impl AutomationModule for CompositeAutomationModule {
         }
         Ok(())
     }
+
+    fn reload_configuration(&mut self) -> anyhow::Result<()> {
+        for module in &mut self.modules {
+            module.reload_configuration()?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -151,9 +188,10 @@ mod tests {
         module.add_module(Box::new(TestModule::new(test_module_data_2.clone())));
 
         let action = AutomationAction::StreamdeckClientReloadDeviceConfiguration;
-        let result = module.handle_action(&action);
+        let handling = module.dispatch_action(&action);
 
-        assert!(result.unwrap());
+        assert!(handling.result.unwrap());
+        assert_eq!(Some("test"), handling.module);
         assert!(!test_module_data_1.lock().unwrap().handled_action);
         assert!(test_module_data_2.lock().unwrap().handled_action);
     }
@@ -171,9 +209,10 @@ mod tests {
         module.add_module(Box::new(TestModule::new(test_module_data.clone())));
 
         let action = AutomationAction::StreamdeckClientReloadDeviceConfiguration;
-        let result = module.handle_action(&action);
+        let handling = module.dispatch_action(&action);
 
-        assert!(!result.unwrap());
+        assert!(!handling.result.unwrap());
+        assert_eq!(None, handling.module);
     }
 
     fn setup() -> TestData {
@@ -208,6 +247,10 @@ mod tests {
             unimplemented!()
         }
 
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
         fn get_routes(&self) -> Option<Router> {
             None
         }
@@ -227,5 +270,9 @@ mod tests {
             data.sent_initial_state = true;
             Ok(())
         }
+
+        fn reload_configuration(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
     }
 }
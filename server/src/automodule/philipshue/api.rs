@@ -1,22 +1,40 @@
 use crate::automodule::philipshue::config::PhilipsHueAutomationModuleConfiguration;
+use crate::websocket::dto::AutomationServerStatusUpdate;
 use anyhow::anyhow;
 use axum::http;
 use axum::http::StatusCode;
+use home_automation_common::action::AutomationStatusUpdate;
+use home_automation_common::state::{
+    philips_hue_group_brightness_state_id, philips_hue_group_on_state_id, AutomationState,
+    AutomationStateValue,
+};
 use hyper::client::HttpConnector;
 use hyper::Body;
 use hyper_tls::HttpsConnector;
+use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+/// The CLIP API v2 (bridge software 1.48 and newer) expects the application key in this header, unlike API v1
+/// where it is part of the URL. Requests without it are rejected with 403 Forbidden.
+const HUE_APPLICATION_KEY_HEADER: &str = "hue-application-key";
+
 pub struct ApiClient {
     request_sender: UnboundedSender<ConfigureHueGroupedLightRequest>,
 }
 
 impl ApiClient {
-    pub fn new(configuration: PhilipsHueAutomationModuleConfiguration) -> Self {
+    pub fn new(
+        configuration: Arc<RwLock<PhilipsHueAutomationModuleConfiguration>>,
+        status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
+    ) -> Self {
         let (request_tx, request_rx) =
             tokio::sync::mpsc::unbounded_channel::<ConfigureHueGroupedLightRequest>();
 
-        tokio::spawn(Self::create_requester_task(request_rx, configuration));
+        tokio::spawn(Self::create_requester_task(
+            request_rx,
+            configuration,
+            status_update_sender,
+        ));
 
         ApiClient {
             request_sender: request_tx,
@@ -25,20 +43,44 @@ impl ApiClient {
 
     async fn create_requester_task(
         mut request_receiver: UnboundedReceiver<ConfigureHueGroupedLightRequest>,
-        configuration: PhilipsHueAutomationModuleConfiguration,
+        configuration: Arc<RwLock<PhilipsHueAutomationModuleConfiguration>>,
+        status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
     ) {
         let https_connector = HttpsConnector::new();
         let client = hyper::Client::builder()
             .pool_max_idle_per_host(1)
             .build(https_connector);
         while let Some(request) = request_receiver.recv().await {
-            if let Err(err) = Self::configure_grouped_light(request, &configuration, &client).await
-            {
-                error!("Could not configure group on philips hue bridge: {}", err);
+            let states = Self::get_group_states(&request);
+            // the configuration can be replaced while the request is running
+            let configuration = configuration.read().unwrap().clone();
+            match Self::configure_grouped_light(request, &configuration, &client).await {
+                Ok(()) => {
+                    let update = AutomationServerStatusUpdate::broadcast(
+                        AutomationStatusUpdate::StatesChanged { states },
+                    );
+                    if let Err(err) = status_update_sender.send(update) {
+                        error!("Could not send philips hue group states: {}.", err);
+                    }
+                }
+                Err(err) => error!("Could not configure group on philips hue bridge: {}", err),
             }
         }
     }
 
+    fn get_group_states(request: &ConfigureHueGroupedLightRequest) -> Vec<AutomationState> {
+        vec![
+            AutomationState::new(
+                philips_hue_group_on_state_id(&request.id),
+                AutomationStateValue::Bool(request.on),
+            ),
+            AutomationState::new(
+                philips_hue_group_brightness_state_id(&request.id),
+                AutomationStateValue::Number(request.brightness as i64),
+            ),
+        ]
+    }
+
     async fn configure_grouped_light(
         request: ConfigureHueGroupedLightRequest,
         configuration: &PhilipsHueAutomationModuleConfiguration,
@@ -65,6 +107,7 @@ impl ApiClient {
             .method("PUT")
             .uri(url)
             .header(http::header::CONTENT_TYPE, "application/json")
+            .header(HUE_APPLICATION_KEY_HEADER, configuration.api_key.expose()?)
             .body(Body::from(serialized_body));
 
         match request {
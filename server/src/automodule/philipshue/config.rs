@@ -1,14 +1,67 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use home_automation_common::config::{Secret, Secrets, VersionedConfiguration};
+use schemars::JsonSchema;
+
+use crate::backup::BackupConfiguration;
+
 pub const CONFIG_FILE_NAME: &str = "philipsHueConfig.json";
+const MAX_BRIGHTNESS: u16 = 100;
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq, Eq)]
 pub struct PhilipsHueAutomationModuleConfiguration {
     pub bridge_ip: String,
-    pub api_key: String,
+    pub api_key: Secret,
     pub groups: Vec<PhilipsHueGroupConfiguration>,
     pub presets: Vec<PhilipsHuePresetConfiguration>,
 }
 
+impl VersionedConfiguration for PhilipsHueAutomationModuleConfiguration {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
 impl PhilipsHueAutomationModuleConfiguration {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut group_ids = HashSet::new();
+        for group in &self.groups {
+            if !group_ids.insert(group.id.as_str()) {
+                return Err(anyhow!("Group {} is configured more than once.", group.id));
+            }
+        }
+        let mut preset_ids = HashSet::new();
+        for preset in &self.presets {
+            if !preset_ids.insert(preset.id.as_str()) {
+                return Err(anyhow!(
+                    "Preset {} is configured more than once.",
+                    preset.id
+                ));
+            }
+            if preset.brightness > MAX_BRIGHTNESS {
+                return Err(anyhow!(
+                    "Preset {} has a brightness above {}.",
+                    preset.id,
+                    MAX_BRIGHTNESS
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn resolve_secrets(&mut self, secrets: &Secrets) -> anyhow::Result<()> {
+        self.api_key
+            .resolve(secrets)
+            .map_err(|err| anyhow!("Could not resolve philips hue api key: {}", err))
+    }
+
+    /// The configuration which can be returned to clients.
+    pub fn redacted(&self) -> PhilipsHueAutomationModuleConfiguration {
+        PhilipsHueAutomationModuleConfiguration {
+            api_key: self.api_key.redacted(),
+            ..self.clone()
+        }
+    }
+
     pub fn find_group(&self, group_id: &str) -> Option<&PhilipsHueGroupConfiguration> {
         self.groups.iter().find(|group| group.id.eq(group_id))
     }
@@ -18,13 +71,29 @@ impl PhilipsHueAutomationModuleConfiguration {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+impl BackupConfiguration for PhilipsHueAutomationModuleConfiguration {
+    fn validate_backup(&self) -> anyhow::Result<()> {
+        self.validate()
+    }
+
+    fn redact_secrets(&self) -> Self {
+        self.redacted()
+    }
+
+    fn restore_secrets(&mut self, current: &Self) {
+        if self.api_key.is_redacted() {
+            self.api_key = current.api_key.clone();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq, Eq)]
 pub struct PhilipsHueGroupConfiguration {
     pub id: String,
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq, Eq)]
 pub struct PhilipsHuePresetConfiguration {
     pub id: String,
     pub on: bool,
@@ -1,4 +0,0 @@
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct SetStatePayload {
-    pub on: bool,
-}
@@ -1,58 +1,167 @@
 use crate::automodule::philipshue::api::ApiClient;
-use crate::automodule::philipshue::config::{
-    PhilipsHueAutomationModuleConfiguration, CONFIG_FILE_NAME,
+use crate::automodule::philipshue::config::CONFIG_FILE_NAME;
+use crate::automodule::philipshue::routes::{
+    configure_group, get_configuration, get_groups, get_presets, HueState,
 };
-use crate::automodule::philipshue::routes::{configure_group, get_groups, get_presets, HueState};
-use crate::automodule::AutomationModule;
+use crate::automodule::{AutomationModule, CONFIGURATION_WATCH_DEBOUNCE};
+use crate::backup::ConfigurationBackup;
 use crate::websocket::dto::AutomationServerStatusUpdate;
+use anyhow::anyhow;
 use axum::Router;
-use home_automation_common::action::AutomationAction;
-use home_automation_common::config::ConfigurationManager;
-use std::path::Path;
-use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedSender;
+use home_automation_common::action::{AutomationAction, AutomationStatusUpdate};
+use home_automation_common::config::{
+    ConfigurationFormat, ConfigurationManager, ConfigurationWatcher, Secrets,
+};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+pub use crate::automodule::philipshue::config::PhilipsHueAutomationModuleConfiguration;
 
 mod api;
 mod config;
-mod dto;
 mod routes;
 
+const MODULE_NAME: &str = "philipshue";
+
 pub struct PhilipsHueAutomationModule {
-    configuration: PhilipsHueAutomationModuleConfiguration,
+    configuration: Arc<RwLock<PhilipsHueAutomationModuleConfiguration>>,
     api_client: ApiClient,
+    configuration_change_sender: UnboundedSender<()>,
+    _configuration_watcher: ConfigurationWatcher,
 }
 
-impl PhilipsHueAutomationModule {}
+impl PhilipsHueAutomationModule {
+    /// The stored configuration with the values of its secrets.
+    fn resolve_configuration(
+        configuration_manager: &ConfigurationManager<PhilipsHueAutomationModuleConfiguration>,
+        application_folder: &Path,
+    ) -> anyhow::Result<PhilipsHueAutomationModuleConfiguration> {
+        let mut configuration = configuration_manager.get_configuration().clone();
+        configuration.resolve_secrets(&Secrets::load(application_folder)?)?;
+        Ok(configuration)
+    }
+
+    /// Replaces the shared configuration with valid changes of the configuration file and tells the clients about it.
+    async fn apply_configuration_changes(
+        mut change_receiver: UnboundedReceiver<()>,
+        mut configuration_manager: ConfigurationManager<PhilipsHueAutomationModuleConfiguration>,
+        application_folder: PathBuf,
+        configuration: Arc<RwLock<PhilipsHueAutomationModuleConfiguration>>,
+        status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
+    ) {
+        while change_receiver.recv().await.is_some() {
+            let reloaded_configuration = configuration_manager
+                .reload_changed_configuration(PhilipsHueAutomationModuleConfiguration::validate)
+                .and_then(|changed| {
+                    changed
+                        .then(|| {
+                            Self::resolve_configuration(&configuration_manager, &application_folder)
+                        })
+                        .transpose()
+                });
+            match reloaded_configuration {
+                Ok(Some(reloaded_configuration)) => {
+                    info!("Applying changed philips hue config.");
+                    *configuration.write().unwrap() = reloaded_configuration;
+                    let update = AutomationServerStatusUpdate::broadcast(
+                        AutomationStatusUpdate::ConfigurationReloaded {
+                            module: MODULE_NAME.to_owned(),
+                        },
+                    );
+                    if let Err(err) = status_update_sender.send(update) {
+                        error!(
+                            "Could not send philips hue config reloaded message: {}.",
+                            err
+                        );
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => error!(
+                    "Keeping the running philips hue config, the changed file is invalid: {}",
+                    err
+                ),
+            }
+        }
+    }
+}
+
+/// Rewrites the configuration in another format, a running server has to be restarted to use it.
+pub fn convert_philips_hue_configuration(
+    application_folder: &Path,
+    format: ConfigurationFormat,
+) -> anyhow::Result<PathBuf> {
+    ConfigurationManager::<PhilipsHueAutomationModuleConfiguration>::convert(
+        application_folder,
+        CONFIG_FILE_NAME,
+        format,
+    )
+}
+
+pub fn philips_hue_configuration_backup() -> ConfigurationBackup {
+    ConfigurationBackup::new::<PhilipsHueAutomationModuleConfiguration>(CONFIG_FILE_NAME)
+}
 
 impl AutomationModule for PhilipsHueAutomationModule {
     fn new(
         application_folder: &Path,
-        _: UnboundedSender<AutomationServerStatusUpdate>,
+        status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
     ) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        let configuration_manager = Arc::new(ConfigurationManager::<
-            PhilipsHueAutomationModuleConfiguration,
-        >::load(application_folder, CONFIG_FILE_NAME)?);
+        let configuration_manager =
+            ConfigurationManager::<PhilipsHueAutomationModuleConfiguration>::load(
+                application_folder,
+                CONFIG_FILE_NAME,
+            )?;
+        let configuration =
+            Self::resolve_configuration(&configuration_manager, application_folder)?;
+        if configuration.api_key.is_plain() {
+            warn!("The philips hue api key is stored in plain text, consider moving it to the secrets file, an environment variable or a systemd credential.");
+        }
+        let configuration = Arc::new(RwLock::new(configuration));
+
+        let api_client = ApiClient::new(configuration.clone(), status_update_sender.clone());
 
-        let api_client = ApiClient::new(configuration_manager.get_configuration().clone());
+        let (change_sender, change_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let configuration_change_sender = change_sender.clone();
+        let configuration_watcher =
+            configuration_manager.watch(CONFIGURATION_WATCH_DEBOUNCE, move || {
+                if let Err(err) = change_sender.send(()) {
+                    error!("Could not report changed philips hue config: {}.", err);
+                }
+            })?;
+        tokio::spawn(Self::apply_configuration_changes(
+            change_receiver,
+            configuration_manager,
+            application_folder.to_owned(),
+            configuration.clone(),
+            status_update_sender,
+        ));
 
         Ok(PhilipsHueAutomationModule {
             api_client,
-            configuration: configuration_manager.get_configuration().clone(),
+            configuration,
+            configuration_change_sender,
+            _configuration_watcher: configuration_watcher,
         })
     }
 
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
     fn get_routes(&self) -> Option<Router> {
         let hue_state = HueState {
             request_sender: self.api_client.request_sender(),
-            configuration: Arc::new(self.configuration.clone()),
+            configuration: self.configuration.clone(),
         };
         Some(
             Router::new().nest(
                 "/philipshue",
                 Router::new()
+                    .route("/configuration", axum::routing::get(get_configuration))
                     .route("/groups", axum::routing::get(get_groups))
                     .route("/groups", axum::routing::put(configure_group))
                     .route("/presets", axum::routing::get(get_presets))
@@ -68,4 +177,11 @@ impl AutomationModule for PhilipsHueAutomationModule {
     fn send_initial_state(&self, _: usize) -> anyhow::Result<()> {
         Ok(())
     }
+
+    /// The configuration is reloaded by the same task which applies changes of the file.
+    fn reload_configuration(&mut self) -> anyhow::Result<()> {
+        self.configuration_change_sender
+            .send(())
+            .map_err(|err| anyhow!("Could not reload philips hue config: {}", err))
+    }
 }
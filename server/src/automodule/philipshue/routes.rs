@@ -3,14 +3,14 @@ use crate::automodule::philipshue::config::PhilipsHueAutomationModuleConfigurati
 use axum::extract::State;
 use axum::Json;
 use hyper::StatusCode;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::UnboundedSender;
 use ts_rs::TS;
 
 #[derive(Clone)]
 pub struct HueState {
     pub(super) request_sender: UnboundedSender<ConfigureHueGroupedLightRequest>,
-    pub(super) configuration: Arc<PhilipsHueAutomationModuleConfiguration>,
+    pub(super) configuration: Arc<RwLock<PhilipsHueAutomationModuleConfiguration>>,
 }
 
 #[derive(Serialize, Deserialize, TS)]
@@ -37,6 +37,8 @@ pub async fn get_groups(State(state): State<HueState>) -> Json<Vec<PhilipsHueGro
     Json(
         state
             .configuration
+            .read()
+            .unwrap()
             .groups
             .iter()
             .map(|group| PhilipsHueGroupDto {
@@ -51,6 +53,8 @@ pub async fn get_presets(State(state): State<HueState>) -> Json<Vec<PhilipsHuePr
     Json(
         state
             .configuration
+            .read()
+            .unwrap()
             .presets
             .iter()
             .map(|preset| PhilipsHuePresetDto {
@@ -63,12 +67,20 @@ pub async fn get_presets(State(state): State<HueState>) -> Json<Vec<PhilipsHuePr
     )
 }
 
+/// Secrets are redacted.
+pub async fn get_configuration(
+    State(state): State<HueState>,
+) -> Json<PhilipsHueAutomationModuleConfiguration> {
+    Json(state.configuration.read().unwrap().redacted())
+}
+
 pub async fn configure_group(
     State(state): State<HueState>,
     Json(dto): Json<PhilipsHueConfigureGroupDto>,
 ) -> Result<(), StatusCode> {
-    let group = state.configuration.find_group(&dto.group_id);
-    let preset = state.configuration.find_preset(&dto.preset_id);
+    let configuration = state.configuration.read().unwrap();
+    let group = configuration.find_group(&dto.group_id);
+    let preset = configuration.find_preset(&dto.preset_id);
 
     if let Some(group) = group {
         if let Some(preset) = preset {
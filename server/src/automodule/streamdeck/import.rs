@@ -0,0 +1,644 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Context};
+use home_automation_common::automacro::AutomationMacro;
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckAutomationConfiguration, StreamdeckButtonConfiguration, StreamdeckFolder,
+    StreamdeckFolderAction, StreamdeckKeyStyle, StreamdeckModel, StreamdeckVerticalAlignment,
+};
+
+use crate::archive::read_limited_archive;
+use crate::assets::AssetType;
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+const OPEN_FOLDER_ACTION: &str = "com.elgato.streamdeck.profile.openchild";
+const BACK_TO_PARENT_ACTION: &str = "com.elgato.streamdeck.profile.backtoparent";
+const PROFILE_FILE_SIZE_LIMIT: u64 = 20 * 1024 * 1024;
+const PROFILE_EXTRACTED_SIZE_LIMIT: u64 = 200 * 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProfileManifest {
+    name: Option<String>,
+    device_model: Option<String>,
+    #[serde(default)]
+    actions: HashMap<String, Option<ProfileAction>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProfileAction {
+    name: Option<String>,
+    #[serde(rename = "UUID")]
+    uuid: String,
+    #[serde(default)]
+    settings: serde_json::Value,
+    #[serde(default)]
+    state: usize,
+    #[serde(default)]
+    states: Vec<ProfileActionState>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+struct ProfileActionState {
+    title: Option<String>,
+    image: Option<String>,
+    show_title: Option<bool>,
+    title_color: Option<String>,
+    title_alignment: Option<String>,
+    /* stored as string or number depending on the version of the Elgato app */
+    #[serde(rename = "FSize")]
+    font_size: Option<serde_json::Value>,
+}
+
+/// An Elgato profile converted to a button configuration, the images still have to be stored as assets.
+pub struct ImportedProfile {
+    pub configuration: StreamdeckAutomationConfiguration,
+    pub images: Vec<(String, Vec<u8>)>,
+    pub report: StreamdeckProfileImportReport,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckProfileImportReport {
+    pub device_id: String,
+    pub model: StreamdeckModel,
+    pub imported_buttons: usize,
+    pub imported_folders: usize,
+    pub imported_images: usize,
+    /* Actions which have no counterpart in the home automation, their buttons do not execute anything. */
+    pub placeholders: Vec<StreamdeckImportPlaceholder>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamdeckImportPlaceholder {
+    pub folder_id: Option<String>,
+    pub key: u8,
+    pub action: String,
+    pub title: String,
+}
+
+/// Converts an Elgato `.streamDeckProfile` archive into the button configuration of the given device.
+/// The model is detected from the profile if it is not given.
+pub fn import_profile(
+    archive: &[u8],
+    device_id: &str,
+    model: Option<StreamdeckModel>,
+) -> anyhow::Result<ImportedProfile> {
+    let files = read_archive(archive)?;
+    let root_manifest_path = files
+        .keys()
+        .filter(|path| path.ends_with(MANIFEST_FILE_NAME))
+        .min_by_key(|path| path.matches('/').count())
+        .context("Could not find manifest in profile archive.")?
+        .clone();
+    let manifest = parse_manifest(&files, &root_manifest_path)?;
+    let model = model
+        .or_else(|| manifest.device_model.as_deref().and_then(get_model))
+        .context("Could not detect the streamdeck model of the profile, please specify it.")?;
+
+    let mut importer = ProfileImporter {
+        files: &files,
+        device_id,
+        model,
+        folders: Vec::new(),
+        visited_folders: HashSet::new(),
+        images: Vec::new(),
+        report: StreamdeckProfileImportReport {
+            device_id: device_id.to_owned(),
+            model,
+            imported_buttons: 0,
+            imported_folders: 0,
+            imported_images: 0,
+            placeholders: Vec::new(),
+            warnings: Vec::new(),
+        },
+    };
+    let button_configurations =
+        importer.import_buttons(get_directory(&root_manifest_path), &manifest, None);
+    let mut report = importer.report;
+    report.imported_folders = importer.folders.len();
+    report.imported_images = importer.images.len();
+
+    Ok(ImportedProfile {
+        configuration: StreamdeckAutomationConfiguration {
+            device_name: manifest.name.unwrap_or_else(|| device_id.to_owned()),
+            button_configurations,
+            folders: importer.folders,
+            ..Default::default()
+        },
+        images: importer.images,
+        report,
+    })
+}
+
+struct ProfileImporter<'a> {
+    files: &'a HashMap<String, Vec<u8>>,
+    device_id: &'a str,
+    model: StreamdeckModel,
+    folders: Vec<StreamdeckFolder>,
+    visited_folders: HashSet<String>,
+    images: Vec<(String, Vec<u8>)>,
+    report: StreamdeckProfileImportReport,
+}
+
+impl ProfileImporter<'_> {
+    fn import_buttons(
+        &mut self,
+        directory: &str,
+        manifest: &ProfileManifest,
+        folder_id: Option<&str>,
+    ) -> Vec<StreamdeckButtonConfiguration> {
+        let mut positions: Vec<&String> = manifest.actions.keys().collect();
+        positions.sort();
+
+        let mut button_configurations = Vec::new();
+        for position in positions {
+            let action = match &manifest.actions[position] {
+                Some(action) => action,
+                None => continue,
+            };
+            let key = match parse_position(position)
+                .and_then(|(column, row)| self.model.key_index(column, row))
+            {
+                Some(key) => key,
+                None => {
+                    self.report.warnings.push(format!(
+                        "Skipped action {} at position {} which does not exist on a {:?} streamdeck.",
+                        action.uuid, position, self.model
+                    ));
+                    continue;
+                }
+            };
+            button_configurations
+                .push(self.import_button(directory, position, key, action, folder_id));
+        }
+        self.report.imported_buttons += button_configurations.len();
+        button_configurations
+    }
+
+    fn import_button(
+        &mut self,
+        directory: &str,
+        position: &str,
+        key: u8,
+        action: &ProfileAction,
+        folder_id: Option<&str>,
+    ) -> StreamdeckButtonConfiguration {
+        let default_state = ProfileActionState::default();
+        let state = action
+            .states
+            .get(action.state)
+            .or_else(|| action.states.first())
+            .unwrap_or(&default_state);
+        let title = state
+            .title
+            .as_deref()
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .or(action.name.as_deref())
+            .unwrap_or_default()
+            .to_owned();
+
+        let folder_action = match action.uuid.as_str() {
+            OPEN_FOLDER_ACTION => self.import_folder(action, &title),
+            BACK_TO_PARENT_ACTION => Some(StreamdeckFolderAction::Back),
+            _ => None,
+        };
+        if folder_action.is_none() {
+            self.report.placeholders.push(StreamdeckImportPlaceholder {
+                folder_id: folder_id.map(str::to_owned),
+                key,
+                action: action.uuid.clone(),
+                title: title.clone(),
+            });
+        }
+
+        StreamdeckButtonConfiguration {
+            key,
+            text: if state.show_title == Some(false) {
+                String::new()
+            } else {
+                title.clone()
+            },
+            image: self.import_image(directory, position, action.state, state, folder_id),
+            style: get_style(state),
+            state_binding: None,
+            press_macro: AutomationMacro::new(title, vec![]),
+            release_macro: None,
+            long_press_macro: None,
+            double_press_macro: None,
+            repeat_macro: None,
+            gesture_timing: None,
+            offline_action: None,
+            offline_queue_seconds: None,
+            folder_action,
+            live_key: None,
+        }
+    }
+
+    /// Folders are stored as child profiles which are referenced by their UUID.
+    fn import_folder(
+        &mut self,
+        action: &ProfileAction,
+        title: &str,
+    ) -> Option<StreamdeckFolderAction> {
+        let folder_id = action
+            .settings
+            .get("ProfileUUID")
+            .and_then(|uuid| uuid.as_str())
+            .map(|uuid| uuid.to_lowercase())?;
+        if self.visited_folders.insert(folder_id.clone()) {
+            let manifest_suffix = format!("/{}.sdprofile/{}", folder_id, MANIFEST_FILE_NAME);
+            let manifest_path = self
+                .files
+                .keys()
+                .find(|path| path.to_lowercase().ends_with(&manifest_suffix))
+                .cloned();
+            let manifest = match manifest_path
+                .as_ref()
+                .map(|manifest_path| parse_manifest(self.files, manifest_path))
+            {
+                Some(Ok(manifest)) => manifest,
+                Some(Err(err)) => {
+                    self.report
+                        .warnings
+                        .push(format!("Could not import folder {}: {}", title, err));
+                    return None;
+                }
+                None => {
+                    self.report
+                        .warnings
+                        .push(format!("Could not find the content of folder {}.", title));
+                    return None;
+                }
+            };
+
+            let button_configurations = self.import_buttons(
+                get_directory(manifest_path.as_deref().unwrap_or_default()),
+                &manifest,
+                Some(&folder_id),
+            );
+            self.folders.push(StreamdeckFolder {
+                id: folder_id.clone(),
+                name: manifest
+                    .name
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| title.to_owned()),
+                button_configurations,
+            });
+        }
+        Some(StreamdeckFolderAction::Open { folder_id })
+    }
+
+    /// Custom icons are either referenced by the state or stored next to the manifest per position and state.
+    fn import_image(
+        &mut self,
+        directory: &str,
+        position: &str,
+        state_index: usize,
+        state: &ProfileActionState,
+        folder_id: Option<&str>,
+    ) -> Option<String> {
+        let image_path = match state.image.as_deref().filter(|image| !image.is_empty()) {
+            Some(image) => format!("{}{}", directory, image),
+            None => format!("{}{}/CState{}/image.png", directory, position, state_index),
+        };
+        let content = self.files.get(&image_path)?;
+        let asset_type = match AssetType::from_content(content) {
+            Some(asset_type) => asset_type,
+            None => {
+                self.report.warnings.push(format!(
+                    "Skipped image {} which is neither a PNG nor a JPEG.",
+                    image_path
+                ));
+                return None;
+            }
+        };
+
+        let asset_name = get_asset_name(
+            &format!(
+                "{}_{}_{}",
+                self.device_id,
+                folder_id.unwrap_or("root"),
+                position.replace(',', "_")
+            ),
+            asset_type,
+        );
+        self.images.push((asset_name.clone(), content.clone()));
+        Some(asset_name)
+    }
+}
+
+fn read_archive(archive: &[u8]) -> anyhow::Result<HashMap<String, Vec<u8>>> {
+    let files = read_limited_archive(
+        archive,
+        "profile",
+        PROFILE_FILE_SIZE_LIMIT,
+        PROFILE_EXTRACTED_SIZE_LIMIT,
+    )?;
+    // profiles which were exported on Windows use backslashes as separator
+    Ok(files
+        .into_iter()
+        .map(|(name, content)| (name.replace('\\', "/"), content))
+        .collect())
+}
+
+fn parse_manifest(
+    files: &HashMap<String, Vec<u8>>,
+    manifest_path: &str,
+) -> anyhow::Result<ProfileManifest> {
+    let content = files
+        .get(manifest_path)
+        .ok_or_else(|| anyhow!("Could not find manifest {}.", manifest_path))?;
+    serde_json::from_slice(content)
+        .with_context(|| format!("Could not parse manifest {}.", manifest_path))
+}
+
+/// Returns the directory of the path including the trailing slash.
+fn get_directory(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(index) => &path[..=index],
+        None => "",
+    }
+}
+
+/// Positions are given as column and row, e.g. "2,1".
+fn parse_position(position: &str) -> Option<(u8, u8)> {
+    let (column, row) = position.split_once(',')?;
+    Some((column.trim().parse().ok()?, row.trim().parse().ok()?))
+}
+
+fn get_model(device_model: &str) -> Option<StreamdeckModel> {
+    match device_model {
+        "20GAA9901" => Some(StreamdeckModel::Original),
+        "20GAA9902" => Some(StreamdeckModel::OriginalV2),
+        "20GAI9901" => Some(StreamdeckModel::Mini),
+        "20GAT9901" => Some(StreamdeckModel::Xl),
+        "20GBA9901" => Some(StreamdeckModel::Mk2),
+        _ => None,
+    }
+}
+
+fn get_style(state: &ProfileActionState) -> Option<StreamdeckKeyStyle> {
+    let style = StreamdeckKeyStyle {
+        foreground_color: state
+            .title_color
+            .as_deref()
+            .map(|color| color.trim_start_matches('#').to_uppercase())
+            .filter(|color| color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit())),
+        font_size: state
+            .font_size
+            .as_ref()
+            .and_then(|font_size| match font_size {
+                serde_json::Value::Number(number) => {
+                    number.as_u64().and_then(|size| size.try_into().ok())
+                }
+                serde_json::Value::String(size) => size.parse().ok(),
+                _ => None,
+            }),
+        vertical_alignment: match state.title_alignment.as_deref() {
+            Some("top") => Some(StreamdeckVerticalAlignment::Top),
+            Some("middle") => Some(StreamdeckVerticalAlignment::Middle),
+            Some("bottom") => Some(StreamdeckVerticalAlignment::Bottom),
+            _ => None,
+        },
+        ..Default::default()
+    };
+    (style != StreamdeckKeyStyle::default()).then_some(style)
+}
+
+/// Replaces all characters which are not allowed in asset names.
+fn get_asset_name(name: &str, asset_type: AssetType) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.{}", name, asset_type.extension())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use home_automation_common::automodule::streamdeck::{
+        StreamdeckDeviceConfiguration, StreamdeckDevicesConfiguration, StreamdeckLayout,
+    };
+    use home_automation_common::config::ConfigurationManager;
+    use home_automation_common::fs;
+    use zip::write::FileOptions;
+
+    use crate::assets::AssetStore;
+    use crate::automodule::streamdeck::{import_streamdeck_profile, CONFIG_FILE_NAME};
+
+    use super::*;
+
+    const PNG_IMAGE: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 1, 2, 3];
+    const FOLDER_UUID: &str = "2A9F1B6C-0C3E-4C4B-9D8D-3E1B6B0A7F11";
+
+    fn create_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn profile_archive() -> Vec<u8> {
+        let root_manifest = serde_json::json!({
+            "Name": "Living room",
+            "DeviceModel": "20GAA9902",
+            "Actions": {
+                "0,0": {
+                    "Name": "Website",
+                    "UUID": "com.elgato.streamdeck.system.website",
+                    "Settings": { "path": "https://example.com" },
+                    "State": 0,
+                    "States": [{ "Title": "News", "TitleColor": "#ffcc00", "TitleAlignment": "bottom", "FSize": "12" }]
+                },
+                "1,0": {
+                    "Name": "Create Folder",
+                    "UUID": OPEN_FOLDER_ACTION,
+                    "Settings": { "ProfileUUID": FOLDER_UUID },
+                    "States": [{ "Title": "Lights" }]
+                },
+                "7,3": {
+                    "Name": "Hotkey",
+                    "UUID": "com.elgato.streamdeck.system.hotkey",
+                    "States": [{}]
+                }
+            }
+        });
+        let folder_manifest = serde_json::json!({
+            "Name": "",
+            "Actions": {
+                "0,0": { "UUID": BACK_TO_PARENT_ACTION, "States": [{}] },
+                "2,1": {
+                    "Name": "Multi Action",
+                    "UUID": "com.elgato.streamdeck.multiactions",
+                    "States": [{ "Title": "All off", "ShowTitle": false }]
+                }
+            }
+        });
+        let root_manifest = root_manifest.to_string();
+        let folder_manifest = folder_manifest.to_string();
+        let folder_manifest_path = format!(
+            "ABC.sdProfile/Profiles/{}.sdProfile/manifest.json",
+            FOLDER_UUID
+        );
+        create_archive(&[
+            ("ABC.sdProfile/manifest.json", root_manifest.as_bytes()),
+            ("ABC.sdProfile/0,0/CState0/image.png", PNG_IMAGE),
+            ("ABC.sdProfile/1,0/CState0/image.png", b"no image"),
+            (&folder_manifest_path, folder_manifest.as_bytes()),
+        ])
+    }
+
+    #[test]
+    fn import_elgato_profile() {
+        let imported_profile = import_profile(&profile_archive(), "living room", None).unwrap();
+        let configuration = &imported_profile.configuration;
+        let folder_id = FOLDER_UUID.to_lowercase();
+
+        assert_eq!("Living room", configuration.device_name);
+        assert!(configuration.validate(StreamdeckModel::OriginalV2).is_ok());
+        let website_button = configuration.find_button_configuration(None, 0).unwrap();
+        assert_eq!("News", website_button.text);
+        assert_eq!(
+            Some("living_room_root_0_0.png".to_owned()),
+            website_button.image
+        );
+        let style = website_button.style.clone().unwrap();
+        assert_eq!(Some("FFCC00".to_owned()), style.foreground_color);
+        assert_eq!(Some(12), style.font_size);
+        assert_eq!(
+            Some(StreamdeckVerticalAlignment::Bottom),
+            style.vertical_alignment
+        );
+        assert_eq!(
+            Some(StreamdeckFolderAction::Open {
+                folder_id: folder_id.clone()
+            }),
+            configuration
+                .find_button_configuration(None, 1)
+                .unwrap()
+                .folder_action
+        );
+
+        assert_eq!(1, configuration.folders.len());
+        assert_eq!("Lights", configuration.folders[0].name);
+        assert_eq!(
+            Some(StreamdeckFolderAction::Back),
+            configuration
+                .find_button_configuration(Some(&folder_id), 0)
+                .unwrap()
+                .folder_action
+        );
+        let placeholder_button = configuration
+            .find_button_configuration(Some(&folder_id), 7)
+            .unwrap();
+        assert_eq!("", placeholder_button.text);
+        assert!(placeholder_button.press_macro.actions.is_empty());
+
+        assert_eq!(
+            vec![("living_room_root_0_0.png".to_owned(), PNG_IMAGE.to_vec())],
+            imported_profile.images
+        );
+        let report = imported_profile.report;
+        assert_eq!(StreamdeckModel::OriginalV2, report.model);
+        assert_eq!(4, report.imported_buttons);
+        assert_eq!(1, report.imported_folders);
+        assert_eq!(1, report.imported_images);
+        assert_eq!(
+            vec![
+                StreamdeckImportPlaceholder {
+                    folder_id: None,
+                    key: 0,
+                    action: "com.elgato.streamdeck.system.website".to_owned(),
+                    title: "News".to_owned(),
+                },
+                StreamdeckImportPlaceholder {
+                    folder_id: Some(folder_id),
+                    key: 7,
+                    action: "com.elgato.streamdeck.multiactions".to_owned(),
+                    title: "All off".to_owned(),
+                },
+            ],
+            report.placeholders
+        );
+        // the hotkey at 7,3 does not fit on the deck and the folder icon is not an image
+        assert_eq!(2, report.warnings.len());
+    }
+
+    #[test]
+    fn import_profile_for_other_model() {
+        let imported_profile =
+            import_profile(&profile_archive(), "mini", Some(StreamdeckModel::Mini)).unwrap();
+        assert!(imported_profile
+            .configuration
+            .validate(StreamdeckModel::Mini)
+            .is_ok());
+        assert_eq!(StreamdeckModel::Mini, imported_profile.report.model);
+    }
+
+    #[test]
+    fn keep_assets_of_rejected_import() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let asset_store = AssetStore::new(&path).unwrap();
+        let current_image = [PNG_IMAGE, &[4]].concat();
+        asset_store
+            .store_asset("living_room_root_0_0.png", &current_image)
+            .unwrap();
+        let mut devices_configuration_manager =
+            ConfigurationManager::<StreamdeckDevicesConfiguration>::load(&path, CONFIG_FILE_NAME)
+                .unwrap();
+        // a layout id which is used twice makes the whole configuration invalid
+        let layout = StreamdeckLayout {
+            id: "night".to_owned(),
+            rules: Vec::new(),
+            configuration: StreamdeckAutomationConfiguration::default(),
+        };
+        devices_configuration_manager.set_configuration(StreamdeckDevicesConfiguration {
+            devices: vec![StreamdeckDeviceConfiguration {
+                device_id: "living room".to_owned(),
+                configuration: StreamdeckAutomationConfiguration::default(),
+                layouts: vec![layout.clone(), layout],
+            }],
+        });
+
+        assert!(import_streamdeck_profile(
+            &mut devices_configuration_manager,
+            &asset_store,
+            &profile_archive(),
+            "living room",
+            None,
+        )
+        .is_err());
+        assert_eq!(
+            current_image,
+            asset_store
+                .load_asset("living_room_root_0_0.png")
+                .unwrap()
+                .1
+        );
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_archive_without_manifest() {
+        let archive = create_archive(&[("ABC.sdProfile/0,0/CState0/image.png", PNG_IMAGE)]);
+        assert!(import_profile(&archive, "test", None).is_err());
+        assert!(import_profile(b"no archive", "test", None).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use chrono::{Datelike, NaiveDateTime, Weekday};
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckDeviceConfiguration, StreamdeckDevicesConfiguration, StreamdeckLayout,
+    StreamdeckLayoutRule, StreamdeckWeekday,
+};
+
+/// Decides which layout every streamdeck shows, depending on the time of day, the server mode and manual overrides.
+#[derive(Default)]
+pub struct StreamdeckLayoutSelector {
+    mode: Option<String>,
+    overrides: HashMap<String, String>,
+    active_layouts: HashMap<String, Option<String>>,
+}
+
+impl StreamdeckLayoutSelector {
+    pub fn set_mode(&mut self, mode: Option<String>) {
+        self.mode = mode;
+    }
+
+    /// Overrides the automatically selected layout of the device, no layout id removes the override.
+    pub fn select_layout(
+        &mut self,
+        devices_configuration: &StreamdeckDevicesConfiguration,
+        device_id: &str,
+        layout_id: Option<String>,
+    ) -> anyhow::Result<()> {
+        match layout_id {
+            Some(layout_id) => {
+                let layout_exists = devices_configuration
+                    .devices
+                    .iter()
+                    .filter(|device| device.device_id.eq(device_id))
+                    .flat_map(|device| device.layouts.iter())
+                    .any(|layout| layout.id.eq(&layout_id));
+                if !layout_exists {
+                    return Err(anyhow!(
+                        "Streamdeck {} has no layout {}.",
+                        device_id,
+                        layout_id
+                    ));
+                }
+                self.overrides.insert(device_id.to_owned(), layout_id);
+            }
+            None => {
+                self.overrides.remove(device_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the configuration of every device with its active layout and remembers which layouts are shown.
+    pub fn resolve(
+        &mut self,
+        devices_configuration: &StreamdeckDevicesConfiguration,
+        now: NaiveDateTime,
+    ) -> StreamdeckDevicesConfiguration {
+        let devices = devices_configuration
+            .devices
+            .iter()
+            .map(|device| {
+                let active_layout = self.get_active_layout(device, now);
+                self.active_layouts.insert(
+                    device.device_id.clone(),
+                    active_layout.map(|layout| layout.id.clone()),
+                );
+                let configuration = match active_layout {
+                    Some(layout) => {
+                        let mut configuration = layout.configuration.clone();
+                        configuration.device_name = device.configuration.device_name.clone();
+                        configuration
+                    }
+                    None => device.configuration.clone(),
+                };
+                // the clients only need the configuration which is shown
+                StreamdeckDeviceConfiguration {
+                    device_id: device.device_id.clone(),
+                    configuration,
+                    layouts: Vec::new(),
+                }
+            })
+            .collect();
+        StreamdeckDevicesConfiguration { devices }
+    }
+
+    /// Whether the active layout of any device differs from the one which was resolved last.
+    pub fn layouts_changed(
+        &self,
+        devices_configuration: &StreamdeckDevicesConfiguration,
+        now: NaiveDateTime,
+    ) -> bool {
+        devices_configuration.devices.iter().any(|device| {
+            let active_layout = self
+                .get_active_layout(device, now)
+                .map(|layout| layout.id.clone());
+            self.active_layouts.get(&device.device_id) != Some(&active_layout)
+        })
+    }
+
+    fn get_active_layout<'a>(
+        &self,
+        device: &'a StreamdeckDeviceConfiguration,
+        now: NaiveDateTime,
+    ) -> Option<&'a StreamdeckLayout> {
+        if let Some(layout_id) = self.overrides.get(&device.device_id) {
+            if let Some(layout) = device.layouts.iter().find(|layout| layout.id.eq(layout_id)) {
+                return Some(layout);
+            }
+        }
+        device.layouts.iter().find(|layout| {
+            layout
+                .rules
+                .iter()
+                .any(|rule| rule_matches(rule, now, self.mode.as_deref()))
+        })
+    }
+}
+
+fn rule_matches(rule: &StreamdeckLayoutRule, now: NaiveDateTime, mode: Option<&str>) -> bool {
+    if rule.mode.is_some() && rule.mode.as_deref() != mode {
+        return false;
+    }
+    if !rule.weekdays.is_empty()
+        && !rule
+            .weekdays
+            .iter()
+            .any(|weekday| get_weekday(*weekday) == now.weekday())
+    {
+        return false;
+    }
+
+    // rules of validated configurations always have valid times
+    let (from, to) = match rule.get_time_range() {
+        Ok(time_range) => time_range,
+        Err(err) => {
+            warn!("Ignoring streamdeck layout rule: {}.", err);
+            return false;
+        }
+    };
+    let time = now.time();
+    match (from, to) {
+        (Some(from), Some(to)) if from <= to => from <= time && time < to,
+        // the range wraps around midnight
+        (Some(from), Some(to)) => from <= time || time < to,
+        (Some(from), None) => from <= time,
+        (None, Some(to)) => time < to,
+        (None, None) => true,
+    }
+}
+
+fn get_weekday(weekday: StreamdeckWeekday) -> Weekday {
+    match weekday {
+        StreamdeckWeekday::Monday => Weekday::Mon,
+        StreamdeckWeekday::Tuesday => Weekday::Tue,
+        StreamdeckWeekday::Wednesday => Weekday::Wed,
+        StreamdeckWeekday::Thursday => Weekday::Thu,
+        StreamdeckWeekday::Friday => Weekday::Fri,
+        StreamdeckWeekday::Saturday => Weekday::Sat,
+        StreamdeckWeekday::Sunday => Weekday::Sun,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use home_automation_common::automodule::streamdeck::StreamdeckAutomationConfiguration;
+
+    use super::*;
+
+    fn layout(id: &str, rules: Vec<StreamdeckLayoutRule>) -> StreamdeckLayout {
+        StreamdeckLayout {
+            id: id.to_owned(),
+            rules,
+            configuration: StreamdeckAutomationConfiguration::default(),
+        }
+    }
+
+    fn devices_configuration() -> StreamdeckDevicesConfiguration {
+        StreamdeckDevicesConfiguration {
+            devices: vec![StreamdeckDeviceConfiguration {
+                device_id: "desk".to_owned(),
+                configuration: StreamdeckAutomationConfiguration {
+                    device_name: "Desk".to_owned(),
+                    ..Default::default()
+                },
+                layouts: vec![
+                    layout(
+                        "guest",
+                        vec![StreamdeckLayoutRule {
+                            mode: Some("guest".to_owned()),
+                            ..Default::default()
+                        }],
+                    ),
+                    layout(
+                        "evening",
+                        vec![StreamdeckLayoutRule {
+                            from: Some("18:00".to_owned()),
+                            to: Some("02:00".to_owned()),
+                            ..Default::default()
+                        }],
+                    ),
+                    layout(
+                        "weekend_morning",
+                        vec![StreamdeckLayoutRule {
+                            to: Some("12:00".to_owned()),
+                            weekdays: vec![StreamdeckWeekday::Saturday, StreamdeckWeekday::Sunday],
+                            ..Default::default()
+                        }],
+                    ),
+                    layout("cleaning", Vec::new()),
+                ],
+            }],
+        }
+    }
+
+    // 2023-01-07 is a saturday
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn active_layout(
+        selector: &StreamdeckLayoutSelector,
+        configuration: &StreamdeckDevicesConfiguration,
+        now: NaiveDateTime,
+    ) -> Option<String> {
+        selector
+            .get_active_layout(&configuration.devices[0], now)
+            .map(|layout| layout.id.clone())
+    }
+
+    #[test]
+    fn select_layout_by_rules() {
+        let configuration = devices_configuration();
+        let mut selector = StreamdeckLayoutSelector::default();
+
+        assert_eq!(None, active_layout(&selector, &configuration, at(9, 9, 0)));
+        assert_eq!(
+            Some("weekend_morning".to_owned()),
+            active_layout(&selector, &configuration, at(7, 9, 0))
+        );
+        assert_eq!(
+            Some("evening".to_owned()),
+            active_layout(&selector, &configuration, at(9, 18, 0))
+        );
+        assert_eq!(
+            Some("evening".to_owned()),
+            active_layout(&selector, &configuration, at(10, 1, 59))
+        );
+        assert_eq!(None, active_layout(&selector, &configuration, at(10, 2, 0)));
+
+        selector.set_mode(Some("guest".to_owned()));
+        assert_eq!(
+            Some("guest".to_owned()),
+            active_layout(&selector, &configuration, at(9, 18, 0))
+        );
+    }
+
+    #[test]
+    fn override_and_resolve_layouts() {
+        let configuration = devices_configuration();
+        let mut selector = StreamdeckLayoutSelector::default();
+
+        let resolved = selector.resolve(&configuration, at(9, 9, 0));
+        assert_eq!(
+            configuration.devices[0].configuration,
+            resolved.devices[0].configuration
+        );
+        assert!(resolved.devices[0].layouts.is_empty());
+        assert!(!selector.layouts_changed(&configuration, at(9, 9, 0)));
+        assert!(selector.layouts_changed(&configuration, at(9, 19, 0)));
+
+        assert!(selector
+            .select_layout(&configuration, "desk", Some("unknown".to_owned()))
+            .is_err());
+        selector
+            .select_layout(&configuration, "desk", Some("cleaning".to_owned()))
+            .unwrap();
+        assert!(selector.layouts_changed(&configuration, at(9, 9, 0)));
+        let resolved = selector.resolve(&configuration, at(9, 19, 0));
+        assert_eq!(
+            Some(&Some("cleaning".to_owned())),
+            selector.active_layouts.get("desk")
+        );
+        assert_eq!("Desk", resolved.devices[0].configuration.device_name);
+
+        selector
+            .select_layout(&configuration, "desk", None)
+            .unwrap();
+        assert_eq!(
+            Some("evening".to_owned()),
+            active_layout(&selector, &configuration, at(9, 19, 0))
+        );
+    }
+}
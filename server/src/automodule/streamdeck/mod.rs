@@ -1,35 +1,299 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use axum::extract::DefaultBodyLimit;
+use axum::Router;
 use home_automation_common::action::{AutomationAction, AutomationStatusUpdate};
-use home_automation_common::automodule::streamdeck::StreamdeckDevicesConfiguration;
-use home_automation_common::config::ConfigurationManager;
-use tokio::sync::mpsc::UnboundedSender;
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckDeviceConfiguration, StreamdeckDevicesConfiguration, StreamdeckModel,
+};
+use home_automation_common::config::{
+    ConfigurationFormat, ConfigurationManager, ConfigurationWatcher,
+};
+use home_automation_common::state::{server_mode_state_id, AutomationState, AutomationStateValue};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-use crate::automodule::AutomationModule;
+use crate::assets::AssetStore;
+use crate::automodule::streamdeck::import::StreamdeckProfileImportReport;
+use crate::automodule::streamdeck::layout::StreamdeckLayoutSelector;
+use crate::automodule::streamdeck::routes::{import_profile, StreamdeckState};
+use crate::automodule::{AutomationModule, CONFIGURATION_WATCH_DEBOUNCE};
+use crate::backup::{BackupConfiguration, ConfigurationBackup};
 use crate::websocket::dto::AutomationServerStatusUpdate;
 
+mod import;
+mod layout;
+mod routes;
+
+const MODULE_NAME: &str = "streamdeck";
 const CONFIG_FILE_NAME: &str = "streamdeckDevicesConfig.json";
+const PROFILE_SIZE_LIMIT: usize = 50 * 1024 * 1024;
+const LAYOUT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 pub struct StreamdeckAutomationModule {
-    devices_configuration_manager: ConfigurationManager<StreamdeckDevicesConfiguration>,
+    devices_configuration_manager: Arc<Mutex<ConfigurationManager<StreamdeckDevicesConfiguration>>>,
+    layout_selector: Arc<Mutex<StreamdeckLayoutSelector>>,
+    asset_store: Arc<AssetStore>,
     status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
+    _configuration_watcher: ConfigurationWatcher,
 }
 
 impl StreamdeckAutomationModule {
     fn reload_devices_configuration(&mut self) -> anyhow::Result<()> {
-        self.devices_configuration_manager.reload_configuration()?;
-        let devices_configuration = self.devices_configuration_manager.get_configuration();
-        let update = AutomationServerStatusUpdate::broadcast(
-            AutomationStatusUpdate::StreamdeckClientReloadedDevicesConfiguration(
-                devices_configuration.clone(),
-            ),
+        let mut devices_configuration_manager = self.devices_configuration_manager.lock().unwrap();
+        devices_configuration_manager
+            .reload_changed_configuration(StreamdeckDevicesConfiguration::validate)?;
+        send_devices_configuration(
+            devices_configuration_manager.get_configuration(),
+            &self.layout_selector,
+            &self.status_update_sender,
+        )
+    }
+
+    fn set_server_mode(&mut self, mode: Option<String>) -> anyhow::Result<()> {
+        info!(
+            "Setting server mode to {}.",
+            mode.as_deref().unwrap_or("none")
+        );
+        let state = AutomationState::new(
+            server_mode_state_id(),
+            AutomationStateValue::Text(mode.clone().unwrap_or_default()),
         );
         self.status_update_sender
-            .send(update)
-            .map_err(|err| anyhow!("Could not send status update from BCP module: {}", err))?;
-        Ok(())
+            .send(AutomationServerStatusUpdate::broadcast(
+                AutomationStatusUpdate::StatesChanged {
+                    states: vec![state],
+                },
+            ))
+            .map_err(|err| anyhow!("Could not send server mode: {}", err))?;
+
+        let devices_configuration_manager = self.devices_configuration_manager.lock().unwrap();
+        self.layout_selector.lock().unwrap().set_mode(mode);
+        send_devices_configuration(
+            devices_configuration_manager.get_configuration(),
+            &self.layout_selector,
+            &self.status_update_sender,
+        )
     }
+
+    fn select_layout(&mut self, device_id: &str, layout_id: Option<String>) -> anyhow::Result<()> {
+        let devices_configuration_manager = self.devices_configuration_manager.lock().unwrap();
+        self.layout_selector.lock().unwrap().select_layout(
+            devices_configuration_manager.get_configuration(),
+            device_id,
+            layout_id,
+        )?;
+        send_devices_configuration(
+            devices_configuration_manager.get_configuration(),
+            &self.layout_selector,
+            &self.status_update_sender,
+        )
+    }
+}
+
+/// Sends the configurations of the currently active layouts to all clients.
+fn send_devices_configuration(
+    devices_configuration: &StreamdeckDevicesConfiguration,
+    layout_selector: &Mutex<StreamdeckLayoutSelector>,
+    status_update_sender: &UnboundedSender<AutomationServerStatusUpdate>,
+) -> anyhow::Result<()> {
+    let update = AutomationServerStatusUpdate::broadcast(
+        AutomationStatusUpdate::StreamdeckClientReloadedDevicesConfiguration(
+            layout_selector
+                .lock()
+                .unwrap()
+                .resolve(devices_configuration, chrono::Local::now().naive_local()),
+        ),
+    );
+    status_update_sender
+        .send(update)
+        .map_err(|err| anyhow!("Could not send status update from BCP module: {}", err))?;
+    Ok(())
+}
+
+/// Applies valid changes of the configuration file and sends them to the clients.
+async fn apply_configuration_changes(
+    mut change_receiver: UnboundedReceiver<()>,
+    devices_configuration_manager: Arc<Mutex<ConfigurationManager<StreamdeckDevicesConfiguration>>>,
+    layout_selector: Arc<Mutex<StreamdeckLayoutSelector>>,
+    status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
+) {
+    while change_receiver.recv().await.is_some() {
+        let mut devices_configuration_manager = devices_configuration_manager.lock().unwrap();
+        match devices_configuration_manager
+            .reload_changed_configuration(StreamdeckDevicesConfiguration::validate)
+        {
+            Ok(true) => {
+                info!("Applying changed streamdeck devices configuration.");
+                if let Err(err) = send_devices_configuration(
+                    devices_configuration_manager.get_configuration(),
+                    &layout_selector,
+                    &status_update_sender,
+                ) {
+                    error!("Could not send changed streamdeck devices config: {}.", err);
+                }
+            }
+            Ok(false) => {}
+            Err(err) => error!(
+                "Keeping the running streamdeck devices config, the changed file is invalid: {}",
+                err
+            ),
+        }
+    }
+}
+
+/// Pushes the new layouts to the clients when a time based rule starts or stops to match.
+async fn switch_layouts(
+    devices_configuration_manager: Arc<Mutex<ConfigurationManager<StreamdeckDevicesConfiguration>>>,
+    layout_selector: Arc<Mutex<StreamdeckLayoutSelector>>,
+    status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
+) {
+    let mut interval = tokio::time::interval(LAYOUT_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let devices_configuration_manager = devices_configuration_manager.lock().unwrap();
+        let devices_configuration = devices_configuration_manager.get_configuration();
+        let layouts_changed = layout_selector
+            .lock()
+            .unwrap()
+            .layouts_changed(devices_configuration, chrono::Local::now().naive_local());
+        if !layouts_changed {
+            continue;
+        }
+        info!("Switching streamdeck layouts.");
+        if let Err(err) = send_devices_configuration(
+            devices_configuration,
+            &layout_selector,
+            &status_update_sender,
+        ) {
+            error!("Could not send switched streamdeck layouts: {}.", err);
+        }
+    }
+}
+
+/// Replaces the button configuration of the device with the Elgato profile and stores the images of the profile as assets.
+pub fn import_streamdeck_profile(
+    devices_configuration_manager: &mut ConfigurationManager<StreamdeckDevicesConfiguration>,
+    asset_store: &AssetStore,
+    archive: &[u8],
+    device_id: &str,
+    model: Option<StreamdeckModel>,
+) -> anyhow::Result<StreamdeckProfileImportReport> {
+    let imported_profile = import::import_profile(archive, device_id, model)?;
+    let mut devices_configuration = devices_configuration_manager.get_configuration().clone();
+    let mut configuration = imported_profile.configuration;
+    match devices_configuration
+        .devices
+        .iter_mut()
+        .find(|device| device.device_id.eq(device_id))
+    {
+        Some(device) => {
+            // keep the name under which the device is known to the server
+            configuration.device_name = device.configuration.device_name.clone();
+            device.configuration = configuration;
+        }
+        None => devices_configuration
+            .devices
+            .push(StreamdeckDeviceConfiguration {
+                device_id: device_id.to_owned(),
+                configuration,
+                layouts: Vec::new(),
+            }),
+    }
+    devices_configuration
+        .validate()
+        .context("Could not import streamdeck profile.")?;
+
+    // the assets of the current configuration are reset if the imported profile can not be stored
+    let mut previous_assets = Vec::new();
+    for (name, _) in &imported_profile.images {
+        let previous_content = if asset_store.asset_exists(name) {
+            Some(asset_store.load_asset(name)?.1)
+        } else {
+            None
+        };
+        previous_assets.push((name, previous_content));
+    }
+    let previous_configuration = devices_configuration_manager.get_configuration().clone();
+    let result = imported_profile
+        .images
+        .iter()
+        .try_for_each(|(name, content)| asset_store.store_asset(name, content))
+        .and_then(|_| {
+            devices_configuration_manager.set_configuration(devices_configuration);
+            devices_configuration_manager
+                .persist_configuration()
+                .context("Could not persist imported streamdeck profile.")
+        });
+    if let Err(err) = result {
+        devices_configuration_manager.set_configuration(previous_configuration);
+        for (name, previous_content) in previous_assets {
+            let reset = match previous_content {
+                Some(previous_content) => asset_store.store_asset(name, &previous_content),
+                None => asset_store.remove_asset(name),
+            };
+            if let Err(reset_err) = reset {
+                error!(
+                    "Could not reset asset {} after failed profile import: {}",
+                    name, reset_err
+                );
+            }
+        }
+        return Err(err);
+    }
+    Ok(imported_profile.report)
+}
+
+/// Rewrites the device configuration in another format, a running server has to be restarted to use it.
+pub fn convert_streamdeck_configuration(
+    application_folder: &Path,
+    format: ConfigurationFormat,
+) -> anyhow::Result<PathBuf> {
+    ConfigurationManager::<StreamdeckDevicesConfiguration>::convert(
+        application_folder,
+        CONFIG_FILE_NAME,
+        format,
+    )
+}
+
+pub fn streamdeck_configuration_backup() -> ConfigurationBackup {
+    ConfigurationBackup::new::<StreamdeckDevicesConfiguration>(CONFIG_FILE_NAME)
+}
+
+impl BackupConfiguration for StreamdeckDevicesConfiguration {
+    fn validate_backup(&self) -> anyhow::Result<()> {
+        self.validate()
+    }
+}
+
+/// Imports a profile file from the command line, a running server picks the profile up when the device configuration is reloaded.
+pub fn import_streamdeck_profile_file(
+    application_folder: &Path,
+    profile_path: &Path,
+    device_id: &str,
+    model: Option<StreamdeckModel>,
+) -> anyhow::Result<StreamdeckProfileImportReport> {
+    let archive = std::fs::read(profile_path).with_context(|| {
+        format!(
+            "Could not read streamdeck profile {}.",
+            profile_path.display()
+        )
+    })?;
+    let mut devices_configuration_manager =
+        ConfigurationManager::<StreamdeckDevicesConfiguration>::load(
+            application_folder,
+            CONFIG_FILE_NAME,
+        )?;
+    let asset_store = AssetStore::new(application_folder)?;
+    import_streamdeck_profile(
+        &mut devices_configuration_manager,
+        &asset_store,
+        &archive,
+        device_id,
+        model,
+    )
 }
 
 impl AutomationModule for StreamdeckAutomationModule {
@@ -45,22 +309,97 @@ impl AutomationModule for StreamdeckAutomationModule {
                 application_folder,
                 CONFIG_FILE_NAME,
             )?;
+        let asset_store = AssetStore::new(application_folder)?;
+        let devices_configuration_manager = Arc::new(Mutex::new(devices_configuration_manager));
+        let layout_selector = Arc::new(Mutex::new(StreamdeckLayoutSelector::default()));
+        tokio::spawn(switch_layouts(
+            devices_configuration_manager.clone(),
+            layout_selector.clone(),
+            status_update_sender.clone(),
+        ));
+
+        let (change_sender, change_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let configuration_watcher = devices_configuration_manager.lock().unwrap().watch(
+            CONFIGURATION_WATCH_DEBOUNCE,
+            move || {
+                if let Err(err) = change_sender.send(()) {
+                    error!(
+                        "Could not report changed streamdeck devices config: {}.",
+                        err
+                    );
+                }
+            },
+        )?;
+        tokio::spawn(apply_configuration_changes(
+            change_receiver,
+            devices_configuration_manager.clone(),
+            layout_selector.clone(),
+            status_update_sender.clone(),
+        ));
+
         Ok(StreamdeckAutomationModule {
             status_update_sender,
             devices_configuration_manager,
+            layout_selector,
+            asset_store: Arc::new(asset_store),
+            _configuration_watcher: configuration_watcher,
         })
     }
 
-    fn get_routes(&self) -> Option<axum::Router> {
-        None
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn get_routes(&self) -> Option<Router> {
+        let state = StreamdeckState {
+            devices_configuration_manager: self.devices_configuration_manager.clone(),
+            layout_selector: self.layout_selector.clone(),
+            asset_store: self.asset_store.clone(),
+            status_update_sender: self.status_update_sender.clone(),
+        };
+        Some(
+            Router::new().nest(
+                "/streamdeck",
+                Router::new()
+                    .route(
+                        "/devices/:device_id/profile",
+                        axum::routing::post(import_profile),
+                    )
+                    .layer(DefaultBodyLimit::max(PROFILE_SIZE_LIMIT))
+                    .with_state(state),
+            ),
+        )
     }
 
     fn handle_action(&mut self, automation_action: &AutomationAction) -> anyhow::Result<bool> {
         match automation_action {
             AutomationAction::StreamdeckClientReloadDeviceConfiguration => {
-                if let Err(err) = self.reload_devices_configuration() {
-                    error!("Could not reload streamdeck devices config: {}", err);
-                }
+                self.reload_devices_configuration().map_err(|err| {
+                    anyhow!("Could not reload streamdeck devices config: {}", err)
+                })?;
+                Ok(true)
+            }
+            AutomationAction::StreamdeckClientControlDisplay { device_id, command } => {
+                let update = AutomationServerStatusUpdate::broadcast(
+                    AutomationStatusUpdate::StreamdeckClientDisplayCommand {
+                        device_id: device_id.clone(),
+                        command: command.clone(),
+                    },
+                );
+                self.status_update_sender
+                    .send(update)
+                    .map_err(|err| anyhow!("Could not send streamdeck display command: {}", err))?;
+                Ok(true)
+            }
+            AutomationAction::SetServerMode { mode } => {
+                self.set_server_mode(mode.clone())?;
+                Ok(true)
+            }
+            AutomationAction::StreamdeckClientSelectLayout {
+                device_id,
+                layout_id,
+            } => {
+                self.select_layout(device_id, layout_id.clone())?;
                 Ok(true)
             }
             _ => Ok(false),
@@ -68,11 +407,13 @@ impl AutomationModule for StreamdeckAutomationModule {
     }
 
     fn send_initial_state(&self, client_id: usize) -> anyhow::Result<()> {
+        let devices_configuration_manager = self.devices_configuration_manager.lock().unwrap();
         let update = AutomationServerStatusUpdate::single_client(
             AutomationStatusUpdate::StreamdeckClientReloadedDevicesConfiguration(
-                self.devices_configuration_manager
-                    .get_configuration()
-                    .clone(),
+                self.layout_selector.lock().unwrap().resolve(
+                    devices_configuration_manager.get_configuration(),
+                    chrono::Local::now().naive_local(),
+                ),
             ),
             client_id,
         );
@@ -84,4 +425,8 @@ impl AutomationModule for StreamdeckAutomationModule {
         }
         Ok(())
     }
+
+    fn reload_configuration(&mut self) -> anyhow::Result<()> {
+        self.reload_devices_configuration()
+    }
 }
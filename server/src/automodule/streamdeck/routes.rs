@@ -0,0 +1,61 @@
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckDevicesConfiguration, StreamdeckModel,
+};
+use home_automation_common::config::ConfigurationManager;
+use hyper::StatusCode;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::assets::AssetStore;
+use crate::automodule::streamdeck::import::StreamdeckProfileImportReport;
+use crate::automodule::streamdeck::layout::StreamdeckLayoutSelector;
+use crate::automodule::streamdeck::{import_streamdeck_profile, send_devices_configuration};
+use crate::websocket::dto::AutomationServerStatusUpdate;
+
+#[derive(Clone)]
+pub struct StreamdeckState {
+    pub(super) devices_configuration_manager:
+        Arc<Mutex<ConfigurationManager<StreamdeckDevicesConfiguration>>>,
+    pub(super) layout_selector: Arc<Mutex<StreamdeckLayoutSelector>>,
+    pub(super) asset_store: Arc<AssetStore>,
+    pub(super) status_update_sender: UnboundedSender<AutomationServerStatusUpdate>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportProfileQuery {
+    model: Option<StreamdeckModel>,
+}
+
+/// Imports an uploaded Elgato profile as configuration of the device and sends it to the connected clients.
+pub async fn import_profile(
+    State(state): State<StreamdeckState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<ImportProfileQuery>,
+    body: Bytes,
+) -> Result<Json<StreamdeckProfileImportReport>, (StatusCode, String)> {
+    let mut devices_configuration_manager = state.devices_configuration_manager.lock().unwrap();
+    let report = import_streamdeck_profile(
+        &mut devices_configuration_manager,
+        &state.asset_store,
+        &body,
+        &device_id,
+        query.model,
+    )
+    .map_err(|err| {
+        warn!("Could not import streamdeck profile: {}.", err);
+        (StatusCode::BAD_REQUEST, err.to_string())
+    })?;
+
+    if let Err(err) = send_devices_configuration(
+        devices_configuration_manager.get_configuration(),
+        &state.layout_selector,
+        &state.status_update_sender,
+    ) {
+        error!("Could not send imported streamdeck profile: {}.", err);
+    }
+    Ok(Json(report))
+}
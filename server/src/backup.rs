@@ -0,0 +1,562 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Extension, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::{Json, Router};
+use home_automation_common::config::{ConfigurationManager, VersionedConfiguration};
+use hyper::StatusCode;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::archive::read_limited_archive;
+use crate::assets::{validate_asset, AssetStore};
+use crate::auth::{require_admin, AuthenticatedClient};
+use crate::automodule::philipshue::philips_hue_configuration_backup;
+use crate::automodule::streamdeck::streamdeck_configuration_backup;
+use crate::automodule::AutomationModule;
+use crate::services::ServicesContext;
+
+const BACKUP_MANIFEST_NAME: &str = "backup.json";
+const BACKUP_VERSION: u32 = 1;
+const CONFIGURATIONS_FOLDER_NAME: &str = "config";
+const ASSETS_FOLDER_NAME: &str = "assets";
+const BACKUP_SIZE_LIMIT: usize = 200 * 1024 * 1024;
+const BACKUP_FILE_SIZE_LIMIT: u64 = 50 * 1024 * 1024;
+const BACKUP_EXTRACTED_SIZE_LIMIT: u64 = 500 * 1024 * 1024;
+
+/// Lists the content of the archive, restores only accept archives which contain exactly these files.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BackupManifest {
+    backup_version: u32,
+    created_at: String,
+    configurations: Vec<String>,
+    assets: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreReport {
+    pub configurations: Vec<String>,
+    pub assets: Vec<String>,
+}
+
+/// A module configuration which is part of backups.
+pub trait BackupConfiguration:
+    Default + Clone + Serialize + DeserializeOwned + VersionedConfiguration + JsonSchema + 'static
+{
+    fn validate_backup(&self) -> anyhow::Result<()>;
+
+    /// The configuration as it is written to the archive, plain secrets must not leave the server.
+    fn redact_secrets(&self) -> Self {
+        self.clone()
+    }
+
+    /// Keeps the secrets of the current configuration which were redacted in the archive.
+    fn restore_secrets(&mut self, _current: &Self) {}
+}
+
+/// The configuration file of a module, which is exported to and restored from backups.
+pub struct ConfigurationBackup {
+    config_file_name: &'static str,
+    export: fn(&Path, &str) -> anyhow::Result<String>,
+    prepare_restore: fn(&Path, &str, &str) -> anyhow::Result<Box<dyn RestoreStep>>,
+}
+
+impl ConfigurationBackup {
+    pub fn new<T>(config_file_name: &'static str) -> ConfigurationBackup
+    where
+        T: BackupConfiguration,
+    {
+        ConfigurationBackup {
+            config_file_name,
+            export: export_configuration::<T>,
+            prepare_restore: prepare_configuration_restore::<T>,
+        }
+    }
+}
+
+fn get_configuration_backups() -> Vec<ConfigurationBackup> {
+    vec![
+        philips_hue_configuration_backup(),
+        streamdeck_configuration_backup(),
+    ]
+}
+
+/// Writes the configurations of all modules and all assets to a zip archive. Configurations are always
+/// stored as JSON, restoring them keeps the format of the files on the server.
+pub fn create_backup(application_folder: &Path) -> anyhow::Result<Vec<u8>> {
+    let asset_store = AssetStore::new(application_folder)?;
+    let mut manifest = BackupManifest {
+        backup_version: BACKUP_VERSION,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        configurations: Vec::new(),
+        assets: Vec::new(),
+    };
+    let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for configuration_backup in get_configuration_backups() {
+        let name = configuration_backup.config_file_name;
+        let content = (configuration_backup.export)(application_folder, name)
+            .with_context(|| format!("Could not export configuration {}.", name))?;
+        archive.start_file(configuration_path(name), options)?;
+        archive.write_all(content.as_bytes())?;
+        manifest.configurations.push(name.to_owned());
+    }
+    for name in asset_store.list_assets()? {
+        let (_, content) = asset_store.load_asset(&name)?;
+        archive.start_file(asset_path(&name), options)?;
+        archive.write_all(&content)?;
+        manifest.assets.push(name);
+    }
+    archive.start_file(BACKUP_MANIFEST_NAME, options)?;
+    archive.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+
+    let archive = archive
+        .finish()
+        .context("Could not write backup archive.")?;
+    Ok(archive.into_inner())
+}
+
+/// Validates every file of the archive before anything is written. If writing a file fails, the files which
+/// were already restored are reset to their previous content. Assets which are not part of the archive are kept.
+pub fn restore_backup(application_folder: &Path, archive: &[u8]) -> anyhow::Result<RestoreReport> {
+    let mut files = read_archive(archive)?;
+    let manifest: BackupManifest = serde_json::from_slice(
+        &files
+            .remove(BACKUP_MANIFEST_NAME)
+            .ok_or_else(|| anyhow!("Backup does not contain {}.", BACKUP_MANIFEST_NAME))?,
+    )
+    .context("Could not parse backup manifest.")?;
+    if manifest.backup_version > BACKUP_VERSION {
+        return Err(anyhow!(
+            "Backup version {} is newer than the supported version {}.",
+            manifest.backup_version,
+            BACKUP_VERSION
+        ));
+    }
+
+    let configuration_backups = get_configuration_backups();
+    let mut steps: Vec<Box<dyn RestoreStep>> = Vec::new();
+    for name in &manifest.configurations {
+        let configuration_backup = configuration_backups
+            .iter()
+            .find(|configuration_backup| configuration_backup.config_file_name.eq(name))
+            .ok_or_else(|| anyhow!("Backup contains unknown configuration {}.", name))?;
+        let content = files
+            .remove(&configuration_path(name))
+            .ok_or_else(|| anyhow!("Configuration {} is missing in the backup.", name))?;
+        let content = String::from_utf8(content)
+            .with_context(|| format!("Configuration {} is not valid UTF-8.", name))?;
+        let step = (configuration_backup.prepare_restore)(application_folder, name, &content)
+            .with_context(|| format!("Invalid configuration {} in backup.", name))?;
+        steps.push(step);
+    }
+    let asset_store = Arc::new(AssetStore::new(application_folder)?);
+    for name in &manifest.assets {
+        let content = files
+            .remove(&asset_path(name))
+            .ok_or_else(|| anyhow!("Asset {} is missing in the backup.", name))?;
+        validate_asset(name, &content)?;
+        let previous_content = if asset_store.asset_exists(name) {
+            Some(asset_store.load_asset(name)?.1)
+        } else {
+            None
+        };
+        steps.push(Box::new(AssetRestore {
+            asset_store: asset_store.clone(),
+            name: name.clone(),
+            content,
+            previous_content,
+        }));
+    }
+    if let Some(unexpected_file) = files.keys().next() {
+        return Err(anyhow!(
+            "Backup contains file {} which is not listed in its manifest.",
+            unexpected_file
+        ));
+    }
+
+    apply_restore_steps(&mut steps)?;
+    Ok(RestoreReport {
+        configurations: manifest.configurations,
+        assets: manifest.assets,
+    })
+}
+
+fn apply_restore_steps(steps: &mut [Box<dyn RestoreStep>]) -> anyhow::Result<()> {
+    for index in 0..steps.len() {
+        if let Err(err) = steps[index].apply() {
+            for applied_step in steps[..index].iter_mut().rev() {
+                if let Err(rollback_err) = applied_step.rollback() {
+                    error!(
+                        "Could not reset {} after failed restore: {}",
+                        applied_step.name(),
+                        rollback_err
+                    );
+                }
+            }
+            return Err(err.context(format!(
+                "Could not restore {}, the previous state was kept.",
+                steps[index].name()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A validated change of the data folder, which can be undone after it was applied.
+trait RestoreStep {
+    fn name(&self) -> &str;
+    fn apply(&mut self) -> anyhow::Result<()>;
+    fn rollback(&mut self) -> anyhow::Result<()>;
+}
+
+struct ConfigurationRestore<T> {
+    name: String,
+    configuration_manager: ConfigurationManager<T>,
+    previous_configuration: T,
+    restored_configuration: T,
+}
+
+impl<T> RestoreStep for ConfigurationRestore<T>
+where
+    T: BackupConfiguration,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&mut self) -> anyhow::Result<()> {
+        self.configuration_manager
+            .set_configuration(self.restored_configuration.clone());
+        self.configuration_manager.persist_configuration()
+    }
+
+    fn rollback(&mut self) -> anyhow::Result<()> {
+        self.configuration_manager
+            .set_configuration(self.previous_configuration.clone());
+        self.configuration_manager.persist_configuration()
+    }
+}
+
+struct AssetRestore {
+    asset_store: Arc<AssetStore>,
+    name: String,
+    content: Vec<u8>,
+    previous_content: Option<Vec<u8>>,
+}
+
+impl RestoreStep for AssetRestore {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&mut self) -> anyhow::Result<()> {
+        self.asset_store.store_asset(&self.name, &self.content)
+    }
+
+    fn rollback(&mut self) -> anyhow::Result<()> {
+        match &self.previous_content {
+            Some(previous_content) => self.asset_store.store_asset(&self.name, previous_content),
+            None => self.asset_store.remove_asset(&self.name),
+        }
+    }
+}
+
+fn export_configuration<T>(
+    application_folder: &Path,
+    config_file_name: &str,
+) -> anyhow::Result<String>
+where
+    T: BackupConfiguration,
+{
+    let configuration_manager =
+        ConfigurationManager::<T>::load(application_folder, config_file_name)?;
+    ConfigurationManager::<T>::export_configuration(
+        &configuration_manager.get_configuration().redact_secrets(),
+    )
+}
+
+fn prepare_configuration_restore<T>(
+    application_folder: &Path,
+    config_file_name: &str,
+    content: &str,
+) -> anyhow::Result<Box<dyn RestoreStep>>
+where
+    T: BackupConfiguration,
+{
+    let mut restored_configuration = ConfigurationManager::<T>::import_configuration(content)?;
+    restored_configuration.validate_backup()?;
+    let configuration_manager =
+        ConfigurationManager::<T>::load(application_folder, config_file_name)?;
+    let previous_configuration = configuration_manager.get_configuration().clone();
+    restored_configuration.restore_secrets(&previous_configuration);
+    Ok(Box::new(ConfigurationRestore {
+        name: config_file_name.to_owned(),
+        configuration_manager,
+        previous_configuration,
+        restored_configuration,
+    }))
+}
+
+fn configuration_path(config_file_name: &str) -> String {
+    format!("{}/{}", CONFIGURATIONS_FOLDER_NAME, config_file_name)
+}
+
+fn asset_path(asset_name: &str) -> String {
+    format!("{}/{}", ASSETS_FOLDER_NAME, asset_name)
+}
+
+fn read_archive(archive: &[u8]) -> anyhow::Result<BTreeMap<String, Vec<u8>>> {
+    read_limited_archive(
+        archive,
+        "backup",
+        BACKUP_FILE_SIZE_LIMIT,
+        BACKUP_EXTRACTED_SIZE_LIMIT,
+    )
+}
+
+struct BackupState {
+    application_folder: PathBuf,
+    services_context: Arc<ServicesContext>,
+}
+
+pub fn get_routes(application_folder: &Path, services_context: Arc<ServicesContext>) -> Router {
+    Router::new()
+        .route("/backup", axum::routing::get(get_backup))
+        .route("/restore", axum::routing::post(restore))
+        .layer(DefaultBodyLimit::max(BACKUP_SIZE_LIMIT))
+        .with_state(Arc::new(BackupState {
+            application_folder: application_folder.to_owned(),
+            services_context,
+        }))
+}
+
+async fn get_backup(
+    State(state): State<Arc<BackupState>>,
+    Extension(client): Extension<AuthenticatedClient>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&client, "create backups")?;
+    let archive = create_backup(&state.application_folder).map_err(|err| {
+        error!("Could not create backup: {}.", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    })?;
+    let file_name = format!(
+        "home-automation-backup-{}.zip",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        ],
+        archive,
+    ))
+}
+
+/// Restores an archive of [get_backup] and reloads the configurations of all modules.
+async fn restore(
+    State(state): State<Arc<BackupState>>,
+    Extension(client): Extension<AuthenticatedClient>,
+    body: Bytes,
+) -> Result<Json<RestoreReport>, (StatusCode, String)> {
+    require_admin(&client, "restore backups")?;
+    // no actions are handled while the files are replaced
+    let mut modules = state.services_context.modules.lock().unwrap();
+    let report = restore_backup(&state.application_folder, &body).map_err(|err| {
+        warn!("Could not restore backup: {:#}.", err);
+        (StatusCode::BAD_REQUEST, format!("{:#}", err))
+    })?;
+    info!(
+        "Restored {} configurations and {} assets from backup.",
+        report.configurations.len(),
+        report.assets.len()
+    );
+    modules.reload_configuration().map_err(|err| {
+        error!("Could not reload modules after restoring backup: {}.", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    })?;
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use home_automation_common::config::{Secret, SecretSource};
+    use home_automation_common::fs;
+
+    use crate::auth::ClientRole;
+    use crate::automodule::philipshue::PhilipsHueAutomationModuleConfiguration;
+
+    use super::*;
+
+    const HUE_CONFIG_FILE_NAME: &str = "philipsHueConfig.json";
+    const PNG_CONTENT: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 1];
+
+    fn store_hue_configuration(path: &Path, bridge_ip: &str, api_key: &str) {
+        let mut configuration_manager = ConfigurationManager::<
+            PhilipsHueAutomationModuleConfiguration,
+        >::load(path, HUE_CONFIG_FILE_NAME)
+        .unwrap();
+        configuration_manager.set_configuration(PhilipsHueAutomationModuleConfiguration {
+            bridge_ip: bridge_ip.to_owned(),
+            api_key: Secret::new(SecretSource::Plain(api_key.to_owned())),
+            ..Default::default()
+        });
+        configuration_manager.persist_configuration().unwrap();
+    }
+
+    fn load_hue_configuration(path: &Path) -> PhilipsHueAutomationModuleConfiguration {
+        ConfigurationManager::<PhilipsHueAutomationModuleConfiguration>::load(
+            path,
+            HUE_CONFIG_FILE_NAME,
+        )
+        .unwrap()
+        .get_configuration()
+        .clone()
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let asset_store = AssetStore::new(&path).unwrap();
+        store_hue_configuration(&path, "10.0.0.2", "backed up key");
+        asset_store.store_asset("light.png", PNG_CONTENT).unwrap();
+
+        let archive = create_backup(&path).unwrap();
+        let files = read_archive(&archive).unwrap();
+        let hue_configuration =
+            String::from_utf8(files["config/philipsHueConfig.json"].clone()).unwrap();
+        assert!(hue_configuration.contains("\"api_key\": \"<redacted>\""));
+        assert_eq!(PNG_CONTENT, files["assets/light.png"]);
+
+        store_hue_configuration(&path, "10.0.0.3", "current key");
+        asset_store
+            .store_asset("light.png", &[PNG_CONTENT, &[2]].concat())
+            .unwrap();
+        asset_store.store_asset("other.png", PNG_CONTENT).unwrap();
+
+        let report = restore_backup(&path, &archive).unwrap();
+        assert_eq!(
+            vec!["philipsHueConfig.json", "streamdeckDevicesConfig.json"],
+            report.configurations
+        );
+        assert_eq!(vec!["light.png"], report.assets);
+        let hue_configuration = load_hue_configuration(&path);
+        assert_eq!("10.0.0.2", hue_configuration.bridge_ip);
+        // redacted secrets keep their current value
+        assert_eq!("current key", hue_configuration.api_key.expose().unwrap());
+        assert_eq!(PNG_CONTENT, asset_store.load_asset("light.png").unwrap().1);
+        assert!(asset_store.asset_exists("other.png"));
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_backup() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        store_hue_configuration(&path, "10.0.0.2", "key");
+        let archive = create_backup(&path).unwrap();
+        store_hue_configuration(&path, "10.0.0.3", "key");
+
+        let mut files = read_archive(&archive).unwrap();
+        files.insert("assets/broken.png".to_owned(), vec![1, 2, 3]);
+        let mut manifest: BackupManifest =
+            serde_json::from_slice(&files[BACKUP_MANIFEST_NAME]).unwrap();
+        manifest.assets.push("broken.png".to_owned());
+        files.insert(
+            BACKUP_MANIFEST_NAME.to_owned(),
+            serde_json::to_vec(&manifest).unwrap(),
+        );
+        assert!(restore_backup(&path, &write_archive(&files)).is_err());
+        assert_eq!("10.0.0.3", load_hue_configuration(&path).bridge_ip);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[test]
+    fn roll_back_failed_restore() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let asset_store = AssetStore::new(&path).unwrap();
+        store_hue_configuration(&path, "10.0.0.2", "key");
+        asset_store.store_asset("a.png", PNG_CONTENT).unwrap();
+        asset_store.store_asset("b.png", PNG_CONTENT).unwrap();
+        let archive = create_backup(&path).unwrap();
+
+        store_hue_configuration(&path, "10.0.0.3", "key");
+        asset_store.remove_asset("a.png").unwrap();
+        asset_store.remove_asset("b.png").unwrap();
+        // a folder with the name of an asset can not be replaced
+        std::fs::create_dir(path.join("assets").join("b.png")).unwrap();
+
+        assert!(restore_backup(&path, &archive).is_err());
+        assert_eq!("10.0.0.3", load_hue_configuration(&path).bridge_ip);
+        assert!(!asset_store.asset_exists("a.png"));
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restrict_backups_to_admins() {
+        let path = fs::util::prepare_temp_folder().unwrap();
+        let (status_update_sender, _) = tokio::sync::mpsc::unbounded_channel();
+        let modules =
+            crate::automodule::CompositeAutomationModule::new(&path, status_update_sender).unwrap();
+        let state = Arc::new(BackupState {
+            application_folder: path.clone(),
+            services_context: Arc::new(ServicesContext {
+                modules: Box::new(std::sync::Mutex::new(modules)),
+                states: Box::default(),
+                executions: Box::default(),
+            }),
+        });
+        let client = |role| AuthenticatedClient {
+            name: "desk".to_owned(),
+            role,
+        };
+
+        let (status, _) = get_backup(State(state.clone()), Extension(client(ClientRole::Client)))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        assert!(
+            get_backup(State(state.clone()), Extension(client(ClientRole::Admin)))
+                .await
+                .is_ok()
+        );
+        let (status, _) = restore(
+            State(state),
+            Extension(client(ClientRole::Client)),
+            Bytes::new(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(StatusCode::FORBIDDEN, status);
+
+        fs::util::delete_temp_folder(&path).unwrap();
+    }
+
+    fn write_archive(files: &BTreeMap<String, Vec<u8>>) -> Vec<u8> {
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            archive
+                .start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            archive.write_all(content).unwrap();
+        }
+        archive.finish().unwrap().into_inner()
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use axum::{Json, Router};
+use home_automation_common::websocket::dto::MacroExecutionReport;
+
+use crate::services::ServicesContext;
+
+/// Number of executed macros which are kept, older reports are dropped.
+const EXECUTION_HISTORY_SIZE: NonZeroUsize = NonZeroUsize::new(100).unwrap();
+
+/// Reports of the most recently executed macros.
+pub struct ExecutionHistory {
+    reports: VecDeque<MacroExecutionReport>,
+    capacity: usize,
+}
+
+impl ExecutionHistory {
+    pub fn new(capacity: NonZeroUsize) -> ExecutionHistory {
+        let capacity = capacity.get();
+        ExecutionHistory {
+            reports: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, report: MacroExecutionReport) {
+        if self.reports.len() >= self.capacity {
+            self.reports.pop_front();
+        }
+        self.reports.push_back(report);
+    }
+
+    /// Returns the reports from the newest to the oldest.
+    pub fn get_reports(&self) -> Vec<MacroExecutionReport> {
+        self.reports.iter().rev().cloned().collect()
+    }
+}
+
+impl Default for ExecutionHistory {
+    fn default() -> Self {
+        ExecutionHistory::new(EXECUTION_HISTORY_SIZE)
+    }
+}
+
+pub fn get_routes() -> Router {
+    Router::new().route("/executions", axum::routing::get(get_executions))
+}
+
+async fn get_executions(
+    Extension(services_context): Extension<Arc<ServicesContext>>,
+) -> Json<Vec<MacroExecutionReport>> {
+    Json(services_context.executions.lock().unwrap().get_reports())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(macro_name: &str) -> MacroExecutionReport {
+        MacroExecutionReport {
+            macro_name: macro_name.to_owned(),
+            client_name: "desk".to_owned(),
+            executed_at: "2022-12-01T20:00:00+01:00".to_owned(),
+            actions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn keep_most_recent_reports() {
+        let mut history = ExecutionHistory::new(NonZeroUsize::new(2).unwrap());
+        history.record(report("first"));
+        history.record(report("second"));
+        history.record(report("third"));
+
+        assert_eq!(
+            vec![report("third"), report("second")],
+            history.get_reports()
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use log::LevelFilter;
 use log4rs::append::console::ConsoleAppender;
 use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
@@ -7,16 +9,14 @@ use log4rs::append::rolling_file::RollingFileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::json::JsonEncoder;
 
-use crate::APPLICATION_NAME;
-
 const LOG_FILE_NAME: &str = "log.log";
 const ROTATED_LOG_FILE_NAME: &str = "log.{}.log";
 const MAX_LOG_SIZE_BYTES: u64 = 5_000_000;
 const LOG_FILE_COUNT: u32 = 5;
 
 /// init logger configuration or panic if something fails (since we cannot log yet)
-pub fn init_logger(level_filter: LevelFilter) {
-    let log_folder_path = home_automation_common::fs::get_log_folder_path(APPLICATION_NAME)
+pub fn init_logger(level_filter: LevelFilter, data_folder: &Path) {
+    let log_folder_path = home_automation_common::fs::get_log_folder_path_in(data_folder)
         .unwrap_or_else(|err| {
             panic!("Could not get log file path: {}", err);
         });
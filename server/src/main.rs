@@ -7,35 +7,73 @@ extern crate serde_derive;
 extern crate core;
 
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 use axum::Router;
+use clap::Parser;
+use home_automation_common::automodule::streamdeck::StreamdeckModel;
+use home_automation_common::config::ConfigurationFormat;
 use log::LevelFilter;
 
-use crate::automodule::philipshue::PhilipsHueAutomationModule;
-use crate::automodule::streamdeck::StreamdeckAutomationModule;
+use crate::assets::AssetStore;
+use crate::auth::ClientAuthenticator;
+use crate::automodule::philipshue::{
+    convert_philips_hue_configuration, PhilipsHueAutomationModule,
+};
+use crate::automodule::streamdeck::{
+    convert_streamdeck_configuration, import_streamdeck_profile_file, StreamdeckAutomationModule,
+};
 use crate::automodule::{AutomationModule, CompositeAutomationModule};
+use crate::execution::ExecutionHistory;
 use crate::services::ServicesContext;
+use crate::settings::{CommandLineArguments, ConfigurationModule, ServerCommand, ServerSettings};
+use crate::state::AutomationStateStore;
 use crate::websocket::dto::AutomationServerStatusUpdate;
 use crate::websocket::server::WebsocketServer;
 
+mod archive;
+mod assets;
+mod auth;
 mod automodule;
+mod backup;
+mod execution;
 mod logger;
+mod schemas;
 mod services;
+mod settings;
+mod state;
 mod websocket;
 
 const APPLICATION_NAME: &str = "home-automation-server";
 
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    let application_folder = home_automation_common::fs::get_application_folder(APPLICATION_NAME)
-        .unwrap_or_else(|err| {
-            panic!("Could not get application folder: {}", err);
-        });
+    let settings = ServerSettings::load(CommandLineArguments::parse()).unwrap_or_else(|err| {
+        panic!("Could not load server settings: {}.", err);
+    });
+    let application_folder = settings.data_dir.clone();
+
+    match &settings.command {
+        Some(ServerCommand::ImportStreamdeckProfile {
+            profile_file,
+            device_id,
+            model,
+        }) => {
+            logger::init_logger(LevelFilter::Info, &application_folder);
+            run_import_streamdeck_profile(&application_folder, profile_file, device_id, *model);
+            return;
+        }
+        Some(ServerCommand::ConvertConfig { module, format }) => {
+            logger::init_logger(LevelFilter::Info, &application_folder);
+            run_convert_config(&application_folder, *module, *format);
+            return;
+        }
+        None => {}
+    }
 
-    logger::init_logger(get_logging_level(&args));
+    logger::init_logger(settings.log_level, &application_folder);
+    info!("Using data folder {}.", application_folder.display());
 
     let (status_update_tx, status_update_rx) =
         tokio::sync::mpsc::unbounded_channel::<AutomationServerStatusUpdate>();
@@ -55,28 +93,61 @@ async fn main() {
             .unwrap_or_else(|err| panic!("Could not load streamdeck module: {}.", err));
     composite_module.add_module(Box::new(streamdeck_module));
 
-    let api_routes = composite_module.get_routes().unwrap();
+    let module_routes = composite_module.get_routes().unwrap();
+
+    // assets (e.g. streamdeck button images)
+    let asset_store = AssetStore::new(&application_folder)
+        .unwrap_or_else(|err| panic!("Could not prepare asset store: {}.", err));
 
     let services_context = Arc::new(ServicesContext {
         modules: Box::new(Mutex::new(composite_module)),
+        states: Box::new(Mutex::new(AutomationStateStore::default())),
+        executions: Box::new(Mutex::new(ExecutionHistory::default())),
     });
 
+    // clients which may use the websocket and the API
+    let authenticator = Arc::new(
+        ClientAuthenticator::load(&application_folder)
+            .unwrap_or_else(|err| panic!("Could not load clients config: {}.", err)),
+    );
+    let _auth_configuration_watcher = authenticator
+        .watch()
+        .unwrap_or_else(|err| panic!("Could not watch clients config: {}.", err));
+
     // websocket server
     let (websocket_event_tx, websocket_event_rx) = tokio::sync::mpsc::unbounded_channel();
     let websocket_server = Arc::new(tokio::sync::Mutex::new(WebsocketServer::new(
         websocket_event_tx,
     )));
 
+    // REST API
+    let api_routes = Router::new()
+        .merge(module_routes)
+        .merge(asset_store.get_routes())
+        .merge(schemas::get_routes())
+        .merge(execution::get_routes())
+        .merge(auth::get_routes(authenticator.clone()))
+        .merge(backup::get_routes(
+            &application_folder,
+            services_context.clone(),
+        ));
+
     // routes (matched from bottom to top from more specific to less specific)
     let router = Router::new()
         .fallback_service(
-            axum::routing::get_service(tower_http::services::ServeDir::new("static")).handle_error(
-                |err| async move { error!("error occurred when serving static file: {}.", err) },
-            ),
+            axum::routing::get_service(tower_http::services::ServeDir::new(&settings.static_dir))
+                .handle_error(|err| async move {
+                    error!("error occurred when serving static file: {}.", err)
+                }),
         )
-        .merge(api_routes)
+        .nest("/api", api_routes)
         // WS
-        .route("/ws", axum::routing::get(websocket::route::ws_handler))
+        .route(
+            websocket::WEBSOCKET_PATH,
+            axum::routing::get(websocket::route::ws_handler),
+        );
+    // all routes except the static files of the web interface
+    let router = auth::authenticate_routes(router, authenticator)
         .layer(axum::extract::Extension(services_context.clone()))
         .layer(axum::extract::Extension(websocket_server.clone()));
 
@@ -88,22 +159,47 @@ async fn main() {
     )
     .await;
 
-    let port = 8091;
-    info!("Starting home automation server on port {}.", port);
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let addr = SocketAddr::new(settings.bind_address, settings.port);
+    info!("Starting home automation server on {}.", addr);
     axum_server::bind(addr)
         .serve(router.into_make_service())
         .await
         .unwrap();
 }
 
-fn get_logging_level(args: &[String]) -> LevelFilter {
-    match args.get(1) {
-        Some(arg) => match arg.as_str() {
-            "--debug" => LevelFilter::Debug,
-            "--trace" => LevelFilter::Trace,
-            _ => LevelFilter::Debug,
-        },
-        None => LevelFilter::Info,
-    }
+fn run_import_streamdeck_profile(
+    application_folder: &Path,
+    profile_path: &Path,
+    device_id: &str,
+    model: Option<StreamdeckModel>,
+) {
+    let report = import_streamdeck_profile_file(application_folder, profile_path, device_id, model)
+        .unwrap_or_else(|err| panic!("Could not import streamdeck profile: {}.", err));
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report)
+            .unwrap_or_else(|err| panic!("Could not serialize import report: {}.", err))
+    );
+    info!("Imported streamdeck profile, reload the streamdeck device configuration to apply it.");
+}
+
+fn run_convert_config(
+    application_folder: &Path,
+    module: ConfigurationModule,
+    format: ConfigurationFormat,
+) {
+    let result = match module {
+        ConfigurationModule::PhilipsHue => {
+            convert_philips_hue_configuration(application_folder, format)
+        }
+        ConfigurationModule::Streamdeck => {
+            convert_streamdeck_configuration(application_folder, format)
+        }
+    };
+    let converted_path =
+        result.unwrap_or_else(|err| panic!("Could not convert configuration: {}.", err));
+    info!(
+        "Converted configuration to {}, restart the server to use it.",
+        converted_path.display()
+    );
 }
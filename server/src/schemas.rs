@@ -0,0 +1,66 @@
+use axum::extract::Path;
+use axum::{Json, Router};
+use home_automation_common::automodule::streamdeck::{
+    StreamdeckAutomationConfiguration, StreamdeckClientConfiguration,
+    StreamdeckDevicesConfiguration,
+};
+use home_automation_common::config::{get_schema, get_schema_file_name};
+use hyper::StatusCode;
+use schemars::JsonSchema;
+
+use crate::auth::AuthConfiguration;
+use crate::automodule::philipshue::PhilipsHueAutomationModuleConfiguration;
+use crate::settings::ServerConfiguration;
+
+/// The schemas of the configuration files of the server and its clients, by file name.
+fn get_schemas() -> Vec<(String, serde_json::Value)> {
+    fn schema<T: JsonSchema>() -> (String, serde_json::Value) {
+        (get_schema_file_name::<T>(), get_schema::<T>())
+    }
+    vec![
+        schema::<ServerConfiguration>(),
+        schema::<AuthConfiguration>(),
+        schema::<PhilipsHueAutomationModuleConfiguration>(),
+        schema::<StreamdeckDevicesConfiguration>(),
+        schema::<StreamdeckClientConfiguration>(),
+        schema::<StreamdeckAutomationConfiguration>(),
+    ]
+}
+
+pub fn get_routes() -> Router {
+    Router::new().nest(
+        "/schemas",
+        Router::new()
+            .route("/", axum::routing::get(get_schema_names))
+            .route("/:name", axum::routing::get(get_schema_by_name)),
+    )
+}
+
+async fn get_schema_names() -> Json<Vec<String>> {
+    Json(get_schemas().into_iter().map(|(name, _)| name).collect())
+}
+
+async fn get_schema_by_name(
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    get_schemas()
+        .into_iter()
+        .find(|(schema_name, _)| schema_name.eq(&name))
+        .map(|(_, schema)| Json(schema))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod test {
+    use home_automation_common::types::export_schema;
+
+    use super::*;
+
+    #[test]
+    fn export_schemas() {
+        for (name, schema) in get_schemas() {
+            assert_eq!(Some("object"), schema["type"].as_str(), "{}", name);
+            export_schema(&name, &schema);
+        }
+    }
+}
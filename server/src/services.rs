@@ -1,7 +1,11 @@
 use std::sync::Mutex;
 
 use crate::automodule::CompositeAutomationModule;
+use crate::execution::ExecutionHistory;
+use crate::state::AutomationStateStore;
 
 pub struct ServicesContext {
     pub modules: Box<Mutex<CompositeAutomationModule>>,
+    pub states: Box<Mutex<AutomationStateStore>>,
+    pub executions: Box<Mutex<ExecutionHistory>>,
 }
@@ -0,0 +1,405 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use clap::{Parser, Subcommand, ValueEnum};
+use home_automation_common::automodule::streamdeck::StreamdeckModel;
+use home_automation_common::config::{
+    ConfigurationFormat, ConfigurationManager, VersionedConfiguration,
+};
+use log::LevelFilter;
+use schemars::JsonSchema;
+
+use crate::APPLICATION_NAME;
+
+const SERVER_CONFIG_FILE_NAME: &str = "serverConfig.json";
+const DEFAULT_BIND_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 8091;
+const DEFAULT_STATIC_DIR: &str = "static";
+
+/// Every setting can be passed on the command line or as environment variable, which take precedence over
+/// the server configuration file.
+#[derive(Parser, Debug)]
+#[command(version, about = "Home automation server")]
+pub struct CommandLineArguments {
+    /// Folder for the configuration, assets and logs [default: ~/.mariokaufmann/home-automation-server]
+    #[arg(long, env = "HOME_AUTOMATION_DATA_DIR", global = true)]
+    data_dir: Option<PathBuf>,
+    /// Server configuration file in JSON, TOML or YAML [default: serverConfig.json in the data folder]
+    #[arg(long, env = "HOME_AUTOMATION_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// Address the server listens on [default: 0.0.0.0]
+    #[arg(long, env = "HOME_AUTOMATION_BIND_ADDRESS")]
+    bind_address: Option<IpAddr>,
+    /// Port the server listens on [default: 8091]
+    #[arg(long, env = "HOME_AUTOMATION_PORT")]
+    port: Option<u16>,
+    /// Folder with the static files of the web interface [default: static]
+    #[arg(long, env = "HOME_AUTOMATION_STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+    /// Off, error, warn, info, debug or trace [default: info]
+    #[arg(long, env = "HOME_AUTOMATION_LOG_LEVEL")]
+    log_level: Option<LevelFilter>,
+    /// Same as --log-level debug
+    #[arg(long, conflicts_with_all = ["log_level", "trace"])]
+    debug: bool,
+    /// Same as --log-level trace
+    #[arg(long, conflicts_with = "log_level")]
+    trace: bool,
+    #[command(subcommand)]
+    command: Option<ServerCommand>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum ServerCommand {
+    /// Imports an Elgato streamdeck profile as configuration of a device
+    ImportStreamdeckProfile {
+        profile_file: PathBuf,
+        device_id: String,
+        /// original, originalV2, mini, xl or mk2 [default: model of the profile]
+        #[arg(value_parser = parse_streamdeck_model)]
+        model: Option<StreamdeckModel>,
+    },
+    /// Rewrites the configuration of a module in another format
+    ConvertConfig {
+        module: ConfigurationModule,
+        /// json, toml or yaml
+        #[arg(value_parser = parse_configuration_format)]
+        format: ConfigurationFormat,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[value(rename_all = "lower")]
+pub enum ConfigurationModule {
+    PhilipsHue,
+    Streamdeck,
+}
+
+/// Server configuration file, all settings are optional.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerConfiguration {
+    /* Only used if the configuration file is not in the data folder itself. */
+    pub data_dir: Option<PathBuf>,
+    pub bind_address: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub static_dir: Option<PathBuf>,
+    pub log_level: Option<String>,
+}
+
+impl VersionedConfiguration for ServerConfiguration {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ServerSettings {
+    pub data_dir: PathBuf,
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub static_dir: PathBuf,
+    pub log_level: LevelFilter,
+    pub command: Option<ServerCommand>,
+}
+
+impl ServerSettings {
+    /// Reads the server configuration file, which is created in the data folder if no other file was given.
+    pub fn load(arguments: CommandLineArguments) -> anyhow::Result<ServerSettings> {
+        let configuration = match &arguments.config {
+            Some(config_file_path) => load_server_configuration(config_file_path)?,
+            None => {
+                let data_dir = match &arguments.data_dir {
+                    Some(data_dir) => data_dir.clone(),
+                    None => home_automation_common::fs::get_application_folder(APPLICATION_NAME)?,
+                };
+                std::fs::create_dir_all(&data_dir).with_context(|| {
+                    format!("Could not create data folder {}.", data_dir.display())
+                })?;
+                ConfigurationManager::<ServerConfiguration>::load(
+                    &data_dir,
+                    SERVER_CONFIG_FILE_NAME,
+                )?
+                .get_configuration()
+                .clone()
+            }
+        };
+
+        let settings = Self::resolve(arguments, configuration, || {
+            home_automation_common::fs::get_application_folder(APPLICATION_NAME)
+        })?;
+        std::fs::create_dir_all(&settings.data_dir).with_context(|| {
+            format!(
+                "Could not create data folder {}.",
+                settings.data_dir.display()
+            )
+        })?;
+        Ok(settings)
+    }
+
+    /// The default data folder is only determined if no other folder is configured.
+    fn resolve<F>(
+        arguments: CommandLineArguments,
+        configuration: ServerConfiguration,
+        get_default_data_dir: F,
+    ) -> anyhow::Result<ServerSettings>
+    where
+        F: FnOnce() -> anyhow::Result<PathBuf>,
+    {
+        let configured_log_level = configuration
+            .log_level
+            .map(|log_level| {
+                LevelFilter::from_str(&log_level)
+                    .map_err(|_| anyhow!("Invalid log level {}.", log_level))
+            })
+            .transpose()?;
+        let log_level = if arguments.debug {
+            Some(LevelFilter::Debug)
+        } else if arguments.trace {
+            Some(LevelFilter::Trace)
+        } else {
+            arguments.log_level
+        };
+
+        // a configuration file in the data folder cannot move the data folder
+        let configured_data_dir = configuration
+            .data_dir
+            .filter(|_| arguments.config.is_some());
+
+        let data_dir = match arguments.data_dir.or(configured_data_dir) {
+            Some(data_dir) => data_dir,
+            None => get_default_data_dir()?,
+        };
+
+        Ok(ServerSettings {
+            data_dir,
+            bind_address: arguments
+                .bind_address
+                .or(configuration.bind_address)
+                .unwrap_or(DEFAULT_BIND_ADDRESS),
+            port: arguments
+                .port
+                .or(configuration.port)
+                .unwrap_or(DEFAULT_PORT),
+            static_dir: arguments
+                .static_dir
+                .or(configuration.static_dir)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_STATIC_DIR)),
+            log_level: log_level
+                .or(configured_log_level)
+                .unwrap_or(LevelFilter::Info),
+            command: arguments.command,
+        })
+    }
+}
+
+/// A configuration file which was passed explicitly has to exist.
+fn load_server_configuration(config_file_path: &Path) -> anyhow::Result<ServerConfiguration> {
+    if !config_file_path.exists() {
+        return Err(anyhow!(
+            "Server configuration {} does not exist.",
+            config_file_path.display()
+        ));
+    }
+    let folder = config_file_path
+        .parent()
+        .filter(|folder| !folder.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let file_name = config_file_path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .context("Invalid server configuration file name.")?;
+    let configuration_manager =
+        ConfigurationManager::<ServerConfiguration>::load(folder, file_name)?;
+    Ok(configuration_manager.get_configuration().clone())
+}
+
+fn parse_streamdeck_model(model: &str) -> anyhow::Result<StreamdeckModel> {
+    serde_json::from_value(serde_json::Value::String(model.to_owned()))
+        .map_err(|err| anyhow!("Unknown streamdeck model {}: {}", model, err))
+}
+
+fn parse_configuration_format(format: &str) -> anyhow::Result<ConfigurationFormat> {
+    ConfigurationFormat::from_extension(format)
+        .ok_or_else(|| anyhow!("Unknown configuration format {}.", format))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// The arguments are also read from the environment, which is shared by all tests.
+    static ENVIRONMENT_LOCK: Mutex<()> = Mutex::new(());
+
+    fn resolve(args: &[&str], configuration: ServerConfiguration) -> ServerSettings {
+        let _lock = ENVIRONMENT_LOCK.lock().unwrap();
+        resolve_arguments(args, configuration)
+    }
+
+    fn resolve_arguments(args: &[&str], configuration: ServerConfiguration) -> ServerSettings {
+        let arguments =
+            CommandLineArguments::try_parse_from([APPLICATION_NAME].iter().chain(args)).unwrap();
+        ServerSettings::resolve(arguments, configuration, || Ok(PathBuf::from("default"))).unwrap()
+    }
+
+    #[test]
+    fn resolve_defaults() {
+        let settings = resolve(&[], ServerConfiguration::default());
+        assert_eq!(
+            ServerSettings {
+                data_dir: PathBuf::from("default"),
+                bind_address: DEFAULT_BIND_ADDRESS,
+                port: DEFAULT_PORT,
+                static_dir: PathBuf::from(DEFAULT_STATIC_DIR),
+                log_level: LevelFilter::Info,
+                command: None,
+            },
+            settings
+        );
+    }
+
+    #[test]
+    fn prefer_arguments_over_configuration() {
+        let configuration = ServerConfiguration {
+            data_dir: Some(PathBuf::from("configured")),
+            bind_address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            port: Some(8092),
+            static_dir: None,
+            log_level: Some("warn".to_owned()),
+        };
+
+        let settings = resolve(&["--config", "server.json"], configuration.clone());
+        assert_eq!(PathBuf::from("configured"), settings.data_dir);
+        assert_eq!(IpAddr::V4(Ipv4Addr::LOCALHOST), settings.bind_address);
+        assert_eq!(8092, settings.port);
+        assert_eq!(LevelFilter::Warn, settings.log_level);
+
+        let settings = resolve(
+            &[
+                "--data-dir",
+                "test",
+                "--port",
+                "8093",
+                "--static-dir",
+                "web",
+                "--debug",
+            ],
+            configuration.clone(),
+        );
+        assert_eq!(PathBuf::from("test"), settings.data_dir);
+        assert_eq!(IpAddr::V4(Ipv4Addr::LOCALHOST), settings.bind_address);
+        assert_eq!(8093, settings.port);
+        assert_eq!(PathBuf::from("web"), settings.static_dir);
+        assert_eq!(LevelFilter::Debug, settings.log_level);
+
+        let arguments = CommandLineArguments::try_parse_from([
+            APPLICATION_NAME,
+            "--debug",
+            "--log-level",
+            "info",
+        ]);
+        assert!(arguments.is_err());
+        let invalid_configuration = ServerConfiguration {
+            log_level: Some("loud".to_owned()),
+            ..configuration
+        };
+        let arguments = CommandLineArguments::try_parse_from([APPLICATION_NAME]).unwrap();
+        assert!(
+            ServerSettings::resolve(arguments, invalid_configuration, || Ok(PathBuf::new()))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn resolve_by_precedence() {
+        let _lock = ENVIRONMENT_LOCK.lock().unwrap();
+        let configuration = ServerConfiguration {
+            data_dir: Some(PathBuf::from("configured")),
+            port: Some(8092),
+            ..ServerConfiguration::default()
+        };
+
+        assert_eq!(
+            DEFAULT_PORT,
+            resolve_arguments(&[], ServerConfiguration::default()).port
+        );
+        assert_eq!(8092, resolve_arguments(&[], configuration.clone()).port);
+        std::env::set_var("HOME_AUTOMATION_PORT", "8093");
+        let from_environment = resolve_arguments(&[], configuration.clone());
+        let from_arguments = resolve_arguments(&["--port", "8094"], configuration.clone());
+        std::env::remove_var("HOME_AUTOMATION_PORT");
+        assert_eq!(8093, from_environment.port);
+        assert_eq!(8094, from_arguments.port);
+
+        // the data folder of the file is only used if the file is not in the data folder
+        assert_eq!(
+            PathBuf::from("default"),
+            resolve_arguments(&[], configuration.clone()).data_dir
+        );
+        assert_eq!(
+            PathBuf::from("configured"),
+            resolve_arguments(&["--config", "server.json"], configuration.clone()).data_dir
+        );
+        assert_eq!(
+            PathBuf::from("test"),
+            resolve_arguments(
+                &["--config", "server.json", "--data-dir", "test"],
+                configuration.clone()
+            )
+            .data_dir
+        );
+        // no default data folder is created if another one is configured
+        let arguments =
+            CommandLineArguments::try_parse_from([APPLICATION_NAME, "--config", "server.json"])
+                .unwrap();
+        let settings = ServerSettings::resolve(arguments, configuration, || {
+            Err(anyhow!("The default data folder is not needed."))
+        })
+        .unwrap();
+        assert_eq!(PathBuf::from("configured"), settings.data_dir);
+    }
+
+    #[test]
+    fn parse_commands() {
+        let settings = resolve(
+            &["--data-dir", "test", "convert-config", "philipshue", "yml"],
+            ServerConfiguration::default(),
+        );
+        assert_eq!(PathBuf::from("test"), settings.data_dir);
+        assert_eq!(
+            Some(ServerCommand::ConvertConfig {
+                module: ConfigurationModule::PhilipsHue,
+                format: ConfigurationFormat::Yaml,
+            }),
+            settings.command
+        );
+
+        let settings = resolve(
+            &[
+                "import-streamdeck-profile",
+                "profile.streamDeckProfile",
+                "desk",
+                "xl",
+            ],
+            ServerConfiguration::default(),
+        );
+        assert_eq!(
+            Some(ServerCommand::ImportStreamdeckProfile {
+                profile_file: PathBuf::from("profile.streamDeckProfile"),
+                device_id: "desk".to_owned(),
+                model: Some(StreamdeckModel::Xl),
+            }),
+            settings.command
+        );
+        assert!(CommandLineArguments::try_parse_from([
+            APPLICATION_NAME,
+            "import-streamdeck-profile",
+            "profile.streamDeckProfile",
+            "desk",
+            "large",
+        ])
+        .is_err());
+    }
+}
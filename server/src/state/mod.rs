@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use home_automation_common::state::{AutomationState, AutomationStateValue};
+
+/// Latest known value of every server side state, used to only distribute actual changes and to
+/// send the current states to newly connected clients.
+#[derive(Default)]
+pub struct AutomationStateStore {
+    states: HashMap<String, AutomationStateValue>,
+}
+
+impl AutomationStateStore {
+    /// Stores the given states and returns the ones whose value changed.
+    pub fn update_states(&mut self, states: Vec<AutomationState>) -> Vec<AutomationState> {
+        states
+            .into_iter()
+            .filter(|state| {
+                let previous_value = self.states.insert(state.id.clone(), state.value.clone());
+                previous_value.as_ref() != Some(&state.value)
+            })
+            .collect()
+    }
+
+    pub fn get_states(&self) -> Vec<AutomationState> {
+        self.states
+            .iter()
+            .map(|(id, value)| AutomationState::new(id.clone(), value.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_only_returns_changed_states() {
+        let mut store = AutomationStateStore::default();
+
+        let on_state =
+            AutomationState::new("light.on".to_owned(), AutomationStateValue::Bool(true));
+        let changed = store.update_states(vec![on_state.clone()]);
+        assert_eq!(vec![on_state.clone()], changed);
+
+        let changed = store.update_states(vec![on_state.clone()]);
+        assert!(changed.is_empty());
+
+        let off_state =
+            AutomationState::new("light.on".to_owned(), AutomationStateValue::Bool(false));
+        let changed = store.update_states(vec![off_state.clone()]);
+        assert_eq!(vec![off_state.clone()], changed);
+        assert_eq!(vec![off_state], store.get_states());
+    }
+}
@@ -1,9 +1,12 @@
-use home_automation_common::websocket::convert::convert_message_to_text;
-use home_automation_common::websocket::dto::AutomationMessage;
+use home_automation_common::websocket::convert::{
+    convert_identified_message_to_text, ProtocolVersion,
+};
+use home_automation_common::websocket::dto::IdentifiedMessage;
 
 pub fn convert_message_to_ws_message(
-    message: AutomationMessage,
+    message: IdentifiedMessage,
+    version: ProtocolVersion,
 ) -> anyhow::Result<axum::extract::ws::Message> {
-    let text = convert_message_to_text(message)?;
+    let text = convert_identified_message_to_text(message, version)?;
     Ok(axum::extract::ws::Message::Text(text))
 }
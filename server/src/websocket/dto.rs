@@ -5,10 +5,13 @@ use home_automation_common::websocket::dto::AutomationMessage;
 pub enum WebsocketEvent {
     MessageReceived {
         client_id: usize,
+        id: Option<u64>,
         message: AutomationMessage,
     },
     ClientConnected {
         client_id: usize,
+        /* Name of the client credentials the client authenticated with. */
+        identity: String,
     },
     ClientDisconnected {
         client_id: usize,
@@ -17,6 +20,8 @@ pub enum WebsocketEvent {
 
 pub struct AutomationServerWebsocketMessage {
     pub message: AutomationMessage,
+    /* The id of the request which is answered by the message. */
+    pub id: Option<u64>,
     pub distribution: MessageDistribution,
 }
 
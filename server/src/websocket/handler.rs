@@ -1,11 +1,17 @@
-use home_automation_common::action::AutomationAction;
-use home_automation_common::websocket::dto::AutomationMessage;
+use home_automation_common::action::{AutomationAction, AutomationStatusUpdate};
+use home_automation_common::automacro::AutomationMacro;
+use home_automation_common::state::{
+    client_connected_state_id, streamdeck_connected_state_id, AutomationState, AutomationStateValue,
+};
+use home_automation_common::websocket::dto::{
+    ActionExecutionResult, AutomationMessage, ConnectedStreamdeck, MacroExecutionReport,
+};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-use crate::automodule::AutomationModule;
 use crate::services::ServicesContext;
 use crate::websocket::dto::{
     AutomationServerWebsocketMessage, MessageDistribution, WebsocketEvent,
@@ -32,36 +38,76 @@ impl WebsocketEventHandler {
     ) {
         while let Some(event) = event_receiver.recv().await {
             match event {
-                WebsocketEvent::ClientConnected { client_id } => {
-                    let new_client_state = ClientState::new();
+                WebsocketEvent::ClientConnected {
+                    client_id,
+                    identity,
+                } => {
+                    let new_client_state = ClientState::new(identity);
                     let previous_state = self.client_states.insert(client_id, new_client_state);
                     if previous_state.is_some() {
                         warn!("Overwriting client state for client with id {}.", client_id);
                     }
+                    self.send_states(client_id, &websocket_message_sender);
                 }
                 WebsocketEvent::ClientDisconnected { client_id } => {
-                    self.client_states.remove(&client_id);
+                    if let Some(client_state) = self.client_states.remove(&client_id) {
+                        let mut states =
+                            get_streamdeck_presence_states(&client_state.streamdecks, &[]);
+                        states.extend(get_client_connected_state(&client_state.name, false));
+                        self.publish_states(states, &websocket_message_sender);
+                    }
                 }
-                WebsocketEvent::MessageReceived { client_id, message } => {
-                    self.handle_automation_message(client_id, message, &websocket_message_sender);
+                WebsocketEvent::MessageReceived {
+                    client_id,
+                    id,
+                    message,
+                } => {
+                    self.handle_automation_message(
+                        client_id,
+                        id,
+                        message,
+                        &websocket_message_sender,
+                    );
                 }
             }
         }
     }
 
+    /// Requests, i.e. messages with an id, are always answered with the same id. Executed macros are always
+    /// reported to the client which sent them.
     fn handle_automation_message(
         &mut self,
         client_id: usize,
+        id: Option<u64>,
         message: AutomationMessage,
         message_sender: &UnboundedSender<AutomationServerWebsocketMessage>,
     ) {
-        self.update_client_state(client_id, &message);
+        let presence_states = self.update_client_state(client_id, &message);
+        self.publish_states(presence_states, message_sender);
 
-        trace!("Got message: {:?}", &message);
+        trace!("Got message {:?}: {:?}", id, &message);
 
         match message {
             AutomationMessage::ExecuteMacro { mac } => {
-                self.handle_actions(mac.actions);
+                let report = self.execute_macro(client_id, mac);
+                self.context
+                    .executions
+                    .lock()
+                    .unwrap()
+                    .record(report.clone());
+                // also sent without a request so that the client can show failed macros
+                Self::reply(
+                    client_id,
+                    id,
+                    AutomationMessage::MacroExecuted { report },
+                    message_sender,
+                );
+            }
+            AutomationMessage::ReportStates { states } => {
+                self.publish_states(states, message_sender);
+                if id.is_some() {
+                    Self::reply(client_id, id, AutomationMessage::Response, message_sender);
+                }
             }
             AutomationMessage::RequestClientStates => {
                 let states = self
@@ -70,56 +116,356 @@ impl WebsocketEventHandler {
                     .map(
                         |client_state| home_automation_common::websocket::dto::ClientState {
                             name: client_state.name.clone(),
+                            identity: client_state.identity.clone(),
                             macros_executed: client_state.macros_executed,
                             connected_since: client_state.connected_since.to_rfc3339(),
                             device_type: client_state.device_type.clone(),
+                            streamdecks: client_state.streamdecks.clone(),
                         },
                     )
                     .collect::<Vec<home_automation_common::websocket::dto::ClientState>>();
                 let response_message = AutomationMessage::ClientStates { states };
-                let websocket_message = AutomationServerWebsocketMessage {
-                    message: response_message,
-                    distribution: MessageDistribution::SingleClient { client_id },
-                };
-                if let Err(err) = message_sender.send(websocket_message) {
-                    error!("Could not send client state response message: {}.", err);
+                Self::reply(client_id, id, response_message, message_sender);
+            }
+            _ => {
+                if id.is_some() {
+                    let response_message = AutomationMessage::Error {
+                        error: "The message is not a request.".to_owned(),
+                    };
+                    Self::reply(client_id, id, response_message, message_sender);
                 }
             }
-            _ => {}
         }
     }
 
-    fn execute_action(services_context: &Arc<ServicesContext>, action: AutomationAction) {
-        let mut modules = services_context.modules.lock().unwrap();
-        match modules.handle_action(&action) {
+    fn reply(
+        client_id: usize,
+        id: Option<u64>,
+        message: AutomationMessage,
+        message_sender: &UnboundedSender<AutomationServerWebsocketMessage>,
+    ) {
+        let websocket_message = AutomationServerWebsocketMessage {
+            message,
+            id,
+            distribution: MessageDistribution::SingleClient { client_id },
+        };
+        if let Err(err) = message_sender.send(websocket_message) {
+            error!("Could not send response message: {}.", err);
+        }
+    }
+
+    fn execute_action(
+        services_context: &Arc<ServicesContext>,
+        action: &AutomationAction,
+    ) -> ActionExecutionResult {
+        let start = Instant::now();
+        let handling = services_context
+            .modules
+            .lock()
+            .unwrap()
+            .dispatch_action(action);
+        let duration_ms = start.elapsed().as_millis() as u64;
+        let (handled, error) = match handling.result {
             Ok(handled) => {
                 if !handled {
                     warn!("Action was not handled by any module.");
                 }
+                (handled, None)
+            }
+            Err(err) => {
+                error!("Error occurred while handling action: {}.", err);
+                (false, Some(err.to_string()))
             }
-            Err(err) => error!("Error occurred while handling action: {}.", err),
+        };
+        ActionExecutionResult {
+            action: action.clone(),
+            handled,
+            module: handling.module.map(str::to_owned),
+            duration_ms,
+            error,
         }
     }
 
-    fn handle_actions(&self, actions: Vec<AutomationAction>) {
-        for action in actions {
-            Self::execute_action(&self.context, action);
+    /// Executes all actions, also if some of them fail, and reports the outcome of every action.
+    fn execute_macro(&self, client_id: usize, mac: AutomationMacro) -> MacroExecutionReport {
+        let executed_at = chrono::Utc::now().to_rfc3339();
+        let actions = mac
+            .actions
+            .iter()
+            .map(|action| Self::execute_action(&self.context, action))
+            .collect();
+        let report = MacroExecutionReport {
+            macro_name: mac.name().to_owned(),
+            client_name: self
+                .client_states
+                .get(&client_id)
+                .map(|client_state| client_state.identity.clone())
+                .unwrap_or_default(),
+            executed_at,
+            actions,
+        };
+        if !report.is_successful() {
+            warn!(
+                "Macro {} could not be executed completely.",
+                report.macro_name
+            );
         }
+        report
     }
 
-    fn update_client_state(&mut self, client_id: usize, message: &AutomationMessage) {
+    /// Returns the connected states which changed because the client made itself known with a new name
+    /// or because streamdecks were attached to or detached from the client.
+    fn update_client_state(
+        &mut self,
+        client_id: usize,
+        message: &AutomationMessage,
+    ) -> Vec<AutomationState> {
         match self.client_states.get_mut(&client_id) {
             Some(client_state) => match message {
                 AutomationMessage::ExecuteMacro { .. } => {
                     client_state.macros_executed += 1;
+                    Vec::new()
                 }
                 AutomationMessage::Pong { client_update } => {
+                    let mut states = get_streamdeck_presence_states(
+                        &client_state.streamdecks,
+                        &client_update.streamdecks,
+                    );
+                    if client_state.name.ne(&client_update.name) {
+                        states.extend(get_client_connected_state(&client_update.name, true));
+                    }
                     client_state.name = client_update.name.clone();
                     client_state.device_type = client_update.device_type.clone();
+                    client_state.streamdecks = client_update.streamdecks.clone();
+                    states
                 }
-                _ => {}
+                _ => Vec::new(),
+            },
+            None => {
+                error!("No client state for client with id {} found.", client_id);
+                Vec::new()
+            }
+        }
+    }
+
+    fn send_states(
+        &self,
+        client_id: usize,
+        message_sender: &UnboundedSender<AutomationServerWebsocketMessage>,
+    ) {
+        let states = self.context.states.lock().unwrap().get_states();
+        let websocket_message = AutomationServerWebsocketMessage {
+            message: AutomationMessage::StatusUpdate {
+                update: AutomationStatusUpdate::StatesChanged { states },
+            },
+            id: None,
+            distribution: MessageDistribution::SingleClient { client_id },
+        };
+        if let Err(err) = message_sender.send(websocket_message) {
+            error!("Could not send states to client: {}.", err);
+        }
+    }
+
+    fn publish_states(
+        &self,
+        states: Vec<AutomationState>,
+        message_sender: &UnboundedSender<AutomationServerWebsocketMessage>,
+    ) {
+        if states.is_empty() {
+            return;
+        }
+        let changed_states = self.context.states.lock().unwrap().update_states(states);
+        if changed_states.is_empty() {
+            return;
+        }
+        let websocket_message = AutomationServerWebsocketMessage {
+            message: AutomationMessage::StatusUpdate {
+                update: AutomationStatusUpdate::StatesChanged {
+                    states: changed_states,
+                },
             },
-            None => error!("No client state for client with id {} found.", client_id),
+            id: None,
+            distribution: MessageDistribution::Broadcast,
+        };
+        if let Err(err) = message_sender.send(websocket_message) {
+            error!("Could not send changed states: {}.", err);
+        }
+    }
+}
+
+fn get_client_connected_state(client_name: &str, connected: bool) -> Option<AutomationState> {
+    if client_name.is_empty() {
+        return None;
+    }
+    Some(AutomationState::new(
+        client_connected_state_id(client_name),
+        AutomationStateValue::Bool(connected),
+    ))
+}
+
+/// Returns connected states for the streamdecks which were attached or detached.
+fn get_streamdeck_presence_states(
+    previous_streamdecks: &[ConnectedStreamdeck],
+    streamdecks: &[ConnectedStreamdeck],
+) -> Vec<AutomationState> {
+    let detached = previous_streamdecks
+        .iter()
+        .filter(|previous| {
+            !streamdecks
+                .iter()
+                .any(|streamdeck| streamdeck.device_id.eq(&previous.device_id))
+        })
+        .map(|streamdeck| (streamdeck, false));
+    let attached = streamdecks
+        .iter()
+        .filter(|streamdeck| {
+            !previous_streamdecks
+                .iter()
+                .any(|previous| previous.device_id.eq(&streamdeck.device_id))
+        })
+        .map(|streamdeck| (streamdeck, true));
+    detached
+        .chain(attached)
+        .map(|(streamdeck, connected)| {
+            AutomationState::new(
+                streamdeck_connected_state_id(&streamdeck.device_id),
+                AutomationStateValue::Bool(connected),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use home_automation_common::automodule::streamdeck::StreamdeckModel;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::automodule::{AutomationModule, CompositeAutomationModule};
+    use crate::execution::ExecutionHistory;
+    use crate::state::AutomationStateStore;
+
+    use super::*;
+
+    fn streamdeck(device_id: &str) -> ConnectedStreamdeck {
+        ConnectedStreamdeck {
+            device_id: device_id.to_owned(),
+            model: StreamdeckModel::Original,
         }
     }
+
+    #[test]
+    fn streamdeck_presence_states() {
+        let states = get_streamdeck_presence_states(
+            &[streamdeck("desk"), streamdeck("bedroom")],
+            &[streamdeck("desk"), streamdeck("kitchen")],
+        );
+
+        assert_eq!(
+            vec![
+                AutomationState::new(
+                    streamdeck_connected_state_id("bedroom"),
+                    AutomationStateValue::Bool(false)
+                ),
+                AutomationState::new(
+                    streamdeck_connected_state_id("kitchen"),
+                    AutomationStateValue::Bool(true)
+                ),
+            ],
+            states
+        );
+    }
+
+    #[test]
+    fn reply_to_requests() {
+        let (status_update_sender, _) = unbounded_channel();
+        let modules = CompositeAutomationModule::new(Path::new(""), status_update_sender).unwrap();
+        let mut handler = WebsocketEventHandler::new(Arc::new(ServicesContext {
+            modules: Box::new(Mutex::new(modules)),
+            states: Box::new(Mutex::new(AutomationStateStore::default())),
+            executions: Box::new(Mutex::new(ExecutionHistory::default())),
+        }));
+        let (message_sender, mut message_receiver) = unbounded_channel();
+        let execute_macro = AutomationMessage::ExecuteMacro {
+            mac: AutomationMacro::new("Play".to_owned(), vec![AutomationAction::PlaySound]),
+        };
+
+        // executed macros are also reported without id
+        handler.handle_automation_message(1, None, execute_macro.clone(), &message_sender);
+        let reply = message_receiver.try_recv().unwrap();
+        assert_eq!(None, reply.id);
+        assert!(matches!(
+            reply.message,
+            AutomationMessage::MacroExecuted { .. }
+        ));
+
+        // no module handles the action
+        handler.handle_automation_message(1, Some(5), execute_macro, &message_sender);
+        let reply = message_receiver.try_recv().unwrap();
+        assert_eq!(Some(5), reply.id);
+        assert!(matches!(
+            reply.distribution,
+            MessageDistribution::SingleClient { client_id: 1 }
+        ));
+        match reply.message {
+            AutomationMessage::MacroExecuted { report } => {
+                assert_eq!("Play", report.macro_name);
+                assert_eq!(1, report.actions.len());
+                assert!(!report.actions[0].handled);
+                assert_eq!(None, report.actions[0].module);
+                assert!(!report.is_successful());
+            }
+            message => panic!("Unexpected reply {:?}.", message),
+        }
+        assert_eq!(
+            2,
+            handler
+                .context
+                .executions
+                .lock()
+                .unwrap()
+                .get_reports()
+                .len()
+        );
+
+        handler.handle_automation_message(1, Some(7), AutomationMessage::Ping, &message_sender);
+        let reply = message_receiver.try_recv().unwrap();
+        assert!(matches!(reply.message, AutomationMessage::Error { .. }));
+
+        handler.handle_automation_message(
+            1,
+            Some(6),
+            AutomationMessage::RequestClientStates,
+            &message_sender,
+        );
+        let reply = message_receiver.try_recv().unwrap();
+        assert_eq!(Some(6), reply.id);
+        assert_eq!(
+            AutomationMessage::ClientStates { states: Vec::new() },
+            reply.message
+        );
+    }
+
+    #[test]
+    fn report_identity_of_client() {
+        let (status_update_sender, _) = unbounded_channel();
+        let modules = CompositeAutomationModule::new(Path::new(""), status_update_sender).unwrap();
+        let mut handler = WebsocketEventHandler::new(Arc::new(ServicesContext {
+            modules: Box::new(Mutex::new(modules)),
+            states: Box::new(Mutex::new(AutomationStateStore::default())),
+            executions: Box::new(Mutex::new(ExecutionHistory::default())),
+        }));
+        // the client did not report its name yet
+        handler
+            .client_states
+            .insert(1, ClientState::new("desk".to_owned()));
+
+        let report = handler.execute_macro(
+            1,
+            AutomationMacro::new("Play".to_owned(), vec![AutomationAction::PlaySound]),
+        );
+        assert_eq!("desk", report.client_name);
+    }
 }
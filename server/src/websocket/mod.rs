@@ -1,4 +1,7 @@
-use home_automation_common::websocket::dto::{AutomationMessage, ClientDeviceType};
+use home_automation_common::action::AutomationStatusUpdate;
+use home_automation_common::websocket::dto::{
+    AutomationMessage, ClientDeviceType, ConnectedStreamdeck, IdentifiedMessage,
+};
 use std::sync::Arc;
 
 use crate::ServicesContext;
@@ -17,20 +20,26 @@ pub mod handler;
 pub mod route;
 pub mod server;
 
+pub const WEBSOCKET_PATH: &str = "/ws";
+
 struct ClientState {
     connected_since: chrono::DateTime<chrono::Utc>,
     name: String,
+    identity: String,
     macros_executed: u32,
     device_type: ClientDeviceType,
+    streamdecks: Vec<ConnectedStreamdeck>,
 }
 
 impl ClientState {
-    fn new() -> ClientState {
+    fn new(identity: String) -> ClientState {
         ClientState {
             name: "".to_owned(),
+            identity,
             connected_since: chrono::Utc::now(),
             macros_executed: 0,
             device_type: ClientDeviceType::Desktop,
+            streamdecks: Vec::new(),
         }
     }
 }
@@ -41,15 +50,18 @@ pub async fn setup(
     status_update_rx: UnboundedReceiver<AutomationServerStatusUpdate>,
     websocket_event_rx: UnboundedReceiver<WebsocketEvent>,
 ) {
-    let websocket_event_handler = WebsocketEventHandler::new(services_context);
+    let websocket_event_handler = WebsocketEventHandler::new(services_context.clone());
 
     let websocket_ping_server = websocket_server;
     // ws ping
     let websocket_ping_task = server::ping_websocket_clients(websocket_ping_server.clone());
     tokio::spawn(websocket_ping_task);
     // ws status update
-    let websocket_status_update_task =
-        create_status_update_task(websocket_ping_server.clone(), status_update_rx);
+    let websocket_status_update_task = create_status_update_task(
+        websocket_ping_server.clone(),
+        services_context,
+        status_update_rx,
+    );
     tokio::spawn(websocket_status_update_task);
     // ws handle message sending
     let (websocket_message_tx, websocket_message_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -67,13 +79,29 @@ pub async fn setup(
 
 async fn create_status_update_task(
     server: Arc<tokio::sync::Mutex<WebsocketServer>>,
+    services_context: Arc<ServicesContext>,
     mut status_update_rx: UnboundedReceiver<AutomationServerStatusUpdate>,
 ) {
     while let Some(status_update) = status_update_rx.recv().await {
-        let message = AutomationMessage::StatusUpdate {
-            update: status_update.update,
+        let update = match status_update.update {
+            AutomationStatusUpdate::StatesChanged { states } => {
+                // only distribute states which actually changed
+                let changed_states = services_context
+                    .states
+                    .lock()
+                    .unwrap()
+                    .update_states(states);
+                if changed_states.is_empty() {
+                    continue;
+                }
+                AutomationStatusUpdate::StatesChanged {
+                    states: changed_states,
+                }
+            }
+            update => update,
         };
-        send_message(server.clone(), message, status_update.distribution).await
+        let message = AutomationMessage::StatusUpdate { update };
+        send_message(server.clone(), message.into(), status_update.distribution).await
     }
 }
 
@@ -92,20 +120,20 @@ async fn create_send_message_task(
     mut websocket_message_rx: UnboundedReceiver<AutomationServerWebsocketMessage>,
 ) {
     while let Some(websocket_message) = websocket_message_rx.recv().await {
-        let message = websocket_message.message;
+        let message = IdentifiedMessage::new(websocket_message.id, websocket_message.message);
         send_message(server.clone(), message, websocket_message.distribution).await
     }
 }
 
 async fn send_message(
     server: Arc<tokio::sync::Mutex<WebsocketServer>>,
-    message: AutomationMessage,
+    message: IdentifiedMessage,
     distribution: MessageDistribution,
 ) {
     let locked_server = server.lock().await;
     match distribution {
         MessageDistribution::Broadcast => {
-            if let Err(err) = locked_server.broadcast_message(message).await {
+            if let Err(err) = locked_server.broadcast_message(message.message).await {
                 debug!("Error occurred when sending broadcast message: {}", err);
             }
         }
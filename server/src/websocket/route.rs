@@ -1,24 +1,36 @@
+use crate::auth::AuthenticatedClient;
 use crate::{AutomationModule, ServicesContext, WebsocketServer};
 use axum::extract::ws::WebSocket;
 use axum::extract::{Extension, WebSocketUpgrade};
 use axum::response::IntoResponse;
+use home_automation_common::websocket::convert::ProtocolVersion;
 use std::sync::Arc;
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    Extension(client): Extension<AuthenticatedClient>,
     Extension(context_data): Extension<Arc<ServicesContext>>,
     Extension(websocket_server_data): Extension<Arc<tokio::sync::Mutex<WebsocketServer>>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|websocket| handle_socket(websocket, context_data, websocket_server_data))
+    ws.protocols(
+        ProtocolVersion::SUPPORTED
+            .into_iter()
+            .map(|version| version.subprotocol()),
+    )
+    .on_upgrade(|websocket| {
+        handle_socket(websocket, client.name, context_data, websocket_server_data)
+    })
 }
 
 pub async fn handle_socket(
     socket: WebSocket,
+    identity: String,
     context_data: Arc<ServicesContext>,
     websocket_server_data: Arc<tokio::sync::Mutex<WebsocketServer>>,
 ) {
     let client_id =
-        crate::websocket::server::add_websocket_to_server(socket, websocket_server_data).await;
+        crate::websocket::server::add_websocket_to_server(socket, identity, websocket_server_data)
+            .await;
     // send initial status updates
     let locked_modules = context_data.modules.lock().unwrap();
     locked_modules
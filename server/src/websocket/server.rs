@@ -4,9 +4,10 @@ use std::sync::Arc;
 
 use anyhow::anyhow;
 use futures::{FutureExt, StreamExt};
-use home_automation_common::websocket::convert::parse_message_from_string;
-use home_automation_common::websocket::dto::AutomationMessage;
-use home_automation_common::websocket::MessageSender;
+use home_automation_common::websocket::convert::{
+    parse_identified_message_from_string, parse_message_id_from_string, ProtocolVersion,
+};
+use home_automation_common::websocket::dto::{AutomationMessage, IdentifiedMessage};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
@@ -19,10 +20,11 @@ const WS_PING_INTERVAL: Duration = Duration::from_secs(10);
 
 pub async fn add_websocket_to_server(
     websocket: axum::extract::ws::WebSocket,
+    identity: String,
     server: Arc<tokio::sync::Mutex<WebsocketServer>>,
 ) -> usize {
     let mut locked_server = server.lock().await;
-    locked_server.add_client_socket(websocket).await
+    locked_server.add_client_socket(websocket, identity).await
 }
 
 pub async fn ping_websocket_clients(server: Arc<tokio::sync::Mutex<WebsocketServer>>) {
@@ -51,8 +53,12 @@ impl WebsocketTask {
     }
 }
 
+type WebsocketMessageSender =
+    tokio::sync::mpsc::UnboundedSender<Result<axum::extract::ws::Message, axum::Error>>;
+
 struct WebsocketClientConnection {
-    sender: tokio::sync::mpsc::UnboundedSender<Result<axum::extract::ws::Message, axum::Error>>,
+    protocol_version: ProtocolVersion,
+    sender: WebsocketMessageSender,
     tasks: Vec<WebsocketTask>,
 }
 
@@ -80,9 +86,24 @@ impl WebsocketServer {
         }
     }
 
-    async fn add_client_socket(&mut self, websocket: axum::extract::ws::WebSocket) -> usize {
+    async fn add_client_socket(
+        &mut self,
+        websocket: axum::extract::ws::WebSocket,
+        identity: String,
+    ) -> usize {
         let next_id = self.next_user_id.fetch_add(1, Ordering::SeqCst);
-        info!("Connected to new websocket client with id {}.", next_id);
+        // clients which do not request a subprotocol use the first version
+        let protocol_version = websocket
+            .protocol()
+            .and_then(|protocol| protocol.to_str().ok())
+            .and_then(ProtocolVersion::from_subprotocol)
+            .unwrap_or_default();
+        info!(
+            "Connected to new websocket client {} with id {} using protocol version {}.",
+            identity,
+            next_id,
+            protocol_version.number()
+        );
 
         let (websocket_sink, websocket_stream) = websocket.split();
 
@@ -117,7 +138,9 @@ impl WebsocketServer {
         // receiving
         let handle_messages_task_join_handle = tokio::spawn(WebsocketServer::handle_user_messages(
             next_id,
+            protocol_version,
             websocket_stream,
+            sending_tx.clone(),
             self.websocket_event_sender.clone(),
         ));
         let handle_messages_task = WebsocketTask {
@@ -126,6 +149,7 @@ impl WebsocketServer {
         };
 
         let client_connection = WebsocketClientConnection {
+            protocol_version,
             sender: sending_tx,
             tasks: vec![send_task, handle_messages_task],
         };
@@ -134,7 +158,10 @@ impl WebsocketServer {
             .await
             .insert(next_id, client_connection);
 
-        let event = WebsocketEvent::ClientConnected { client_id: next_id };
+        let event = WebsocketEvent::ClientConnected {
+            client_id: next_id,
+            identity,
+        };
         if let Err(err) = self.websocket_event_sender.send(event) {
             error!(
                 "Could not send connected event on websocket event sender: {}.",
@@ -147,7 +174,9 @@ impl WebsocketServer {
 
     async fn handle_user_messages(
         id: usize,
+        protocol_version: ProtocolVersion,
         mut stream: futures::stream::SplitStream<axum::extract::ws::WebSocket>,
+        message_sender: WebsocketMessageSender,
         websocket_event_sender: UnboundedSender<WebsocketEvent>,
     ) {
         loop {
@@ -157,15 +186,23 @@ impl WebsocketServer {
                         Ok(message) => {
                             match message.to_text() {
                                 Ok(text) => {
-                                    match parse_message_from_string(text) {
+                                    match parse_identified_message_from_string(text) {
                                         Ok(message) => {
-                                            let event = WebsocketEvent::MessageReceived { client_id: id, message };
+                                            let event = WebsocketEvent::MessageReceived {
+                                                client_id: id,
+                                                id: message.id,
+                                                message: message.message,
+                                            };
                                             if let Err(err) = websocket_event_sender.send(event) {
                                                 error!("Could not send websocket event: {}.", err);
                                             }
                                         }
                                         Err(err) => {
                                             error!(                            "Could not parse text {} to websocket message: {}.",                text, err            );
+                                            // the client would otherwise wait for a reply to its request until it times out
+                                            if let Some(request_id) = parse_message_id_from_string(text) {
+                                                Self::reply_with_parse_error(request_id, err, protocol_version, &message_sender);
+                                            }
                                         }
                                     }
                                 }
@@ -173,7 +210,10 @@ impl WebsocketServer {
                             }
                         }
                         Err(err) => {
-                            error!("Could not receive message for client with id {}: {}.", id, err);
+                            error!(
+                                "Could not receive message for client with id {}: {}.",
+                                id, err
+                            );
                             break;
                         }
                     }
@@ -186,15 +226,38 @@ impl WebsocketServer {
         }
     }
 
+    fn reply_with_parse_error(
+        request_id: u64,
+        err: anyhow::Error,
+        protocol_version: ProtocolVersion,
+        message_sender: &WebsocketMessageSender,
+    ) {
+        let message = AutomationMessage::Error {
+            error: format!("Could not parse message: {}", err),
+        };
+        let result = convert_message_to_ws_message(
+            IdentifiedMessage::new(Some(request_id), message),
+            protocol_version,
+        )
+        .and_then(|message| Ok(message_sender.send(Ok(message))?));
+        if let Err(err) = result {
+            error!(
+                "Could not reply to request {} which could not be parsed: {}.",
+                request_id, err
+            );
+        }
+    }
+
     pub async fn send_message_to_client(
         &self,
-        message: AutomationMessage,
+        message: IdentifiedMessage,
         client_id: usize,
     ) -> anyhow::Result<()> {
-        let converted_message = convert_message_to_ws_message(message)?;
         let users = self.client_connections.read().await;
         match users.get(&client_id) {
             Some(client_connection) => {
+                let converted_message =
+                    convert_message_to_ws_message(message, client_connection.protocol_version)?;
                 Self::send_message_on_connection(&converted_message, client_connection)
             }
             None => Err(anyhow!(
@@ -205,14 +268,32 @@ impl WebsocketServer {
     }
 
     pub async fn broadcast_message(&self, message: AutomationMessage) -> anyhow::Result<()> {
-        let converted_message = convert_message_to_ws_message(message)?;
+        let converted_messages = Self::convert_message_for_all_versions(message)?;
         let users = self.client_connections.read().await;
         for client_connection in users.values() {
-            Self::send_message_on_connection(&converted_message, client_connection)?;
+            Self::send_message_on_connection(
+                &converted_messages[&client_connection.protocol_version],
+                client_connection,
+            )?;
         }
         Ok(())
     }
 
+    /// Broadcasts are converted once for every protocol version instead of once per client.
+    fn convert_message_for_all_versions(
+        message: AutomationMessage,
+    ) -> anyhow::Result<HashMap<ProtocolVersion, axum::extract::ws::Message>> {
+        ProtocolVersion::SUPPORTED
+            .into_iter()
+            .map(|version| {
+                Ok((
+                    version,
+                    convert_message_to_ws_message(message.clone().into(), version)?,
+                ))
+            })
+            .collect()
+    }
+
     fn send_message_on_connection(
         message: &axum::extract::ws::Message,
         client_connection: &WebsocketClientConnection,
@@ -222,13 +303,14 @@ impl WebsocketServer {
     }
 
     pub async fn broadcast_ping(&self) -> anyhow::Result<()> {
-        let converted_message = convert_message_to_ws_message(AutomationMessage::Ping)?;
+        let converted_messages = Self::convert_message_for_all_versions(AutomationMessage::Ping)?;
         let mut concat_error_message: Option<String> = None;
 
         let mut ids_to_remove: Vec<usize> = Vec::new();
 
         let users = self.client_connections.read().await;
         for (user_id, client_connection) in users.iter() {
+            let converted_message = &converted_messages[&client_connection.protocol_version];
             if let Err(err) = client_connection.sender.send(Ok(converted_message.clone())) {
                 let error_message = format!(
                     "Could not send ping for client with id {}: {}.\n ",
@@ -278,18 +360,6 @@ impl WebsocketServer {
     }
 }
 
-struct ServerWebsocketSender {
-    sender: UnboundedSender<Result<axum::extract::ws::Message, axum::Error>>,
-}
-
-impl MessageSender for ServerWebsocketSender {
-    fn send_message(&mut self, message: AutomationMessage) -> anyhow::Result<()> {
-        let ws_message = convert_message_to_ws_message(message)?;
-        self.sender.send(Ok(ws_message))?;
-        Ok(())
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use std::net::SocketAddr;
@@ -299,10 +369,14 @@ mod tests {
     use axum::response::IntoResponse;
     use axum::Router;
     use axum_server::Handle;
+    use futures::SinkExt;
     use home_automation_common::action::{AutomationAction, AutomationStatusUpdate};
     use home_automation_common::automacro::AutomationMacro;
-    use home_automation_common::websocket::convert::convert_message_to_text;
+    use home_automation_common::websocket::convert::{
+        convert_message_to_text, parse_message_from_string,
+    };
     use tokio::sync::mpsc::UnboundedReceiver;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 
     use crate::websocket::server;
 
@@ -316,37 +390,42 @@ mod tests {
         shutdown_signal_sender: ShutdownSignalSender,
     ) {
         while let Some(event) = event_receiver.recv().await {
-            match event {
-                WebsocketEvent::MessageReceived {
-                    message,
-                    client_id: _,
-                } => {
-                    let success = message.eq(&expected_message);
-
-                    if success {
-                        let sender = shutdown_signal_sender.lock().unwrap().take();
-                        if let Some(sender) = sender {
-                            sender
-                                .send(())
-                                .expect("Could not send signal to shut down server.");
-                        }
-                        break;
+            if let WebsocketEvent::MessageReceived { message, .. } = event {
+                let success = message.eq(&expected_message);
+
+                if success {
+                    let sender = shutdown_signal_sender.lock().unwrap().take();
+                    if let Some(sender) = sender {
+                        sender
+                            .send(())
+                            .expect("Could not send signal to shut down server.");
                     }
+                    break;
                 }
-                _ => {}
             }
         }
     }
 
     pub fn convert_message_to_tungstenite_message(
         message: AutomationMessage,
+        version: ProtocolVersion,
     ) -> anyhow::Result<tokio_tungstenite::tungstenite::Message> {
-        let text = convert_message_to_text(message)?;
+        let text = convert_message_to_text(message, version)?;
         Ok(tokio_tungstenite::tungstenite::Message::Text(text))
     }
 
     #[tokio::test]
     async fn test_websocket_integration() {
+        run_websocket_integration(None).await;
+    }
+
+    #[tokio::test]
+    async fn test_websocket_integration_with_subprotocol() {
+        run_websocket_integration(Some(ProtocolVersion::V2)).await;
+    }
+
+    /// Connects a client which requests the given protocol version or no subprotocol at all.
+    async fn run_websocket_integration(requested_version: Option<ProtocolVersion>) {
         let ping_message = AutomationMessage::StatusUpdate {
             update: AutomationStatusUpdate::SoundPlayed {
                 sound: "test".to_string(),
@@ -387,9 +466,23 @@ mod tests {
         let port = handle.listening().await.unwrap().port();
 
         let ws_url = format!("ws://{}.{}.{}.{}:{}", url[0], url[1], url[2], url[3], port);
-        let (ws_stream, _) = tokio_tungstenite::connect_async(ws_url)
+        let mut request = ws_url.into_client_request().unwrap();
+        if let Some(version) = requested_version {
+            request.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                version.subprotocol().parse().unwrap(),
+            );
+        }
+        let (ws_stream, response) = tokio_tungstenite::connect_async(request)
             .await
             .expect("Could not connect to websocket.");
+        let negotiated_version = response
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|protocol| protocol.to_str().ok())
+            .and_then(ProtocolVersion::from_subprotocol);
+        assert_eq!(requested_version, negotiated_version);
+        let version = negotiated_version.unwrap_or_default();
         let (write, read) = ws_stream.split();
 
         let client_ping_message = ping_message.clone();
@@ -400,6 +493,8 @@ mod tests {
                 let ping_message = client_ping_message.clone();
                 if let Ok(item) = item {
                     if item.is_text() {
+                        let is_envelope = item.to_text().unwrap().starts_with("{\"version\":2,");
+                        assert_eq!(version == ProtocolVersion::V2, is_envelope);
                         if let Ok(message) = parse_message_from_string(item.to_text().unwrap()) {
                             if message.eq(&ping_message) {
                                 return client_pong_message.clone();
@@ -409,7 +504,7 @@ mod tests {
                 }
                 panic!("Invalid message received.");
             })
-            .map(|message| convert_message_to_tungstenite_message(message))
+            .map(move |message| convert_message_to_tungstenite_message(message, version))
             .filter_map(|parsing_result| {
                 let result = match parsing_result {
                     Ok(message) => Some(Ok(message)),
@@ -440,6 +535,51 @@ mod tests {
             .expect("Could not join event handler.");
     }
 
+    #[tokio::test]
+    async fn reply_to_unparsable_request() {
+        let (websocket_event_tx, _websocket_event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let websocket_server = Arc::new(tokio::sync::Mutex::new(WebsocketServer::new(
+            websocket_event_tx,
+        )));
+        let router = Router::new()
+            .route("/", axum::routing::get(ws_handler))
+            .layer(axum::extract::Extension(websocket_server));
+
+        let handle = axum_server::Handle::new();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server_join_handle = tokio::spawn(
+            axum_server::bind(addr)
+                .handle(handle.clone())
+                .serve(router.into_make_service()),
+        );
+        let port = handle.listening().await.unwrap().port();
+
+        let mut request = format!("ws://127.0.0.1:{}", port)
+            .into_client_request()
+            .unwrap();
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            ProtocolVersion::V2.subprotocol().parse().unwrap(),
+        );
+        let (mut ws_stream, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .expect("Could not connect to websocket.");
+        ws_stream
+            .send(tokio_tungstenite::tungstenite::Message::Text(
+                r#"{"version":2,"type":"Unknown","id":3}"#.to_owned(),
+            ))
+            .await
+            .unwrap();
+
+        let reply = ws_stream.next().await.unwrap().unwrap();
+        let reply = parse_identified_message_from_string(reply.to_text().unwrap()).unwrap();
+        assert_eq!(Some(3), reply.id);
+        assert!(matches!(reply.message, AutomationMessage::Error { .. }));
+
+        handle.shutdown();
+        server_join_handle.await.unwrap().unwrap();
+    }
+
     async fn shutdown_server(
         server_shutdown_rx: tokio::sync::oneshot::Receiver<()>,
         server_handle: Handle,
@@ -452,8 +592,13 @@ mod tests {
         ws: WebSocketUpgrade,
         Extension(websocket_server): Extension<Arc<tokio::sync::Mutex<WebsocketServer>>>,
     ) -> impl IntoResponse {
-        ws.on_upgrade(|websocket| async {
-            server::add_websocket_to_server(websocket, websocket_server).await;
+        ws.protocols(
+            ProtocolVersion::SUPPORTED
+                .into_iter()
+                .map(|version| version.subprotocol()),
+        )
+        .on_upgrade(|websocket| async {
+            server::add_websocket_to_server(websocket, "test".to_owned(), websocket_server).await;
         })
     }
 }
@@ -1,6 +1,38 @@
+const TOKEN_STORAGE_KEY = "homeAutomationToken";
+
+function getToken(): string | null {
+  return localStorage.getItem(TOKEN_STORAGE_KEY);
+}
+
+// the API rejects requests without the token of a client, which is asked for once and kept in the browser
+async function authorizedFetch(
+  url: string,
+  init: RequestInit = {}
+): Promise<Response> {
+  for (;;) {
+    const token = getToken();
+    const headers = new Headers(init.headers);
+    if (token) {
+      headers.set("Authorization", `Bearer ${token}`);
+    }
+    const response = await fetch(url, { ...init, headers });
+    if (response.status !== 401) {
+      return response;
+    }
+    // another request may have asked for a token in the meantime
+    if (getToken() === token) {
+      const newToken = window.prompt("Token of this client:")?.trim();
+      if (!newToken) {
+        return response;
+      }
+      localStorage.setItem(TOKEN_STORAGE_KEY, newToken);
+    }
+  }
+}
+
 export async function get<T>(subUrl: string): Promise<T> {
   const url = `/api/${subUrl}`;
-  const response = await fetch(url);
+  const response = await authorizedFetch(url);
 
   if (!response.ok) {
     throw new Error("Could not get data from API: " + response.statusText);
@@ -11,7 +43,7 @@ export async function get<T>(subUrl: string): Promise<T> {
 
 export async function put<T>(subUrl: string, payload: T): Promise<void> {
   const url = `/api/${subUrl}`;
-  const response = await fetch(url, {
+  const response = await authorizedFetch(url, {
     method: "PUT",
     headers: {
       "Content-Type": "application/json",